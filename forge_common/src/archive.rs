@@ -0,0 +1,283 @@
+// Site archives, created and extracted in-process
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use filetime::FileTime;
+use tar::{Archive, Builder, EntryType};
+
+use crate::{
+    compression::{self, Compression, Encoder},
+    error::{AppError, AppResult},
+    exclude::{self, Excludes},
+};
+
+/// Running totals while an archive is written or unpacked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveProgress {
+    pub files: u64,
+    pub bytes: u64,
+    /// Left out by the exclude rules, folders count along with their contents.
+    pub excluded_files: u64,
+    pub excluded_bytes: u64,
+}
+
+/// Packs the contents of `source` into a compressed tarball at `output`,
+/// keeping permissions, ownership, symlinks and modification times.
+/// `progress` is called after each entry with its path relative to `source`.
+pub fn create_archive<F>(
+    source: &Path,
+    output: &Path,
+    compression: &Compression,
+    excludes: &Excludes,
+    progress: F,
+) -> AppResult<ArchiveProgress>
+where
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    if let Some(parent_dir) = output.parent() {
+        fs::create_dir_all(parent_dir).map_err(|e| AppError::FileError(parent_dir.into(), e))?;
+    }
+
+    let file = File::create(output).map_err(|e| AppError::FileError(output.into(), e))?;
+    let mut writer = BufWriter::new(file);
+
+    write_archive(source, &mut writer, compression, excludes, progress)
+        .and_then(|totals| {
+            writer
+                .flush()
+                .map_err(|e| AppError::FileError(output.into(), e))?;
+            Ok(totals)
+        })
+        .map_err(|e| match e {
+            // Failing to write the compressed stream means the output file.
+            AppError::CommandError(_, e) => AppError::FileError(output.into(), e),
+            e => e,
+        })
+}
+
+/// Like `create_archive`, but writes the compressed tarball to `writer`.
+pub fn write_archive<W, F>(
+    source: &Path,
+    writer: W,
+    compression: &Compression,
+    excludes: &Excludes,
+    mut progress: F,
+) -> AppResult<ArchiveProgress>
+where
+    W: Write,
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    let mut builder = new_builder(writer, compression)?;
+
+    let mut totals = ArchiveProgress::default();
+    append_folder(
+        &mut builder,
+        source,
+        Path::new(""),
+        excludes,
+        &mut totals,
+        &mut progress,
+    )?;
+
+    finish_builder(builder)?;
+    Ok(totals)
+}
+
+/// Packs only `paths`, relative to `source`, into a compressed tarball on
+/// `writer`. Folders are added without their contents.
+pub fn write_paths<W, F>(
+    source: &Path,
+    paths: &[String],
+    writer: W,
+    compression: &Compression,
+    mut progress: F,
+) -> AppResult<ArchiveProgress>
+where
+    W: Write,
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    let mut builder = new_builder(writer, compression)?;
+
+    let mut totals = ArchiveProgress::default();
+    for name in paths {
+        let path = source.join(name);
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| AppError::FileError(path.clone(), e))?;
+        append_entry(
+            &mut builder,
+            &path,
+            Path::new(name),
+            &metadata,
+            &mut totals,
+            &mut progress,
+        )?;
+    }
+
+    finish_builder(builder)?;
+    Ok(totals)
+}
+
+fn stream_error(e: io::Error) -> AppError {
+    AppError::CommandError("tar".into(), e)
+}
+
+fn new_builder<W: Write>(writer: W, compression: &Compression) -> AppResult<Builder<Encoder<W>>> {
+    let mut builder = Builder::new(compression.encoder(writer).map_err(stream_error)?);
+    builder.follow_symlinks(false);
+    Ok(builder)
+}
+
+fn finish_builder<W: Write>(builder: Builder<Encoder<W>>) -> AppResult<()> {
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .and_then(|mut writer| writer.flush())
+        .map_err(stream_error)
+}
+
+fn append_folder<W, F>(
+    builder: &mut Builder<Encoder<W>>,
+    folder: &Path,
+    relative: &Path,
+    excludes: &Excludes,
+    totals: &mut ArchiveProgress,
+    progress: &mut F,
+) -> AppResult<()>
+where
+    W: Write,
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    let mut entries = fs::read_dir(folder)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|e| AppError::FileError(folder.into(), e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = relative.join(entry.file_name());
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| AppError::FileError(path.clone(), e))?;
+
+        // Sockets only mean something to a running process, tar skips them as well.
+        if metadata.file_type().is_socket() {
+            continue;
+        }
+
+        if excludes.is_excluded(&name, metadata.is_dir()) {
+            let (files, bytes) = match metadata.is_dir() {
+                true => exclude::folder_size(&path),
+                false if metadata.is_file() => (0, metadata.len()),
+                false => (0, 0),
+            };
+            totals.excluded_files += files + 1;
+            totals.excluded_bytes += bytes;
+            continue;
+        }
+
+        append_entry(builder, &path, &name, &metadata, totals, progress)?;
+
+        if metadata.is_dir() {
+            append_folder(builder, &path, &name, excludes, totals, progress)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn append_entry<W, F>(
+    builder: &mut Builder<Encoder<W>>,
+    path: &Path,
+    name: &Path,
+    metadata: &fs::Metadata,
+    totals: &mut ArchiveProgress,
+    progress: &mut F,
+) -> AppResult<()>
+where
+    W: Write,
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    builder
+        .append_path_with_name(path, name)
+        .map_err(|e| AppError::FileError(path.into(), e))?;
+
+    totals.files += 1;
+    if metadata.is_file() {
+        totals.bytes += metadata.len();
+    }
+    progress(name, totals);
+
+    Ok(())
+}
+
+/// Unpacks a tarball, compressed with any of the codecs, into `destination`,
+/// restoring permissions and modification times, and ownership when running
+/// as root. `progress` is called after each entry with its path inside the
+/// archive.
+pub fn extract_archive<F>(
+    archive: &Path,
+    destination: &Path,
+    mut progress: F,
+) -> AppResult<ArchiveProgress>
+where
+    F: FnMut(&Path, &ArchiveProgress),
+{
+    let file = File::open(archive).map_err(|e| AppError::FileError(archive.into(), e))?;
+    let decoder = compression::decoder(BufReader::new(file))
+        .map_err(|e| AppError::FileError(archive.into(), e))?;
+    let mut tarball = Archive::new(decoder);
+    tarball.set_preserve_permissions(true);
+    tarball.set_preserve_mtime(true);
+    tarball.set_preserve_ownerships(running_as_root());
+    tarball.set_overwrite(true);
+
+    fs::create_dir_all(destination).map_err(|e| AppError::FileError(destination.into(), e))?;
+
+    let mut totals = ArchiveProgress::default();
+    let mut folders: Vec<(PathBuf, FileTime)> = vec![];
+
+    let entries = tarball
+        .entries()
+        .map_err(|e| AppError::FileError(archive.into(), e))?;
+
+    for entry in entries {
+        let mut entry = entry.map_err(|e| AppError::FileError(archive.into(), e))?;
+        let name = entry
+            .path()
+            .map_err(|e| AppError::FileError(archive.into(), e))?
+            .into_owned();
+        let target = destination.join(&name);
+
+        entry
+            .unpack_in(destination)
+            .map_err(|e| AppError::FileError(target.clone(), e))?;
+
+        let header = entry.header();
+        if header.entry_type() == EntryType::Directory {
+            if let Ok(mtime) = header.mtime() {
+                folders.push((target, FileTime::from_unix_time(mtime as i64, 0)));
+            }
+        } else if header.entry_type() == EntryType::Regular {
+            totals.bytes += entry.size();
+        }
+
+        totals.files += 1;
+        progress(&name, &totals);
+    }
+
+    // Unpacking files touches their folder, so folder times are put back last.
+    for (folder, mtime) in folders.into_iter().rev() {
+        filetime::set_file_mtime(&folder, mtime).map_err(|e| AppError::FileError(folder, e))?;
+    }
+
+    Ok(totals)
+}
+
+fn running_as_root() -> bool {
+    // SAFETY: geteuid has no preconditions and can't fail.
+    unsafe { libc::geteuid() == 0 }
+}
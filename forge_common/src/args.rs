@@ -1,5 +1,11 @@
 use clap::Parser;
 
+use crate::{
+    compression::Codec,
+    ssh::{HostKeyChecking, SshTransport},
+    sync::{CompareBy, FileTransfer},
+};
+
 #[derive(Parser, Debug)]
 #[command(
     name = "Forge move",
@@ -17,6 +23,14 @@ pub struct Args {
     #[arg(long, value_name = "DEST_SERVER")]
     pub dest_server_id: Option<String>,
 
+    /// Destination server name, used to look up the server ID and hostname
+    #[arg(
+        long,
+        value_name = "DEST_SERVER_NAME",
+        conflicts_with = "dest_server_id"
+    )]
+    pub dest_server_name: Option<String>,
+
     /// Destination hostname
     #[arg(long, value_name = "DEST_HOST")]
     pub dest_host: Option<String>,
@@ -33,6 +47,14 @@ pub struct Args {
     #[arg(long, value_name = "SOURCE_FOLDER")]
     pub source_folder: Option<String>,
 
+    /// Source server ID, to migrate a site straight off another forge server
+    #[arg(long, value_name = "SOURCE_SERVER")]
+    pub source_server_id: Option<String>,
+
+    /// Source site ID on the source server
+    #[arg(long, value_name = "SOURCE_SITE")]
+    pub source_site_id: Option<String>,
+
     /// Temp folder
     #[arg(long, value_name = "TEMP_FOLDER")]
     pub temp_folder: Option<String>,
@@ -44,4 +66,133 @@ pub struct Args {
     // Username to use on destination server
     #[arg(long, value_name = "USER_NAME")]
     pub user_name: Option<String>,
+
+    /// Resume a previously failed migration run
+    #[arg(long, value_name = "RUN_ID")]
+    pub resume: Option<String>,
+
+    /// Sync the files and database of a finished run into its site again, e.g. at cutover
+    #[arg(long, value_name = "RUN_ID", conflicts_with_all = ["resume", "dry_run"])]
+    pub resync: Option<String>,
+
+    /// Roll back created forge resources automatically when a migration fails
+    #[arg(long)]
+    pub auto_rollback: Option<bool>,
+
+    /// Print the migration plan without making any changes
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Let a dry run read a source site on another server over SSH, to detect its type,
+    /// database settings and .forgeignore
+    #[arg(long, requires = "dry_run")]
+    pub read_source: bool,
+
+    /// Write the migration plan as JSON to this file
+    #[arg(long, value_name = "PLAN_FILE", requires = "dry_run")]
+    pub plan_file: Option<String>,
+
+    /// Execute a plan file written by --dry-run
+    #[arg(long, value_name = "PLAN_FILE", conflicts_with_all = ["dry_run", "resume", "resync"])]
+    pub apply: Option<String>,
+
+    /// Forge API base URL, e.g. a caching proxy or a local mock
+    #[arg(long, value_name = "URL")]
+    pub forge_base_url: Option<String>,
+
+    /// Forge API version
+    #[arg(long, value_name = "VERSION")]
+    pub forge_api_version: Option<String>,
+
+    /// Forge API request timeout in seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub forge_timeout: Option<u64>,
+
+    /// HTTP(S) proxy for forge API requests
+    #[arg(long, value_name = "PROXY_URL")]
+    pub forge_proxy: Option<String>,
+
+    /// PEM bundle of extra CA certificates to trust for the forge API
+    #[arg(long, value_name = "CA_BUNDLE")]
+    pub forge_ca_bundle: Option<String>,
+
+    /// Retries for transient forge API failures
+    #[arg(long, value_name = "RETRIES")]
+    pub forge_max_retries: Option<u32>,
+
+    /// Forge API requests per minute, 0 to disable the throttle
+    #[arg(long, value_name = "REQUESTS")]
+    pub forge_requests_per_minute: Option<u32>,
+
+    /// Compression for the archives: gzip, zstd or none
+    #[arg(long, value_name = "CODEC")]
+    pub compression: Option<Codec>,
+
+    /// Compression level, 1-9 for gzip and 1-19 for zstd
+    #[arg(long, value_name = "LEVEL")]
+    pub compression_level: Option<i32>,
+
+    /// Threads used by zstd, 0 for one per CPU core
+    #[arg(long, value_name = "THREADS")]
+    pub compression_threads: Option<u32>,
+
+    /// Pipe the archives straight to the destination instead of writing them to the temp folder
+    #[arg(long)]
+    pub stream: Option<bool>,
+
+    /// Leave out paths matching this pattern (.gitignore syntax), can be repeated
+    #[arg(long = "exclude", value_name = "PATTERN")]
+    pub excludes: Vec<String>,
+
+    /// Leave out the caches and logs that are safe to skip for the site type
+    #[arg(long)]
+    pub default_excludes: Option<bool>,
+
+    /// How the files are copied: archive (everything at once) or sync (only changes)
+    #[arg(long, value_name = "STRATEGY")]
+    pub file_transfer: Option<FileTransfer>,
+
+    /// How sync spots changed files: mtime (size and time) or checksum
+    #[arg(long, value_name = "COMPARE")]
+    pub sync_compare: Option<CompareBy>,
+
+    /// Delete files from the destination that no longer exist on the source when syncing
+    #[arg(long)]
+    pub sync_delete: Option<bool>,
+
+    /// SSH user for the forge servers, forge by default
+    #[arg(long, value_name = "USER")]
+    pub ssh_user: Option<String>,
+
+    /// SSH port for the forge servers
+    #[arg(long, value_name = "PORT")]
+    pub ssh_port: Option<u16>,
+
+    /// Private key to authenticate to the forge servers with
+    #[arg(long, value_name = "FILE")]
+    pub ssh_identity_file: Option<String>,
+
+    /// Bastion host to connect through, [user@]host[:port] as for ssh -J
+    #[arg(long, value_name = "HOST")]
+    pub ssh_jump_host: Option<String>,
+
+    /// Host key policy: yes (known hosts only), accept-new or no
+    #[arg(long, value_name = "POLICY")]
+    pub ssh_host_key_checking: Option<HostKeyChecking>,
+
+    /// Extra ssh option as Key=Value, can be repeated
+    #[arg(long = "ssh-option", value_name = "OPTION")]
+    pub ssh_options: Vec<String>,
+
+    /// Reuse one SSH connection per server for all the calls of a run
+    #[arg(long)]
+    pub ssh_multiplex: Option<bool>,
+
+    /// How to connect: openssh (the ssh binary) or native (built in, needs the native-ssh feature)
+    #[arg(long, value_name = "TRANSPORT")]
+    pub ssh_transport: Option<SshTransport>,
+
+    /// Run commands meant for remote servers on this machine, from this folder
+    #[arg(long, value_name = "FOLDER", hide = true)]
+    pub local_remote_root: Option<String>,
 }
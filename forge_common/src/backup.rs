@@ -2,236 +2,523 @@
 
 use std::{
     fs::{self, File},
-    io::{self},
+    io::{self, BufWriter, Read, Write},
     path::{Path, PathBuf},
-    process::{Command, Stdio},
 };
 
 use chrono::Utc;
 
 use crate::{
-    config::FinalConfig,
-    database::DatabaseCredentials,
+    archive::{self, ArchiveProgress},
+    compression::Compression,
+    database::{DatabaseCredentials, OptionFile},
     error::{AppError, AppResult},
+    exclude::Excludes,
+    feedback::{Progress, ProgressReader, ProgressWriter},
+    remote::{self, RemoteFolder, RemoteOptionFile, RemoteUpload},
+    runner::{CommandRunner, LocalRunner, Stdin, Stdout},
+    shell::{self, RemoteCommand},
+    site_type::SiteType,
+    source::SourceFolder,
+    stream::{self, StreamSummary},
+    sync::{self, CompareBy, EntryKind, Manifest},
 };
 
-pub fn backup_database(creds: &DatabaseCredentials, output_path: &Path) -> AppResult<()> {
+/// Dumps a local database into a compressed archive, authenticating with the
+/// site's credentials through a temporary option file. The size of the tables
+/// stands in for the size of the dump.
+pub fn backup_database(
+    runner: &dyn CommandRunner,
+    creds: &DatabaseCredentials,
+    compression: &Compression,
+    output_path: &Path,
+    progress: &Progress,
+) -> AppResult<()> {
     // Prepare temp folder
     if let Some(parent_dir) = output_path.parent() {
         fs::create_dir_all(parent_dir)?;
     }
 
-    // Start the database backup
-    let mut mysqldump = Command::new("mariadb-dump")
-        .arg(&creds.database)
-        .arg("--no-tablespaces")
-        .stderr(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| AppError::CommandError("mariadb-dump".into(), e))?;
-
-    let mysqldump_stdout = mysqldump.stdout.take().ok_or_else(|| {
-        AppError::CommandError(
-            "mariadb-dump".into(),
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Failed to capture mariadb-dump output",
-            ),
-        )
-    })?;
+    let output_file =
+        File::create(output_path).map_err(|e| AppError::FileError(output_path.to_path_buf(), e))?;
+    let mut encoder = compression
+        .encoder(BufWriter::new(output_file))
+        .map_err(|e| AppError::FileError(output_path.to_path_buf(), e))?;
 
-    // Start the gzip command, piping mysqldumps output to gzip
-    let mut gzip = Command::new("gzip")
-        .arg("-c")
-        .stdin(Stdio::from(mysqldump_stdout))
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| AppError::CommandError("gzip".into(), e))?;
-
-    // Connect the `mariadb-dump` output to `gzip` input
-    if let Some(mut mysqldump_stdout) = mysqldump.stdout.take() {
-        if let Some(gzip_stdin) = gzip.stdin.as_mut() {
-            std::io::copy(&mut mysqldump_stdout, gzip_stdin)
-                .map_err(|e| AppError::CommandError("gzip".into(), e))?;
-        }
+    let option_file = OptionFile::create(creds)?;
+    if let Some(size) = database_size(runner, &option_file, creds) {
+        progress.set_total(size);
     }
+    runner.run_checked(
+        &local_dump_command(&option_file, creds),
+        Stdin::Null,
+        Stdout::Writer(&mut ProgressWriter::new(&mut encoder, progress)),
+    )?;
+
+    encoder
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .map_err(|e| AppError::FileError(output_path.to_path_buf(), e))
+}
 
-    // Create the output file and write the gzip output to it
-    let mut output_file =
-        File::create(output_path).map_err(|e| AppError::FileError(output_path.to_path_buf(), e))?;
+/// Dumps a database on a remote forge server into a local compressed archive.
+/// Only the compressed bytes are seen here, so there is no total to report.
+pub fn backup_remote_database(
+    runner: &dyn CommandRunner,
+    creds: &DatabaseCredentials,
+    compression: &Compression,
+    output_path: &Path,
+    progress: &Progress,
+) -> AppResult<()> {
+    let option_file = RemoteOptionFile::create(runner, creds)?;
+
+    run_to_file(
+        runner,
+        &compressed(
+            remote_dump_command(option_file.path(), &creds.database),
+            compression,
+        ),
+        Stdin::Null,
+        output_path,
+        progress,
+    )
+}
+
+/// Size of a local database's tables from `information_schema`, `None` when
+/// it can't be worked out. A dump comes out at roughly this size.
+fn database_size(
+    runner: &dyn CommandRunner,
+    option_file: &OptionFile,
+    creds: &DatabaseCredentials,
+) -> Option<u64> {
+    let query = format!(
+        "SELECT COALESCE(SUM(data_length + index_length), 0) FROM information_schema.tables WHERE table_schema = '{}'",
+        creds.database.replace('\\', "\\\\").replace('\'', "\\'")
+    );
+    let command = RemoteCommand::new("mariadb")
+        .arg(&defaults_extra_file(
+            &option_file.path().display().to_string(),
+        ))
+        .args(["-N", "-B", "-e", &query]);
+    let output = runner
+        .run_checked(&command.to_string(), Stdin::Null, Stdout::Capture)
+        .ok()?;
+
+    output.stdout_string().trim().parse().ok()
+}
+
+/// The option file has to come first, `mariadb-dump` ignores it anywhere else.
+fn local_dump_command(option_file: &OptionFile, creds: &DatabaseCredentials) -> String {
+    RemoteCommand::new("mariadb-dump")
+        .arg(&defaults_extra_file(
+            &option_file.path().display().to_string(),
+        ))
+        .args([creds.database.as_str(), "--no-tablespaces"])
+        .to_string()
+}
+
+/// Dump on a server, the credentials are in the `RemoteOptionFile` at `option_file`.
+fn remote_dump_command(option_file: &str, database: &str) -> String {
+    RemoteCommand::new("mysqldump")
+        .arg(&defaults_extra_file(option_file))
+        .args([database, "--no-tablespaces"])
+        .to_string()
+}
+
+fn defaults_extra_file(option_file: &str) -> String {
+    format!("--defaults-extra-file={}", option_file)
+}
+
+/// Archives a local site folder into a tarball. The folder is listed first so
+/// the progress has a total.
+pub fn backup_files(
+    source_folder: &str,
+    compression: &Compression,
+    excludes: &Excludes,
+    output_path: &Path,
+    progress: &Progress,
+) -> AppResult<ArchiveProgress> {
+    let (manifest, _) = sync::local_manifest(Path::new(source_folder), CompareBy::Mtime, excludes)?;
+    progress.set_total(file_bytes(&manifest));
+
+    archive::create_archive(
+        Path::new(source_folder),
+        output_path,
+        compression,
+        excludes,
+        |_, totals| report_archive(progress, totals),
+    )
+}
 
-    if let Some(mut gzip_stdout) = gzip.stdout.take() {
-        std::io::copy(&mut gzip_stdout, &mut output_file)
-            .map_err(|e| AppError::FileError(output_path.to_path_buf(), e))?;
+/// Passes an archive's running totals on to the progress.
+pub fn report_archive(progress: &Progress, totals: &ArchiveProgress) {
+    progress.set_bytes(totals.bytes);
+    progress.set_detail(format!("{} files", totals.files));
+}
+
+/// Bytes held by the files in a listing.
+pub fn file_bytes(manifest: &Manifest) -> u64 {
+    manifest
+        .values()
+        .filter(|entry| entry.kind == EntryKind::File)
+        .map(|entry| entry.size)
+        .sum()
+}
+
+/// Archives a site folder on a remote forge server into a local tarball. The
+/// totals are only known when there are excludes, the folder is listed first then.
+pub fn backup_remote_files(
+    source: RemoteFolder,
+    compression: &Compression,
+    excludes: &Excludes,
+    output_path: &Path,
+    progress: &Progress,
+) -> AppResult<ArchiveProgress> {
+    if excludes.is_empty() {
+        let remote_command = archive_files_command(source.user_name, source.path, compression);
+        run_to_file(
+            source.runner,
+            &remote_command,
+            Stdin::Null,
+            output_path,
+            progress,
+        )?;
+        return Ok(ArchiveProgress::default());
     }
 
-    // Wait for the commands to complete
-    gzip.wait()
-        .map_err(|e| AppError::CommandError("gzip".into(), e))?;
+    let (list, totals) = remote_file_list(source, excludes)?;
+    let remote_command = archive_paths_command(source.user_name, source.path, compression);
+    run_to_file(
+        source.runner,
+        &remote_command,
+        Stdin::Bytes(&list),
+        output_path,
+        progress,
+    )?;
+
+    Ok(totals)
+}
 
-    Ok(())
+/// Lists a remote folder without the excluded paths, NUL separated for
+/// `archive_paths_command`.
+fn remote_file_list(
+    folder: RemoteFolder,
+    excludes: &Excludes,
+) -> AppResult<(Vec<u8>, ArchiveProgress)> {
+    let mut manifest = sync::remote_manifest(folder, CompareBy::Mtime)?;
+    let mut totals = excludes.filter(&mut manifest);
+    totals.files = manifest.len() as u64;
+    totals.bytes = file_bytes(&manifest);
+
+    let list = manifest
+        .keys()
+        .flat_map(|path| format!("{}\0", path).into_bytes())
+        .collect();
+
+    Ok((list, totals))
+}
+
+fn archive_files_command(
+    user_name: Option<&str>,
+    source_folder: &str,
+    compression: &Compression,
+) -> String {
+    tar_command("-cpf", compression.tar_compress_option())
+        .args(["-", "-C", source_folder, "."])
+        .as_user(user_name)
+        .to_string()
+}
+
+/// Archives the paths listed, NUL separated, on stdin from a remote folder,
+/// without descending into folders.
+pub fn archive_paths_command(
+    user_name: Option<&str>,
+    source_folder: &str,
+    compression: &Compression,
+) -> String {
+    tar_command("-cpf", compression.tar_compress_option())
+        .args([
+            "-",
+            "-C",
+            source_folder,
+            "--no-recursion",
+            "--null",
+            "-T",
+            "-",
+        ])
+        .as_user(user_name)
+        .to_string()
+}
+
+/// `tar` with the codec's option, which is made up of fixed values only.
+fn tar_command(mode: &str, codec_option: Option<String>) -> RemoteCommand {
+    let tar = RemoteCommand::new("tar");
+    match codec_option {
+        Some(option) => tar.raw(&option),
+        None => tar,
+    }
+    .arg(mode)
 }
 
-pub fn backup_files(config: &FinalConfig, output_path: &Path) -> AppResult<()> {
+/// Pipes a command's output through the remote compressor, if there is one.
+fn compressed(command: String, compression: &Compression) -> String {
+    match compression.compress_command() {
+        Some(compress) => shell::pipe(&command, &compress),
+        None => command,
+    }
+}
+
+/// Runs `command`, writing its output to `output_path` and counting the bytes received.
+fn run_to_file(
+    runner: &dyn CommandRunner,
+    command: &str,
+    stdin: Stdin,
+    output_path: &Path,
+    progress: &Progress,
+) -> AppResult<()> {
     // Prepare temp folder
     if let Some(parent_dir) = output_path.parent() {
         fs::create_dir_all(parent_dir)?;
     }
 
-    let status = Command::new("tar")
-        .current_dir(&config.source_folder)
-        .arg("-zcpvf")
-        .arg(&output_path)
-        .arg(".")
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map_err(|e| AppError::CommandError("tar".into(), e))?;
-
-    if !status.success() {
-        return Err(AppError::CommandError(
-            "tar".into(),
-            io::Error::new(io::ErrorKind::Other, "tar command failed"),
-        ));
-    }
+    let file_error = |e| AppError::FileError(output_path.to_path_buf(), e);
+    let file = File::create(output_path).map_err(file_error)?;
+    let mut writer = ProgressWriter::new(BufWriter::new(file), progress);
 
-    Ok(())
+    runner.run_checked(command, stdin, Stdout::Writer(&mut writer))?;
+
+    writer.flush().map_err(file_error)
+}
+
+/// Opens an archive to feed to a remote command, the progress total is its size.
+fn open_archive(archive_file: &Path, progress: &Progress) -> AppResult<File> {
+    let file_error = |e| AppError::FileError(archive_file.to_path_buf(), e);
+    let file = File::open(archive_file).map_err(file_error)?;
+    progress.set_total(file.metadata().map_err(file_error)?.len());
+
+    Ok(file)
 }
 
 pub fn restore_files(
-    archive_file: &str,
-    dest_host: &str,
-    user_name: Option<String>,
+    runner: &dyn CommandRunner,
+    archive_file: &Path,
+    user_name: Option<&str>,
     remote_directory: &str,
+    compression: &Compression,
+    progress: &Progress,
 ) -> AppResult<()> {
-    if !Path::new(archive_file).exists() {
-        return Err(AppError::CommandError(
-            "cat".into(),
-            io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("Archive file not found: {}", archive_file),
-            ),
+    if !archive_file.exists() {
+        return Err(AppError::FileError(
+            archive_file.to_path_buf(),
+            io::Error::new(io::ErrorKind::NotFound, "Archive file not found"),
         ));
     }
 
-    let mut cat_process = Command::new("cat")
-        .arg(archive_file)
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| AppError::CommandError("cat".into(), e))?;
-
-    let cat_stdout = cat_process.stdout.take().ok_or_else(|| {
-        AppError::CommandError(
-            "cat".into(),
-            io::Error::new(io::ErrorKind::Other, "Failed to capture cat output"),
-        )
-    })?;
+    let remote_command = restore_files_command(user_name, remote_directory, compression);
+    let mut reader = ProgressReader::new(open_archive(archive_file, progress)?, progress);
+    send_archive(runner, &remote_command, &mut reader)
+}
 
-    let remote_command = match user_name {
-        Some(user_name) => format!("sudo -u {} tar -zxpvf - -C {}", user_name, remote_directory),
-        None => format!("tar -zxpvf - -C {}", remote_directory),
-    };
+/// Feeds a database archive to `mysql` on the server, `creds` are the new
+/// database's and reach the server through a `RemoteOptionFile`.
+pub fn restore_database(
+    runner: &dyn CommandRunner,
+    archive_file: &Path,
+    creds: &DatabaseCredentials,
+    compression: &Compression,
+    progress: &Progress,
+) -> AppResult<()> {
+    // cat /tmp/forge-move/2024-10-15/callcenter-db.sql.gz | ssh red-snowflake 'gunzip -c | mysql --defaults-extra-file=/tmp/tmp.X new_db'
+    let option_file = RemoteOptionFile::create(runner, creds)?;
+    let remote_command = restore_database_command(option_file.path(), &creds.database, compression);
+    let mut reader = ProgressReader::new(open_archive(archive_file, progress)?, progress);
+    send_archive(runner, &remote_command, &mut reader)
+}
 
-    let mut ssh_process = Command::new("ssh")
-        .arg(dest_host)
-        .arg(remote_command)
-        .stdin(Stdio::from(cat_stdout))
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .map_err(|e| AppError::CommandError("ssh".into(), e))?;
-
-    if !cat_process.wait()?.success() {
-        println!("`cat` command failed.");
-        return Err(AppError::CommandError(
-            "cat".into(),
-            io::Error::new(io::ErrorKind::Other, "cat command failed"),
-        ));
+/// Feeds an archive to `remote_command`, uploading it to the server first
+/// when the runner prefers that.
+fn send_archive(
+    runner: &dyn CommandRunner,
+    remote_command: &str,
+    archive: &mut (dyn Read + Send),
+) -> AppResult<()> {
+    if !runner.prefers_upload() {
+        runner.run_checked(remote_command, Stdin::Reader(archive), Stdout::Null)?;
+        return Ok(());
     }
 
-    if !ssh_process.wait()?.success() {
-        eprintln!("`ssh` command failed.");
-        return Err(AppError::CommandError(
-            "ssh".into(),
-            io::Error::new(io::ErrorKind::Other, "ssh command failed"),
-        ));
-    }
+    let upload = RemoteUpload::create(runner, Stdin::Reader(archive))?;
+    let cat = RemoteCommand::new("cat").arg(upload.path());
+    runner.run_checked(
+        &shell::pipe(&cat.to_string(), remote_command),
+        Stdin::Null,
+        Stdout::Null,
+    )?;
 
     Ok(())
 }
 
-pub fn restore_database(
-    archive_file: &str,
-    dest_host: &str,
-    user_name: Option<String>,
-    remote_db_name: &str,
-    password: &str,
-) -> AppResult<()> {
-    // cat /tmp/forge-move/2024-10-15/callcenter-db.sql.gz | ssh red-snowflake 'gunzip -c | mysql -u foo -ppassword'
-    let mut cat_process = Command::new("cat")
-        .arg(archive_file)
-        .stdout(Stdio::piped())
-        .spawn()
-        .map_err(|e| AppError::CommandError("cat".into(), e))?;
-
-    let cat_stdout = cat_process.stdout.take().ok_or_else(|| {
-        AppError::CommandError(
-            "cat".into(),
-            io::Error::new(io::ErrorKind::Other, "Failed to capture cat output"),
-        )
-    })?;
+/// Dumps the source database straight into the destination one, without an
+/// archive in between. `source` is the source server, or `None` for this machine.
+pub fn stream_database(
+    source: Option<&dyn CommandRunner>,
+    creds: &DatabaseCredentials,
+    compression: &Compression,
+    dest: &dyn CommandRunner,
+    dest_creds: &DatabaseCredentials,
+    progress: &Progress,
+) -> AppResult<StreamSummary> {
+    let dest_option_file = RemoteOptionFile::create(dest, dest_creds)?;
+    let remote_command =
+        restore_database_command(dest_option_file.path(), &dest_creds.database, compression);
+
+    stream::pipe(dest, &remote_command, |writer| match source {
+        Some(source) => {
+            let option_file = RemoteOptionFile::create(source, creds)?;
+            stream::from_remote(
+                source,
+                &compressed(
+                    remote_dump_command(option_file.path(), &creds.database),
+                    compression,
+                ),
+                Stdin::Null,
+                &mut ProgressWriter::new(writer, progress),
+            )
+        }
+        None => {
+            let stream_error = |e| AppError::CommandError("mariadb-dump".into(), e);
+            let mut encoder = compression.encoder(writer).map_err(stream_error)?;
+            let option_file = OptionFile::create(creds)?;
+            if let Some(size) = database_size(&LocalRunner, &option_file, creds) {
+                progress.set_total(size);
+            }
+            LocalRunner.run_checked(
+                &local_dump_command(&option_file, creds),
+                Stdin::Null,
+                Stdout::Writer(&mut ProgressWriter::new(&mut encoder, progress)),
+            )?;
+            encoder.finish().map_err(stream_error)?;
+            Ok(None)
+        }
+    })
+}
 
-    let remote_command = match user_name {
-        Some(user_name) => format!(
-            "gunzip -c | mysql -u {} -p{} {}",
-            user_name, password, remote_db_name
-        ),
-        None => format!(
-            "gunzip -c | mysql -u forge -p{} {}",
-            password, remote_db_name
+/// Archives the source site straight into the destination web directory,
+/// returning what was sent and the totals, see `backup_remote_files`. The
+/// progress has a total for a local folder, a remote one only counts bytes sent.
+pub fn stream_files(
+    source: SourceFolder,
+    dest: RemoteFolder,
+    compression: &Compression,
+    excludes: &Excludes,
+    progress: &Progress,
+) -> AppResult<(StreamSummary, ArchiveProgress)> {
+    let remote_command = restore_files_command(dest.user_name, dest.path, compression);
+    let mut totals = ArchiveProgress::default();
+
+    let summary = stream::pipe(dest.runner, &remote_command, |writer| match source {
+        SourceFolder::Local(folder) => {
+            let (manifest, _) =
+                sync::local_manifest(Path::new(folder), CompareBy::Mtime, excludes)?;
+            progress.set_total(file_bytes(&manifest));
+            totals = archive::write_archive(
+                Path::new(folder),
+                writer,
+                compression,
+                excludes,
+                |_, totals| report_archive(progress, totals),
+            )?;
+            Ok(None)
+        }
+        SourceFolder::Remote(folder) if excludes.is_empty() => stream::from_remote(
+            folder.runner,
+            &archive_files_command(folder.user_name, folder.path, compression),
+            Stdin::Null,
+            &mut ProgressWriter::new(writer, progress),
         ),
+        SourceFolder::Remote(folder) => {
+            let (list, listed) = remote_file_list(folder, excludes)?;
+            totals = listed;
+            stream::from_remote(
+                folder.runner,
+                &archive_paths_command(folder.user_name, folder.path, compression),
+                Stdin::Bytes(&list),
+                &mut ProgressWriter::new(writer, progress),
+            )
+        }
+    })?;
+
+    Ok((summary, totals))
+}
+
+/// Points the restored application's config file at the new database.
+pub fn rewrite_remote_config(
+    site_type: &SiteType,
+    runner: &dyn CommandRunner,
+    user_name: Option<&str>,
+    remote_directory: &str,
+    creds: &DatabaseCredentials,
+    app_host: Option<&str>,
+) -> AppResult<()> {
+    let Some(config_path) = site_type.config_path() else {
+        return Ok(());
     };
 
-    let mut ssh_process = Command::new("ssh")
-        .arg(dest_host)
-        .arg(remote_command)
-        .stdin(Stdio::from(cat_stdout))
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()
-        .map_err(|e| AppError::CommandError("ssh".into(), e))?;
-
-    if !cat_process.wait()?.success() {
-        println!("`cat` command failed.");
-        return Err(AppError::CommandError(
-            "cat".into(),
-            io::Error::new(io::ErrorKind::Other, "cat command failed"),
-        ));
-    }
+    let remote_path = format!("{}/{}", remote_directory.trim_end_matches('/'), config_path);
+    let content = remote::read_file(runner, user_name, &remote_path)?;
+    let updated = site_type.rewrite_config(&content, creds, app_host)?;
 
-    if !ssh_process.wait()?.success() {
-        eprintln!("`ssh` command failed.");
-        return Err(AppError::CommandError(
-            "ssh".into(),
-            io::Error::new(io::ErrorKind::Other, "ssh command failed"),
-        ));
+    remote::write_file(runner, user_name, &remote_path, &updated)
+}
+
+pub fn restore_files_command(
+    user_name: Option<&str>,
+    remote_directory: &str,
+    compression: &Compression,
+) -> String {
+    tar_command("-xpvf", compression.tar_decompress_option())
+        .args(["-", "-C", remote_directory])
+        .as_user(user_name)
+        .to_string()
+}
+
+/// Loads a dump, the credentials are in the `RemoteOptionFile` at `option_file`.
+pub fn restore_database_command(
+    option_file: &str,
+    remote_db_name: &str,
+    compression: &Compression,
+) -> String {
+    let mysql = RemoteCommand::new("mysql")
+        .arg(&defaults_extra_file(option_file))
+        .arg(remote_db_name);
+
+    decompressed(mysql.to_string(), compression)
+}
+
+/// Feeds the remote decompressor's output to a command, if there is one.
+fn decompressed(command: String, compression: &Compression) -> String {
+    match compression.decompress_command() {
+        Some(decompress) => shell::pipe(&decompress, &command),
+        None => command,
     }
-    Ok(())
 }
 
+/// Where an archive is written, e.g. `<temp>/2024-10-15/example.com-files.tar.zst`
+/// for a `-files.tar` postfix, the extension follows the codec.
 pub fn generate_output_path(
     source_folder: &str,
     temp_folder: &str,
     postfix: &str,
+    compression: &Compression,
 ) -> Option<PathBuf> {
     let folder_name = Path::new(source_folder).file_name()?.to_string_lossy();
 
     let date = Utc::now().format("%Y-%m-%d").to_string();
 
-    Some(
-        Path::new(temp_folder)
-            .join(date)
-            .join(format!("{}{}", folder_name, postfix)),
-    )
+    Some(Path::new(temp_folder).join(date).join(format!(
+        "{}{}{}",
+        folder_name,
+        postfix,
+        compression.codec.extension()
+    )))
 }
@@ -0,0 +1,233 @@
+// Compression codecs for archives and database dumps
+
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    str::FromStr,
+    thread,
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl Codec {
+    /// Suffix added to archive names, e.g. `.tar` becomes `.tar.zst`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Gzip => ".gz",
+            Codec::Zstd => ".zst",
+            Codec::None => "",
+        }
+    }
+
+    pub fn default_level(&self) -> i32 {
+        match self {
+            Codec::Gzip => 6,
+            Codec::Zstd => 3,
+            Codec::None => 0,
+        }
+    }
+
+    fn levels(&self) -> (i32, i32) {
+        match self {
+            Codec::Gzip => (1, 9),
+            Codec::Zstd => (1, 19),
+            Codec::None => (0, 0),
+        }
+    }
+
+    /// Tool the remote side needs to compress or decompress with this codec.
+    pub fn remote_tool(&self) -> Option<&'static str> {
+        match self {
+            Codec::Gzip => Some("gzip"),
+            Codec::Zstd => Some("zstd"),
+            Codec::None => None,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            "none" => Ok(Codec::None),
+            other => Err(format!(
+                "unknown compression `{}`, expected gzip, zstd or none",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How archives and database dumps are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: i32,
+    /// Worker threads, only zstd compresses on more than one.
+    pub threads: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            codec: Codec::Gzip,
+            level: Codec::Gzip.default_level(),
+            threads: 1,
+        }
+    }
+}
+
+impl Compression {
+    /// Checks the level suits the codec, threads default to one per CPU core.
+    pub fn new(codec: Codec, level: Option<i32>, threads: Option<u32>) -> AppResult<Self> {
+        let level = level.unwrap_or(codec.default_level());
+        let (min, max) = codec.levels();
+        if codec != Codec::None && !(min..=max).contains(&level) {
+            return Err(AppError::ConfigError(format!(
+                "{} compression level must be between {} and {}, got {}",
+                codec, min, max, level
+            )));
+        }
+
+        let threads = match threads {
+            Some(0) | None => thread::available_parallelism()
+                .map(|cores| cores.get() as u32)
+                .unwrap_or(1),
+            Some(threads) => threads,
+        };
+
+        Ok(Compression {
+            codec,
+            level,
+            threads,
+        })
+    }
+
+    /// Shell command that compresses stdin to stdout, `None` when nothing needs doing.
+    pub fn compress_command(&self) -> Option<String> {
+        match self.codec {
+            Codec::Gzip => Some(format!("gzip -c -{}", self.level)),
+            Codec::Zstd => Some(format!("zstd -c -q -{} -T{}", self.level, self.threads)),
+            Codec::None => None,
+        }
+    }
+
+    /// Shell command that decompresses stdin to stdout.
+    pub fn decompress_command(&self) -> Option<String> {
+        match self.codec {
+            Codec::Gzip => Some("gunzip -c".into()),
+            Codec::Zstd => Some("zstd -d -c -q".into()),
+            Codec::None => None,
+        }
+    }
+
+    /// `tar` option to compress the archive with, leaving tar's exit status intact.
+    pub fn tar_compress_option(&self) -> Option<String> {
+        match self.codec {
+            Codec::Gzip => Some(format!("-I 'gzip -{}'", self.level)),
+            Codec::Zstd => Some(format!("-I 'zstd -{} -T{}'", self.level, self.threads)),
+            Codec::None => None,
+        }
+    }
+
+    /// `tar` option to decompress the archive with, tar adds the `-d` itself.
+    pub fn tar_decompress_option(&self) -> Option<String> {
+        match self.codec {
+            Codec::Gzip => Some("-z".into()),
+            Codec::Zstd => Some("-I zstd".into()),
+            Codec::None => None,
+        }
+    }
+
+    pub fn encoder<W: Write>(&self, writer: W) -> io::Result<Encoder<W>> {
+        match self.codec {
+            Codec::Gzip => Ok(Encoder::Gzip(GzEncoder::new(
+                writer,
+                flate2::Compression::new(self.level as u32),
+            ))),
+            Codec::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, self.level)?;
+                if self.threads > 1 {
+                    encoder.multithread(self.threads)?;
+                }
+                Ok(Encoder::Zstd(encoder))
+            }
+            Codec::None => Ok(Encoder::None(writer)),
+        }
+    }
+}
+
+/// Compressing writer for one of the codecs.
+pub enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    None(W),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the stream, returning the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::None(writer) => Ok(writer),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::None(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::None(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Decompressing reader, the codec is recognised from the start of the stream.
+pub fn decoder<'a, R: BufRead + 'a>(mut reader: R) -> io::Result<Box<dyn Read + 'a>> {
+    let magic = reader.fill_buf()?;
+
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Ok(Box::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
@@ -9,25 +9,74 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     args::Args,
+    compression::{Codec, Compression},
     error::{AppError, AppResult},
+    forge::{server::FORGE_USER, ForgeClient, ForgeClientOptions},
+    picker,
+    plan::Plan,
+    runner::{CommandRunner, LocalAsRemoteRunner, RemoteRunner},
+    shell,
+    source::SourceSite,
+    ssh::{self, HostKeyChecking, SshOptions, SshTransport},
+    sync::{CompareBy, FileTransfer, SyncOptions},
 };
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     pub source_folder: Option<String>,
+    pub source_server_id: Option<String>,
+    pub source_site_id: Option<String>,
     pub forge_api_key: Option<String>,
     pub dest_server_id: Option<String>,
+    pub dest_server_name: Option<String>,
     pub dest_site_name: Option<String>,
     pub dest_host: Option<String>,
     pub dest_db: Option<String>,
     pub temp_folder: Option<String>,
     pub user_name: Option<String>,
     pub isolated: Option<bool>,
+    pub auto_rollback: Option<bool>,
+    pub forge_base_url: Option<String>,
+    pub forge_api_version: Option<String>,
+    pub forge_timeout: Option<u64>,
+    pub forge_proxy: Option<String>,
+    pub forge_ca_bundle: Option<String>,
+    pub forge_max_retries: Option<u32>,
+    pub forge_requests_per_minute: Option<u32>,
+    pub compression: Option<Codec>,
+    pub compression_level: Option<i32>,
+    pub compression_threads: Option<u32>,
+    pub stream: Option<bool>,
+    pub file_transfer: Option<FileTransfer>,
+    pub sync_compare: Option<CompareBy>,
+    pub sync_delete: Option<bool>,
+    pub excludes: Option<Vec<String>>,
+    pub default_excludes: Option<bool>,
+    pub ssh_user: Option<String>,
+    pub ssh_port: Option<u16>,
+    pub ssh_identity_file: Option<String>,
+    pub ssh_jump_host: Option<String>,
+    pub ssh_host_key_checking: Option<HostKeyChecking>,
+    pub ssh_options: Option<Vec<String>>,
+    pub ssh_multiplex: Option<bool>,
+    pub ssh_transport: Option<SshTransport>,
+    pub local_remote_root: Option<String>,
+    #[serde(skip)]
+    pub source_site: Option<SourceSite>,
+}
+
+/// Whether the destination site and database are about to be created, or
+/// already exist and are being picked for removal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    New,
+    Existing,
 }
 
 #[derive(Debug, Clone)]
 pub struct FinalConfig {
     pub source_folder: String,
+    pub source_site: Option<SourceSite>,
     pub forge_api_key: String,
     pub dest_server_id: String,
     pub dest_site_name: String,
@@ -36,6 +85,38 @@ pub struct FinalConfig {
     pub isolated: bool,
     pub user_name: Option<String>,
     pub temp_folder: String,
+    pub auto_rollback: Option<bool>,
+    pub forge_options: ForgeClientOptions,
+    pub compression: Compression,
+    /// Pipe the archives to the destination rather than keeping them in the temp folder.
+    pub stream: bool,
+    /// Set when only changed files are copied.
+    pub sync: Option<SyncOptions>,
+    /// Exclude patterns from the config file and the command line.
+    pub excludes: Vec<String>,
+    pub default_excludes: bool,
+    pub ssh_options: SshOptions,
+    pub local_remote_root: Option<String>,
+}
+
+impl FinalConfig {
+    pub fn forge_client(&self) -> AppResult<ForgeClient> {
+        ForgeClient::with_options(&self.forge_api_key, &self.forge_options)
+    }
+
+    /// Runs commands on `host`, or locally from `local_remote_root` when it is set.
+    pub fn runner(&self, host: &str) -> Box<dyn CommandRunner> {
+        if let Some(root) = &self.local_remote_root {
+            return Box::new(LocalAsRemoteRunner::new(host, Path::new(root)));
+        }
+
+        #[cfg(feature = "native-ssh")]
+        if self.ssh_options.transport == SshTransport::Native {
+            return Box::new(ssh::native::NativeRunner::new(host, &self.ssh_options));
+        }
+
+        Box::new(RemoteRunner::new(host, &self.ssh_options))
+    }
 }
 
 impl Config {
@@ -82,6 +163,11 @@ impl Config {
             self.dest_server_id = Some(destination);
         }
 
+        if let Some(dest_server_name) = args.dest_server_name {
+            self.dest_server_id = None;
+            self.dest_server_name = Some(dest_server_name);
+        }
+
         if let Some(dest_site_name) = args.dest_site_name {
             self.dest_site_name = Some(dest_site_name);
         }
@@ -98,6 +184,14 @@ impl Config {
             self.source_folder = Some(source_folder);
         }
 
+        if let Some(source_server_id) = args.source_server_id {
+            self.source_server_id = Some(source_server_id);
+        }
+
+        if let Some(source_site_id) = args.source_site_id {
+            self.source_site_id = Some(source_site_id);
+        }
+
         if let Some(api_key) = args.forge_api_key {
             self.forge_api_key = Some(api_key);
         }
@@ -114,10 +208,144 @@ impl Config {
             self.temp_folder = Some(temp_folder);
         }
 
+        if let Some(auto_rollback) = args.auto_rollback {
+            self.auto_rollback = Some(auto_rollback);
+        }
+
+        if let Some(forge_base_url) = args.forge_base_url {
+            self.forge_base_url = Some(forge_base_url);
+        }
+
+        if let Some(forge_api_version) = args.forge_api_version {
+            self.forge_api_version = Some(forge_api_version);
+        }
+
+        if let Some(forge_timeout) = args.forge_timeout {
+            self.forge_timeout = Some(forge_timeout);
+        }
+
+        if let Some(forge_proxy) = args.forge_proxy {
+            self.forge_proxy = Some(forge_proxy);
+        }
+
+        if let Some(forge_ca_bundle) = args.forge_ca_bundle {
+            self.forge_ca_bundle = Some(forge_ca_bundle);
+        }
+
+        if let Some(forge_max_retries) = args.forge_max_retries {
+            self.forge_max_retries = Some(forge_max_retries);
+        }
+
+        if let Some(forge_requests_per_minute) = args.forge_requests_per_minute {
+            self.forge_requests_per_minute = Some(forge_requests_per_minute);
+        }
+
+        if let Some(compression) = args.compression {
+            self.compression = Some(compression);
+        }
+
+        if let Some(compression_level) = args.compression_level {
+            self.compression_level = Some(compression_level);
+        }
+
+        if let Some(compression_threads) = args.compression_threads {
+            self.compression_threads = Some(compression_threads);
+        }
+
+        if let Some(stream) = args.stream {
+            self.stream = Some(stream);
+        }
+
+        if let Some(file_transfer) = args.file_transfer {
+            self.file_transfer = Some(file_transfer);
+        }
+
+        if let Some(sync_compare) = args.sync_compare {
+            self.sync_compare = Some(sync_compare);
+        }
+
+        if let Some(sync_delete) = args.sync_delete {
+            self.sync_delete = Some(sync_delete);
+        }
+
+        if !args.excludes.is_empty() {
+            self.excludes
+                .get_or_insert_with(Vec::new)
+                .extend(args.excludes);
+        }
+
+        if let Some(default_excludes) = args.default_excludes {
+            self.default_excludes = Some(default_excludes);
+        }
+
+        if let Some(ssh_user) = args.ssh_user {
+            self.ssh_user = Some(ssh_user);
+        }
+
+        if let Some(ssh_port) = args.ssh_port {
+            self.ssh_port = Some(ssh_port);
+        }
+
+        if let Some(ssh_identity_file) = args.ssh_identity_file {
+            self.ssh_identity_file = Some(ssh_identity_file);
+        }
+
+        if let Some(ssh_jump_host) = args.ssh_jump_host {
+            self.ssh_jump_host = Some(ssh_jump_host);
+        }
+
+        if let Some(ssh_host_key_checking) = args.ssh_host_key_checking {
+            self.ssh_host_key_checking = Some(ssh_host_key_checking);
+        }
+
+        if !args.ssh_options.is_empty() {
+            self.ssh_options
+                .get_or_insert_with(Vec::new)
+                .extend(args.ssh_options);
+        }
+
+        if let Some(ssh_multiplex) = args.ssh_multiplex {
+            self.ssh_multiplex = Some(ssh_multiplex);
+        }
+
+        if let Some(ssh_transport) = args.ssh_transport {
+            self.ssh_transport = Some(ssh_transport);
+        }
+
+        if let Some(local_remote_root) = args.local_remote_root {
+            self.local_remote_root = Some(local_remote_root);
+        }
+
         self
     }
 
-    pub fn finalize(mut self) -> AppResult<FinalConfig> {
+    /// Takes the migration settings from a plan, so it is executed unchanged.
+    pub fn from_plan(mut self, plan: &Plan) -> Self {
+        self.source_folder = Some(plan.source_folder.clone());
+        self.source_site = plan.source_site.clone();
+        self.dest_server_id = Some(plan.dest_server_id.clone());
+        self.dest_host = Some(plan.dest_host.clone());
+        self.dest_site_name = Some(plan.create_site.domain.clone());
+        self.dest_db = Some(plan.create_database.name.clone());
+        self.isolated = Some(plan.create_site.isolated);
+        self.user_name = plan.user_name.clone();
+        self.compression = Some(plan.compression.codec);
+        self.compression_level = Some(plan.compression.level);
+        self.compression_threads = Some(plan.compression.threads);
+        self.stream = Some(plan.stream);
+        self.file_transfer = Some(match plan.sync {
+            Some(_) => FileTransfer::Sync,
+            None => FileTransfer::Archive,
+        });
+        if let Some(sync) = plan.sync {
+            self.sync_compare = Some(sync.compare);
+            self.sync_delete = Some(sync.delete);
+        }
+
+        self
+    }
+
+    pub fn finalize(mut self, destination: Destination) -> AppResult<FinalConfig> {
         if self.forge_api_key.is_none() {
             self.forge_api_key = Some(
                 Input::new()
@@ -127,6 +355,17 @@ impl Config {
             );
         }
 
+        let client = ForgeClient::with_options(
+            self.forge_api_key.as_deref().unwrap_or_default(),
+            &self.forge_options(),
+        )?;
+
+        if self.source_site.is_none()
+            && (self.source_server_id.is_some() || self.source_site_id.is_some())
+        {
+            self.resolve_source_site(&client)?;
+        }
+
         if self.source_folder.is_none() {
             self.source_folder = Some(
                 Input::new()
@@ -136,40 +375,64 @@ impl Config {
             );
         }
 
-        if self.dest_site_name.is_none() {
-            self.dest_site_name = Some(
-                Input::new()
-                    .with_prompt("Please enter destination site name")
-                    .interact_text()
-                    .map_err(AppError::InputError)?,
-            );
+        if self.dest_server_id.is_none() {
+            let server = match &self.dest_server_name {
+                Some(dest_server_name) => client.find_server_by_name(dest_server_name)?,
+                None => picker::pick_server(&client, "Please choose the destination server")?,
+            };
+
+            self.dest_server_id = Some(server.id.to_string());
+            if self.dest_host.is_none() {
+                self.dest_host = Some(server.ssh_host(self.ssh_user()));
+            }
         }
 
-        if self.dest_db.is_none() {
-            self.dest_db = Some(
-                Input::new()
-                    .with_prompt("Please enter destination database name")
-                    .interact_text()
-                    .map_err(AppError::InputError)?,
-            );
-        }
+        let dest_server_id = self.dest_server_id.clone().unwrap_or_default();
 
         if self.dest_host.is_none() {
-            self.dest_host = Some(
-                Input::new()
-                    .with_prompt("Please enter destination hostname")
-                    .interact_text()
-                    .map_err(AppError::InputError)?,
-            );
+            let server = client.get_server(&dest_server_id)?.server;
+            self.dest_host = Some(server.ssh_host(self.ssh_user()));
         }
 
-        if self.dest_server_id.is_none() {
-            self.dest_server_id = Some(
-                Input::new()
-                    .with_prompt("Please specify destination server ID")
-                    .interact_text()
-                    .map_err(AppError::InputError)?,
-            );
+        if self.dest_site_name.is_none() && destination == Destination::Existing {
+            let site = picker::pick_site(&client, &dest_server_id, "Please choose the site")?;
+            self.dest_site_name = Some(site.name);
+        }
+
+        if self.dest_site_name.is_none() {
+            let taken = client
+                .list_sites(&dest_server_id)?
+                .sites
+                .into_iter()
+                .map(|site| site.name)
+                .collect();
+
+            self.dest_site_name = Some(picker::input_unused_name(
+                "Please enter destination site name",
+                taken,
+                "site",
+            )?);
+        }
+
+        if self.dest_db.is_none() && destination == Destination::Existing {
+            let database =
+                picker::pick_database(&client, &dest_server_id, "Please choose the database")?;
+            self.dest_db = Some(database.name);
+        }
+
+        if self.dest_db.is_none() {
+            let taken = client
+                .list_databases(&dest_server_id)?
+                .databases
+                .into_iter()
+                .map(|database| database.name)
+                .collect();
+
+            self.dest_db = Some(picker::input_unused_name(
+                "Please enter destination database name",
+                taken,
+                "database",
+            )?);
         }
 
         if self.temp_folder.is_none() {
@@ -199,10 +462,39 @@ impl Config {
             }
         }
 
+        // Both end up in commands on the destination and in Forge requests.
+        if let Some(user_name) = &self.user_name {
+            shell::validate_user_name(user_name)?;
+        }
+        if let Some(dest_db) = &self.dest_db {
+            shell::validate_database_name(dest_db)?;
+        }
+
+        for option in self.ssh_options.iter().flatten() {
+            ssh::validate_option(option)?;
+        }
+
+        let forge_options = self.forge_options();
+        let ssh_options = self.ssh_options();
+        ssh_options.validate_transport()?;
+        let sync = match self.file_transfer.unwrap_or_default() {
+            FileTransfer::Sync => Some(SyncOptions {
+                compare: self.sync_compare.unwrap_or_default(),
+                delete: self.sync_delete.unwrap_or(false),
+            }),
+            FileTransfer::Archive => None,
+        };
+        let compression = Compression::new(
+            self.compression.unwrap_or_default(),
+            self.compression_level,
+            self.compression_threads,
+        )?;
+
         Ok(FinalConfig {
             source_folder: self
                 .source_folder
                 .expect("source folder should be specified"),
+            source_site: self.source_site,
             forge_api_key: self
                 .forge_api_key
                 .expect("forge api key should be provided"),
@@ -221,13 +513,55 @@ impl Config {
             temp_folder: self.temp_folder.expect("temp folder should be provided"),
             isolated: self.isolated.expect("isolated status should be provided"),
             user_name: self.user_name,
+            auto_rollback: self.auto_rollback,
+            forge_options,
+            compression,
+            stream: self.stream.unwrap_or(false),
+            sync,
+            excludes: self.excludes.unwrap_or_default(),
+            default_excludes: self.default_excludes.unwrap_or(true),
+            ssh_options,
+            local_remote_root: self.local_remote_root,
         })
     }
 
+    fn forge_options(&self) -> ForgeClientOptions {
+        ForgeClientOptions {
+            base_url: self.forge_base_url.clone(),
+            api_version: self.forge_api_version.clone(),
+            timeout: self.forge_timeout,
+            proxy: self.forge_proxy.clone(),
+            ca_bundle: self.forge_ca_bundle.clone(),
+            max_retries: self.forge_max_retries,
+            requests_per_minute: self.forge_requests_per_minute,
+        }
+    }
+
+    fn ssh_options(&self) -> SshOptions {
+        SshOptions {
+            user: self.ssh_user.clone(),
+            port: self.ssh_port,
+            identity_file: self.ssh_identity_file.clone(),
+            jump_host: self.ssh_jump_host.clone(),
+            host_key_checking: self.ssh_host_key_checking,
+            options: self.ssh_options.clone().unwrap_or_default(),
+            multiplex: self.ssh_multiplex.unwrap_or(true),
+            transport: self.ssh_transport.unwrap_or_default(),
+        }
+    }
+
+    /// User the forge servers are reached as over SSH.
+    fn ssh_user(&self) -> &str {
+        self.ssh_user.as_deref().unwrap_or(FORGE_USER)
+    }
+
     fn default() -> Self {
         Config {
             source_folder: None,
+            source_server_id: None,
+            source_site_id: None,
             dest_server_id: None,
+            dest_server_name: None,
             dest_site_name: None,
             dest_db: None,
             dest_host: None,
@@ -235,7 +569,75 @@ impl Config {
             temp_folder: None,
             isolated: None,
             user_name: None,
+            auto_rollback: None,
+            forge_base_url: None,
+            forge_api_version: None,
+            forge_timeout: None,
+            forge_proxy: None,
+            forge_ca_bundle: None,
+            forge_max_retries: None,
+            forge_requests_per_minute: None,
+            compression: None,
+            compression_level: None,
+            compression_threads: None,
+            stream: None,
+            file_transfer: None,
+            sync_compare: None,
+            sync_delete: None,
+            excludes: None,
+            default_excludes: None,
+            ssh_user: None,
+            ssh_port: None,
+            ssh_identity_file: None,
+            ssh_jump_host: None,
+            ssh_host_key_checking: None,
+            ssh_options: None,
+            ssh_multiplex: None,
+            ssh_transport: None,
+            local_remote_root: None,
+            source_site: None,
+        }
+    }
+
+    /// Looks the source site up on forge and takes the source folder, and any
+    /// destination settings that are still missing, from it.
+    fn resolve_source_site(&mut self, client: &ForgeClient) -> AppResult<()> {
+        if self.source_server_id.is_none() {
+            let server = picker::pick_server(client, "Please choose the source server")?;
+            self.source_server_id = Some(server.id.to_string());
         }
+
+        if self.source_site_id.is_none() {
+            let site = picker::pick_site(
+                client,
+                self.source_server_id.as_deref().unwrap_or_default(),
+                "Please choose the site to migrate",
+            )?;
+            self.source_site_id = Some(site.id.to_string());
+        }
+
+        let source_site = SourceSite::resolve(
+            client,
+            self.source_server_id.as_deref().unwrap_or_default(),
+            self.source_site_id.as_deref().unwrap_or_default(),
+            self.ssh_user(),
+        )?;
+
+        self.source_folder = Some(source_site.root_directory.clone());
+
+        if self.dest_site_name.is_none() {
+            self.dest_site_name = Some(source_site.name.clone());
+        }
+
+        if self.isolated.is_none() {
+            self.isolated = Some(source_site.isolated);
+            if source_site.isolated && self.user_name.is_none() {
+                self.user_name = Some(source_site.username.clone());
+            }
+        }
+
+        self.source_site = Some(source_site);
+        Ok(())
     }
 
     fn save_to_file(&self, path: &PathBuf) -> AppResult<()> {
@@ -1,14 +1,134 @@
-use std::path::Path;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
 
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DatabaseCredentials {
     pub username: String,
     pub password: String,
     pub database: String,
+    /// Server to connect to, the client's default when `None`.
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Unix socket, used instead of the host for local connections.
+    pub socket: Option<String>,
+}
+
+impl DatabaseCredentials {
+    /// Where the database server is, for messages, e.g. `db.internal:3307`.
+    pub fn server(&self) -> Option<String> {
+        match (&self.socket, &self.host, self.port) {
+            (Some(socket), _, _) => Some(socket.clone()),
+            (None, Some(host), Some(port)) => Some(format!("{}:{}", host, port)),
+            (None, Some(host), None) => Some(host.clone()),
+            (None, None, Some(port)) => Some(format!("localhost:{}", port)),
+            (None, None, None) => None,
+        }
+    }
+
+    /// `[client]` section of a MySQL option file holding these credentials,
+    /// so the password never has to be given on a command line.
+    pub fn client_options(&self) -> String {
+        let mut options = format!(
+            "[client]\nuser={}\npassword={}\n",
+            option_value(&self.username),
+            option_value(&self.password)
+        );
+        if let Some(host) = &self.host {
+            options.push_str(&format!("host={}\n", option_value(host)));
+        }
+        if let Some(port) = self.port {
+            options.push_str(&format!("port={}\n", port));
+        }
+        if let Some(socket) = &self.socket {
+            options.push_str(&format!("socket={}\n", option_value(socket)));
+        }
+
+        options
+    }
+}
+
+/// Double quoted option file value, so `#` and spaces are kept as they are.
+fn option_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+
+    format!("\"{}\"", escaped)
+}
+
+/// Temporary MySQL option file only the current user can read, for
+/// `--defaults-extra-file`. It is removed again when dropped.
+pub struct OptionFile {
+    path: PathBuf,
+}
+
+impl OptionFile {
+    pub fn create(creds: &DatabaseCredentials) -> AppResult<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "forge-migrate-{}-{:08x}.cnf",
+            std::process::id(),
+            rand::random::<u32>()
+        ));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|e| AppError::FileError(path.clone(), e))?;
+        // From here on the file is cleaned up, even if writing it fails.
+        let option_file = OptionFile { path };
+        file.write_all(creds.client_options().as_bytes())
+            .map_err(|e| AppError::FileError(option_file.path.clone(), e))?;
+
+        Ok(option_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for OptionFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
 pub trait DatabaseConfigProvider {
-    fn get_database_credentials(&self, root_path: &Path) -> AppResult<Option<DatabaseCredentials>>;
+    fn get_database_credentials(&self, root_path: &Path) -> AppResult<Option<DatabaseCredentials>> {
+        let Some(config_path) = self.config_path() else {
+            return Ok(None);
+        };
+
+        let config_path = root_path.join(config_path);
+        let config_content = fs::read_to_string(&config_path)
+            .map_err(|e| AppError::FileError(config_path.clone(), e))?;
+
+        self.parse_database_credentials(&config_content)
+    }
+
+    /// File holding the database settings, relative to the site root.
+    fn config_path(&self) -> Option<&'static str>;
+
+    /// Reads the database settings from the contents of the config file.
+    fn parse_database_credentials(&self, content: &str) -> AppResult<Option<DatabaseCredentials>>;
+
+    /// Replaces the database settings in `content`, and the application URL's
+    /// host when `app_host` is given, leaving everything else untouched.
+    fn rewrite_config(
+        &self,
+        content: &str,
+        creds: &DatabaseCredentials,
+        app_host: Option<&str>,
+    ) -> AppResult<String>;
 }
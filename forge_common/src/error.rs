@@ -1,6 +1,8 @@
 use core::fmt;
 use std::{io, path::PathBuf};
 
+use crate::forge::error::ForgeError;
+
 pub type AppResult<T> = Result<T, AppError>;
 
 #[derive(Debug)]
@@ -15,8 +17,15 @@ pub enum AppError {
     UnknownSiteType(PathBuf),
     CredentialParseError(String),
     ForgeAPIError(String),
+    ForgeResponseError(ForgeError),
     RegexParseError(String),
     ReqwestError(reqwest::Error),
+    JournalError(String),
+    PlanError(String),
+    Interrupted,
+    PreflightError(String),
+    ConfigError(String),
+    ChecksumMismatch(String),
 }
 
 impl From<reqwest::Error> for AppError {
@@ -25,6 +34,16 @@ impl From<reqwest::Error> for AppError {
     }
 }
 
+impl AppError {
+    /// The classified forge response behind this error, if any.
+    pub fn forge_error(&self) -> Option<&ForgeError> {
+        match self {
+            AppError::ForgeResponseError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
 impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -71,9 +90,30 @@ impl fmt::Display for AppError {
             AppError::ForgeAPIError(message) => {
                 write!(f, "Forge API: {}", message)
             }
+            AppError::ForgeResponseError(err) => {
+                write!(f, "Forge API: {}", err)
+            }
             AppError::ReqwestError(err) => {
                 write!(f, "Request Error: {}", err)
             }
+            AppError::JournalError(message) => {
+                write!(f, "Run journal: {}", message)
+            }
+            AppError::PlanError(message) => {
+                write!(f, "Plan: {}", message)
+            }
+            AppError::Interrupted => {
+                write!(f, "Interrupted by user")
+            }
+            AppError::PreflightError(checks) => {
+                write!(f, "Pre-flight checks failed: {}", checks)
+            }
+            AppError::ConfigError(message) => {
+                write!(f, "Invalid configuration: {}", message)
+            }
+            AppError::ChecksumMismatch(message) => {
+                write!(f, "Checksum mismatch: {}", message)
+            }
         }
     }
 }
@@ -89,10 +129,17 @@ impl std::error::Error for AppError {
             AppError::InputError(source) => Some(source),
             AppError::MissingPrerequisites(_) => None,
             AppError::ForgeAPIError(_) => None,
+            AppError::ForgeResponseError(_) => None,
             AppError::UnknownSiteType(_) => None,
             AppError::CredentialParseError(_) => None,
             AppError::RegexParseError(_) => None,
             AppError::ReqwestError(source) => Some(source),
+            AppError::JournalError(_) => None,
+            AppError::PlanError(_) => None,
+            AppError::Interrupted => None,
+            AppError::PreflightError(_) => None,
+            AppError::ConfigError(_) => None,
+            AppError::ChecksumMismatch(_) => None,
         }
     }
 }
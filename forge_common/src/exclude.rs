@@ -0,0 +1,117 @@
+// Paths left out when the site files are copied
+
+use std::{fs, path::Path};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::{
+    archive::ArchiveProgress,
+    error::{AppError, AppResult},
+    sync::{EntryKind, Manifest},
+};
+
+/// Per-site exclude file in the site root, in `.gitignore` syntax.
+pub const IGNORE_FILE: &str = ".forgeignore";
+
+/// Exclude patterns in `.gitignore` syntax, matched against paths relative
+/// to the site root.
+#[derive(Debug, Clone)]
+pub struct Excludes {
+    patterns: Vec<String>,
+    matcher: Gitignore,
+}
+
+impl Default for Excludes {
+    fn default() -> Self {
+        Excludes {
+            patterns: vec![],
+            matcher: Gitignore::empty(),
+        }
+    }
+}
+
+impl Excludes {
+    pub fn new(patterns: &[String]) -> AppResult<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            builder.add_line(None, pattern).map_err(|e| {
+                AppError::ConfigError(format!("Invalid exclude pattern `{}`: {}", pattern, e))
+            })?;
+        }
+
+        let matcher = builder
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Invalid exclude patterns: {}", e)))?;
+
+        Ok(Excludes {
+            patterns: patterns.to_vec(),
+            matcher,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether `path`, relative to the site root, or a folder above it is excluded.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    /// Removes the excluded paths from a listing, returning how much was left out.
+    pub fn filter(&self, manifest: &mut Manifest) -> ArchiveProgress {
+        let mut excluded = ArchiveProgress::default();
+        if self.is_empty() {
+            return excluded;
+        }
+
+        manifest.retain(|path, entry| {
+            let is_dir = entry.kind == EntryKind::Directory;
+            if !self.is_excluded(Path::new(path), is_dir) {
+                return true;
+            }
+
+            excluded.excluded_files += 1;
+            if entry.kind == EntryKind::File {
+                excluded.excluded_bytes += entry.size;
+            }
+            false
+        });
+
+        excluded
+    }
+}
+
+/// The patterns in an exclude file, without blank lines and comments.
+pub fn parse_ignore_file(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Counts the files and bytes in a local folder that is left out.
+pub fn folder_size(folder: &Path) -> (u64, u64) {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return (0, 0);
+    };
+
+    entries.flatten().fold((0, 0), |(files, bytes), entry| {
+        let Ok(metadata) = fs::symlink_metadata(entry.path()) else {
+            return (files, bytes);
+        };
+
+        match metadata.is_dir() {
+            true => {
+                let (inner_files, inner_bytes) = folder_size(&entry.path());
+                (files + 1 + inner_files, bytes + inner_bytes)
+            }
+            false if metadata.is_file() => (files + 1, bytes + metadata.len()),
+            false => (files + 1, bytes),
+        }
+    })
+}
@@ -1,33 +1,286 @@
 use std::{
-    io::{self, Write},
+    io::{self, IsTerminal, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::error::{AppError, AppResult};
 
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How often progress is logged when the output is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+const BAR_WIDTH: usize = 24;
+
+/// Catches Ctrl-C so the migration stops with `AppError::Interrupted` instead
+/// of killing the process, giving the caller a chance to clean up.
+pub fn install_interrupt_handler() -> AppResult<()> {
+    ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::SeqCst))
+        .map_err(|e| AppError::CommandError("ctrlc".into(), io::Error::other(e.to_string())))
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// How far a long running task has got, fed by the task and shown by `show_progress`.
+#[derive(Clone, Default)]
+pub struct Progress(Arc<Mutex<ProgressState>>);
+
+#[derive(Default)]
+struct ProgressState {
+    bytes: u64,
+    total: Option<u64>,
+    detail: String,
+}
+
+impl Progress {
+    /// Bytes expected in all, only set when it is known up front.
+    pub fn set_total(&self, total: u64) {
+        self.0.lock().unwrap().total = Some(total);
+    }
+
+    pub fn set_bytes(&self, bytes: u64) {
+        self.0.lock().unwrap().bytes = bytes;
+    }
+
+    pub fn add_bytes(&self, bytes: u64) {
+        self.0.lock().unwrap().bytes += bytes;
+    }
+
+    /// Extra text shown after the byte counts, e.g. the number of files.
+    pub fn set_detail(&self, detail: impl Into<String>) {
+        self.0.lock().unwrap().detail = detail.into();
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.0.lock().unwrap().bytes
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        self.0.lock().unwrap().total
+    }
+
+    /// Progress line after `elapsed`, empty until there is something to show.
+    fn status(&self, elapsed: Duration) -> String {
+        let state = self.0.lock().unwrap();
+        let counts = match state.bytes {
+            0 => String::new(),
+            bytes => format_progress(bytes, state.total, elapsed),
+        };
+
+        match (counts.as_str(), state.detail.as_str()) {
+            (counts, "") => counts.to_string(),
+            ("", detail) => detail.to_string(),
+            (counts, detail) => format!("{}, {}", counts, detail),
+        }
+    }
+
+    /// The fraction done, when the total is known.
+    fn fraction(&self) -> Option<f64> {
+        let state = self.0.lock().unwrap();
+        state
+            .total
+            .filter(|total| *total > 0)
+            .map(|total| (state.bytes as f64 / total as f64).min(1.0))
+    }
+}
+
+/// Counts the bytes read through it into a `Progress`.
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, progress: &Progress) -> Self {
+        ProgressReader {
+            inner,
+            progress: progress.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.progress.add_bytes(read as u64);
+        Ok(read)
+    }
+}
+
+/// Counts the bytes written through it into a `Progress`.
+pub struct ProgressWriter<W> {
+    inner: W,
+    progress: Progress,
+}
+
+impl<W: Write> ProgressWriter<W> {
+    pub fn new(inner: W, progress: &Progress) -> Self {
+        ProgressWriter {
+            inner,
+            progress: progress.clone(),
+        }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.add_bytes(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bytes done against the total, when known, with the throughput and the
+/// time left, e.g. `1.0 GB of 4.0 GB (25%), 12.5 MB/s, 4m 05s left`.
+pub fn format_progress(bytes: u64, total: Option<u64>, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let rate = match seconds >= 1.0 {
+        true => Some(bytes as f64 / seconds),
+        false => None,
+    };
+
+    let mut line = match total {
+        Some(total) if total > 0 => format!(
+            "{} of {} ({}%)",
+            format_bytes(bytes),
+            format_bytes(total),
+            (bytes.min(total) * 100 / total)
+        ),
+        _ => format_bytes(bytes),
+    };
+
+    if let Some(rate) = rate {
+        line.push_str(&format!(", {}/s", format_bytes(rate as u64)));
+    }
+
+    if let (Some(total), Some(rate)) = (total, rate) {
+        if total > bytes && rate > 0.0 {
+            let left = Duration::from_secs_f64((total - bytes) as f64 / rate);
+            line.push_str(&format!(", {} left", format_duration(left)));
+        }
+    }
+
+    line
+}
+
+/// Short duration, e.g. `45s`, `4m 05s` or `1h 02m`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        seconds if seconds >= 3600 => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+        seconds if seconds >= 60 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        seconds => format!("{}s", seconds),
+    }
+}
+
+/// Text progress bar, `width` characters wide.
+fn progress_bar(fraction: f64, width: usize) -> String {
+    let done = (fraction * width as f64).round() as usize;
+    format!("{}{}", "█".repeat(done), "░".repeat(width - done))
+}
+
+/// Human readable size, e.g. `1.5 MB`.
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        bytes if bytes >= 1 << 30 => format!("{:.1} GB", bytes as f64 / (1u64 << 30) as f64),
+        bytes if bytes >= 1 << 20 => format!("{:.1} MB", bytes as f64 / (1u64 << 20) as f64),
+        bytes if bytes >= 1 << 10 => format!("{:.1} KB", bytes as f64 / (1u64 << 10) as f64),
+        bytes => format!("{} B", bytes),
+    }
+}
+
 pub fn show_spinner<F, T>(task: F, message: &str) -> AppResult<T>
 where
     F: FnOnce() -> AppResult<T> + Send + 'static,
     T: Send + 'static,
 {
+    show_progress(|_| task(), message)
+}
+
+/// Like `show_spinner`, the task reports how far along it is through
+/// `Progress`. On a terminal this is a progress bar once the total is known,
+/// otherwise a log line is printed every `LOG_INTERVAL`.
+///
+/// A task can't be cancelled, so on Ctrl-C it is still waited for and its
+/// result returned: whatever it created has to be known to be cleaned up.
+/// Tasks started after the interrupt fail with `AppError::Interrupted`.
+pub fn show_progress<F, T>(task: F, message: &str) -> AppResult<T>
+where
+    F: FnOnce(Progress) -> AppResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let progress = Progress::default();
+    let task_progress = progress.clone();
     let spinner_frames = vec!["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
     let spinner_len = spinner_frames.len();
+    let terminal = io::stdout().is_terminal();
+    let started = Instant::now();
+    let mut logged = started;
+
+    if interrupted() {
+        println!("✖  {} - interrupted", message);
+        return Err(AppError::Interrupted);
+    }
 
-    let handle = thread::spawn(task);
+    let handle = thread::spawn(move || task(task_progress));
 
     let mut i = 0;
+    let mut stopping = false;
     while !handle.is_finished() {
-        print!("\r{} {}...", spinner_frames[i % spinner_len], &message);
-        io::stdout().flush().unwrap();
+        if stopping || interrupted() {
+            if !stopping {
+                println!(
+                    "\r✖  {} - interrupted, waiting for it to finish\x1b[K",
+                    message
+                );
+                stopping = true;
+            }
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        }
+        let status = progress.status(started.elapsed());
+        if terminal {
+            let bar = progress
+                .fraction()
+                .map(|fraction| format!("{} ", progress_bar(fraction, BAR_WIDTH)))
+                .unwrap_or_default();
+            // Clear to the end of the line, the status can get shorter.
+            print!(
+                "\r{} {}... {}{}\x1b[K",
+                spinner_frames[i % spinner_len],
+                &message,
+                bar,
+                status
+            );
+            io::stdout().flush().unwrap();
+        } else if logged.elapsed() >= LOG_INTERVAL && !status.is_empty() {
+            println!("{}... {}", message, status);
+            logged = Instant::now();
+        }
         i += 1;
         thread::sleep(Duration::from_millis(100)); // Adjust speed as needed
     }
 
     // Clean up the spinner
-    print!("\r"); // Clear spinner line
-    io::stdout().flush().unwrap();
-
+    if terminal {
+        print!("\r\x1b[K"); // Clear spinner line
+        io::stdout().flush().unwrap();
+    }
     // Ensure the task finishes
     match handle.join() {
         Ok(inner_result) => match inner_result {
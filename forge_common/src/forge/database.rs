@@ -1,10 +1,10 @@
 use serde::{Deserialize, Serialize};
 
-use crate::error::{AppError, AppResult};
+use crate::error::AppResult;
 
-use super::ForgeClient;
+use super::{Deletion, ForgeClient};
 
-#[derive(Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateDatabaseRequest {
     pub name: String,
     pub user: String,
@@ -46,11 +46,15 @@ impl ForgeClient {
         self.get_request(server_id, "databases", database_id)
     }
 
-    pub fn delete_database(&self, server_id: &str, database_id: &str) -> AppResult<()> {
+    pub fn delete_database(&self, server_id: &str, database_id: &str) -> AppResult<Deletion> {
         self.delete_request(server_id, "databases", database_id)
     }
 
-    pub fn delete_database_by_name(&self, server_id: &str, database_name: &str) -> AppResult<()> {
+    pub fn delete_database_by_name(
+        &self,
+        server_id: &str,
+        database_name: &str,
+    ) -> AppResult<Deletion> {
         match self
             .list_databases(server_id)?
             .databases
@@ -59,10 +63,7 @@ impl ForgeClient {
             .map(|database| database.id)
         {
             Some(database_id) => self.delete_database(server_id, &database_id.to_string()),
-            None => Err(AppError::ForgeAPIError(format!(
-                "Could not find database with the name: {}",
-                database_name
-            ))),
+            None => Ok(Deletion::AlreadyAbsent),
         }
     }
 }
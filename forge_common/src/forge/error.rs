@@ -0,0 +1,162 @@
+// Forge API errors
+
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use serde_json::Value;
+
+/// A failed response from the forge API, classified by status so callers can
+/// react to a bad key, a missing resource or a validation failure differently.
+#[derive(Debug)]
+pub enum ForgeError {
+    /// 401 or 403, the API key is invalid or lacks access.
+    Unauthorized { status: u16, message: String },
+    /// 404, the server, site or database does not exist.
+    NotFound { url: String },
+    /// 422, forge rejected the request, messages are keyed by field.
+    Validation {
+        message: String,
+        errors: BTreeMap<String, Vec<String>>,
+    },
+    /// 429, retry after the given delay when forge sends one.
+    RateLimited { retry_after: Option<Duration> },
+    /// 5xx, forge failed to handle the request.
+    Server { status: u16, message: String },
+    /// Any other unsuccessful status.
+    Unexpected { status: u16, message: String },
+}
+
+impl ForgeError {
+    pub fn from_response(status: StatusCode, headers: &HeaderMap, url: &str, body: &str) -> Self {
+        let code = status.as_u16();
+
+        match code {
+            401 | 403 => ForgeError::Unauthorized {
+                status: code,
+                message: error_message(body),
+            },
+            404 => ForgeError::NotFound { url: url.into() },
+            422 => {
+                let (message, errors) = validation_errors(body);
+                ForgeError::Validation { message, errors }
+            }
+            429 => ForgeError::RateLimited {
+                retry_after: retry_after(headers),
+            },
+            500..=599 => ForgeError::Server {
+                status: code,
+                message: error_message(body),
+            },
+            _ => ForgeError::Unexpected {
+                status: code,
+                message: error_message(body),
+            },
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, ForgeError::NotFound { .. })
+    }
+
+    pub fn is_unauthorized(&self) -> bool {
+        matches!(self, ForgeError::Unauthorized { .. })
+    }
+}
+
+impl fmt::Display for ForgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForgeError::Unauthorized { status, message } => write!(
+                f,
+                "Access denied ({}), check the forge API key: {}",
+                status, message
+            ),
+            ForgeError::NotFound { url } => write!(f, "Not found: {}", url),
+            ForgeError::Validation { message, errors } => {
+                write!(f, "{}", message)?;
+                for (field, messages) in errors {
+                    for field_message in messages {
+                        write!(f, "\n  {}: {}", field, field_message)?;
+                    }
+                }
+                Ok(())
+            }
+            ForgeError::RateLimited { retry_after } => match retry_after {
+                Some(delay) => write!(f, "Rate limited, retry after {}s", delay.as_secs()),
+                None => write!(f, "Rate limited"),
+            },
+            ForgeError::Server { status, message } => {
+                write!(f, "Forge server error {}: {}", status, message)
+            }
+            ForgeError::Unexpected { status, message } => {
+                write!(f, "Request returned error {}: {}", status, message)
+            }
+        }
+    }
+}
+
+/// The `message` field of a JSON error body, or the body itself.
+fn error_message(body: &str) -> String {
+    let message = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|value| value.get("message")?.as_str().map(String::from));
+
+    match message {
+        Some(message) => message,
+        None if body.trim().is_empty() => "No error details available".into(),
+        None => body.trim().to_string(),
+    }
+}
+
+/// Parses a 422 body. Forge wraps field errors in `errors` on newer
+/// endpoints and returns them at the top level on older ones.
+fn validation_errors(body: &str) -> (String, BTreeMap<String, Vec<String>>) {
+    let value = serde_json::from_str::<Value>(body).unwrap_or(Value::Null);
+    let message = value
+        .get("message")
+        .and_then(Value::as_str)
+        .unwrap_or("The given data was invalid.")
+        .to_string();
+
+    let fields = match value.get("errors") {
+        Some(Value::Object(errors)) => Some(errors),
+        _ => value.as_object(),
+    };
+
+    let errors = fields
+        .into_iter()
+        .flatten()
+        .filter(|(field, _)| field.as_str() != "message")
+        .filter_map(|(field, messages)| {
+            let messages: Vec<String> = match messages {
+                Value::Array(messages) => messages
+                    .iter()
+                    .filter_map(|message| message.as_str().map(String::from))
+                    .collect(),
+                Value::String(message) => vec![message.clone()],
+                _ => return None,
+            };
+            Some((field.clone(), messages))
+        })
+        .filter(|(_, messages)| !messages.is_empty())
+        .collect();
+
+    (message, errors)
+}
+
+/// `Retry-After` as either delay seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
@@ -1,26 +1,65 @@
 pub mod database;
+pub mod error;
+pub mod retry;
+pub mod server;
 pub mod site;
 pub mod user;
 
-use std::time::Duration;
+use std::{fs, path::PathBuf, thread, time::Duration};
 
 use reqwest::{
-    blocking::{Client, RequestBuilder},
+    blocking::{Client, RequestBuilder, Response},
     header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Certificate, Proxy,
 };
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::error::{AppError, AppResult};
 
+use self::{
+    error::ForgeError,
+    retry::{RetryPolicy, Throttle},
+};
+
+/// Outcome of a delete that did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deletion {
+    Removed,
+    AlreadyAbsent,
+}
+
+pub const DEFAULT_BASE_URL: &str = "https://forge.laravel.com";
+pub const DEFAULT_API_VERSION: &str = "v1";
+
+/// Connection settings for the forge API, unset values use the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ForgeClientOptions {
+    pub base_url: Option<String>,
+    pub api_version: Option<String>,
+    /// Per request timeout, in seconds.
+    pub timeout: Option<u64>,
+    pub proxy: Option<String>,
+    /// PEM bundle trusted in addition to the system roots.
+    pub ca_bundle: Option<String>,
+    pub max_retries: Option<u32>,
+    pub requests_per_minute: Option<u32>,
+}
+
 pub struct ForgeClient {
     pub api_key: String,
     pub base_url: String,
     pub version: String,
     pub client: Client,
+    pub retry: RetryPolicy,
+    throttle: Option<Throttle>,
 }
 
 impl ForgeClient {
     pub fn new(api_key: &str) -> AppResult<Self> {
+        Self::with_options(api_key, &ForgeClientOptions::default())
+    }
+
+    pub fn with_options(api_key: &str, options: &ForgeClientOptions) -> AppResult<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(
             AUTHORIZATION,
@@ -31,17 +70,67 @@ impl ForgeClient {
         headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
 
+        let mut builder = Client::builder().default_headers(headers);
+
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(proxy) = &options.proxy {
+            let proxy = Proxy::all(proxy).map_err(|e| {
+                AppError::ForgeAPIError(format!("Invalid proxy '{}': {}", proxy, e))
+            })?;
+            builder = builder.proxy(proxy);
+        }
+
+        if let Some(ca_bundle) = &options.ca_bundle {
+            for certificate in load_ca_bundle(ca_bundle)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        let base_url = options
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+            .to_string();
+
+        let mut retry = RetryPolicy::default();
+        if let Some(max_retries) = options.max_retries {
+            retry.max_retries = max_retries;
+        }
+
         Ok(Self {
             api_key: api_key.to_string(),
-            base_url: "https://forge.laravel.com".into(),
-            version: "v1".into(),
-            client: Client::builder()
-                .default_headers(headers)
+            base_url,
+            version: options
+                .api_version
+                .clone()
+                .unwrap_or(DEFAULT_API_VERSION.into()),
+            client: builder
                 .build()
                 .map_err(|_| AppError::ForgeAPIError("Unable to contruct request client".into()))?,
+            retry,
+            throttle: Throttle::per_minute(
+                options
+                    .requests_per_minute
+                    .unwrap_or(retry::DEFAULT_REQUESTS_PER_MINUTE),
+            ),
         })
     }
 
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.retry.max_retries = max_retries;
+        self
+    }
+
+    /// Limits outgoing requests, `0` turns the throttle off.
+    pub fn with_rate_limit(mut self, requests_per_minute: u32) -> Self {
+        self.throttle = Throttle::per_minute(requests_per_minute);
+        self
+    }
+
     pub fn post_request<T: DeserializeOwned, U: Serialize>(
         &self,
         server_id: &str,
@@ -54,7 +143,7 @@ impl ForgeClient {
         );
 
         let request_builder = self.client.post(&url).json(request_data);
-        match self.send_request(request_builder)? {
+        match self.send_request(request_builder, false)? {
             Some(data) => Ok(data),
             None => Err(AppError::ForgeAPIError(
                 "Expected response data, but received none.".to_string(),
@@ -62,16 +151,53 @@ impl ForgeClient {
         }
     }
 
-    fn delete_request(&self, server_id: &str, endpoint: &str, resource_id: &str) -> AppResult<()> {
+    /// Deletes a resource and waits until forge no longer returns it. A
+    /// resource that is already gone counts as deleted.
+    fn delete_request(
+        &self,
+        server_id: &str,
+        endpoint: &str,
+        resource_id: &str,
+    ) -> AppResult<Deletion> {
         let url = format!(
             "{}/api/{}/servers/{}/{}/{}",
             self.base_url, self.version, server_id, endpoint, resource_id
         );
 
         let request_builder = self.client.delete(&url);
-        let _ = self.send_request::<()>(request_builder);
+        match self.send_request::<serde_json::Value>(request_builder, true) {
+            Ok(_) => {}
+            Err(e) if e.forge_error().is_some_and(|err| err.is_not_found()) => {
+                return Ok(Deletion::AlreadyAbsent)
+            }
+            Err(e) => return Err(e),
+        }
+
+        self.wait_until_deleted(server_id, endpoint, resource_id)?;
+        Ok(Deletion::Removed)
+    }
+
+    fn wait_until_deleted(
+        &self,
+        server_id: &str,
+        endpoint: &str,
+        resource_id: &str,
+    ) -> AppResult<()> {
+        let max_attempts = 30;
+        let delay = Duration::from_secs(2);
 
-        Ok(())
+        for _ in 1..=max_attempts {
+            match self.get_request::<serde_json::Value>(server_id, endpoint, resource_id) {
+                Ok(_) => thread::sleep(delay),
+                Err(e) if e.forge_error().is_some_and(|err| err.is_not_found()) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(AppError::ForgeAPIError(format!(
+            "{} {} on server {} still exists after being deleted",
+            endpoint, resource_id, server_id
+        )))
     }
 
     fn list_request<T: DeserializeOwned>(&self, server_id: &str, endpoint: &str) -> AppResult<T> {
@@ -81,7 +207,7 @@ impl ForgeClient {
         );
 
         let request_builder = self.client.get(&url);
-        match self.send_request(request_builder)? {
+        match self.send_request(request_builder, true)? {
             Some(data) => Ok(data),
             None => Err(AppError::ForgeAPIError(
                 "Expected response data, but received none.".to_string(),
@@ -101,7 +227,19 @@ impl ForgeClient {
         );
 
         let request_builder = self.client.get(&url);
-        match self.send_request(request_builder)? {
+        match self.send_request(request_builder, true)? {
+            Some(data) => Ok(data),
+            None => Err(AppError::ForgeAPIError(
+                "Expected response data, but received none.".to_string(),
+            )),
+        }
+    }
+
+    fn get_path_request<T: DeserializeOwned>(&self, path: &str) -> AppResult<T> {
+        let url = format!("{}/api/{}/{}", self.base_url, self.version, path);
+
+        let request_builder = self.client.get(&url);
+        match self.send_request(request_builder, true)? {
             Some(data) => Ok(data),
             None => Err(AppError::ForgeAPIError(
                 "Expected response data, but received none.".to_string(),
@@ -122,7 +260,7 @@ impl ForgeClient {
         );
 
         let request_builder = self.client.put(&url).json(request_data);
-        match self.send_request(request_builder)? {
+        match self.send_request(request_builder, true)? {
             Some(data) => Ok(data),
             None => Err(AppError::ForgeAPIError(
                 "Expected response data, but received none.".to_string(),
@@ -130,25 +268,31 @@ impl ForgeClient {
         }
     }
 
+    /// Sends a request, retrying transient failures. Requests that aren't
+    /// idempotent are only retried when forge cannot have acted on them.
     fn send_request<T: DeserializeOwned>(
         &self,
         request_builder: RequestBuilder,
+        idempotent: bool,
     ) -> AppResult<Option<T>> {
-        let response = request_builder
-            .send()
-            .map_err(|e| AppError::ForgeAPIError(format!("Request failed: {}", e)))?;
+        let mut retry = 0;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response
-                .text()
-                .unwrap_or_else(|_| "No error details available".into());
+        let response = loop {
+            let attempt = request_builder.try_clone().ok_or_else(|| {
+                AppError::ForgeAPIError("Unable to retry a streaming request".into())
+            })?;
 
-            return Err(AppError::ForgeAPIError(format!(
-                "Request returned error {}: {}",
-                status, error_text
-            )));
-        }
+            let error = match self.send_once(attempt) {
+                Ok(response) => break response,
+                Err(error) => error,
+            };
+
+            retry += 1;
+            match self.retry_delay(&error, idempotent, retry) {
+                Some(delay) => thread::sleep(delay),
+                None => return Err(error),
+            }
+        };
 
         let response_text = response
             .text()
@@ -166,4 +310,63 @@ impl ForgeClient {
 
         Ok(Some(parsed_response))
     }
+
+    fn send_once(&self, request_builder: RequestBuilder) -> AppResult<Response> {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait();
+        }
+
+        let response = request_builder.send()?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let url = response.url().to_string();
+            let error_text = response.text().unwrap_or_default();
+
+            return Err(AppError::ForgeResponseError(ForgeError::from_response(
+                status,
+                &headers,
+                &url,
+                &error_text,
+            )));
+        }
+
+        Ok(response)
+    }
+
+    /// How long to wait before retrying after `error`, or `None` to give up.
+    fn retry_delay(&self, error: &AppError, idempotent: bool, retry: u32) -> Option<Duration> {
+        if retry > self.retry.max_retries {
+            return None;
+        }
+
+        match error {
+            // Rejected before forge handled it, safe to repeat any request.
+            AppError::ForgeResponseError(ForgeError::RateLimited { retry_after }) => {
+                Some(retry_after.unwrap_or_else(|| self.retry.backoff(retry)))
+            }
+            AppError::ReqwestError(e) if e.is_connect() => Some(self.retry.backoff(retry)),
+            AppError::ForgeResponseError(ForgeError::Server { .. }) if idempotent => {
+                Some(self.retry.backoff(retry))
+            }
+            AppError::ReqwestError(e) if idempotent && e.is_timeout() => {
+                Some(self.retry.backoff(retry))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn load_ca_bundle(path: &str) -> AppResult<Vec<Certificate>> {
+    let path = PathBuf::from(path);
+    let pem = fs::read(&path).map_err(|e| AppError::FileError(path.clone(), e))?;
+
+    Certificate::from_pem_bundle(&pem).map_err(|e| {
+        AppError::ForgeAPIError(format!(
+            "Unable to read CA bundle {}: {}",
+            path.display(),
+            e
+        ))
+    })
 }
@@ -0,0 +1,76 @@
+// Retries and rate limiting
+
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::RngExt;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 4;
+/// Forge allows 60 API requests per minute.
+pub const DEFAULT_REQUESTS_PER_MINUTE: u32 = 60;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff for the given retry (starting at 1), with jitter
+    /// so parallel runs don't retry in lockstep.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        let half = exponential / 2;
+        let jitter = rand::rng().random_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter)
+    }
+}
+
+/// Spaces outgoing requests so a run stays under the forge rate limit.
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn per_minute(requests: u32) -> Option<Self> {
+        match requests {
+            0 => None,
+            requests => Some(Self {
+                interval: Duration::from_secs(60) / requests,
+                next: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+
+    /// Blocks until the next request may be sent.
+    pub fn wait(&self) {
+        let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+
+        if *next > now {
+            thread::sleep(*next - now);
+        }
+
+        *next = Instant::now().max(*next) + self.interval;
+    }
+}
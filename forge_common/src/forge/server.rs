@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+use crate::error::{AppError, AppResult};
+
+use super::ForgeClient;
+
+/// Forge provisions every server with this SSH user.
+pub const FORGE_USER: &str = "forge";
+
+#[derive(Debug, Deserialize)]
+pub struct ServerResponse {
+    pub server: Server,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListServerResponse {
+    pub servers: Vec<Server>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Server {
+    pub id: u32,
+    pub name: String,
+    pub ip_address: String,
+    pub private_ip_address: Option<String>,
+    pub php_version: Option<String>,
+    pub database_type: Option<String>,
+    pub provider: Option<String>,
+    pub is_ready: bool,
+    #[serde(default)]
+    pub tags: Vec<Tag>,
+}
+
+/// Forge returns tags either as plain names or as tag objects.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Tag {
+    Name(String),
+    Object { name: String },
+}
+
+impl Tag {
+    pub fn name(&self) -> &str {
+        match self {
+            Tag::Name(name) => name,
+            Tag::Object { name } => name,
+        }
+    }
+}
+
+impl Server {
+    /// SSH destination for the server as `user`, usually `FORGE_USER`.
+    pub fn ssh_host(&self, user: &str) -> String {
+        format!("{}@{}", user, self.ip_address)
+    }
+}
+
+impl ForgeClient {
+    pub fn list_servers(&self) -> AppResult<ListServerResponse> {
+        self.get_path_request("servers")
+    }
+
+    pub fn get_server(&self, server_id: &str) -> AppResult<ServerResponse> {
+        self.get_path_request(&format!("servers/{}", server_id))
+    }
+
+    pub fn find_server_by_name(&self, server_name: &str) -> AppResult<Server> {
+        let mut matches: Vec<Server> = self
+            .list_servers()?
+            .servers
+            .into_iter()
+            .filter(|server| server.name == server_name)
+            .collect();
+
+        match matches.len() {
+            0 => Err(AppError::ForgeAPIError(format!(
+                "Could not find server with the name: {}",
+                server_name
+            ))),
+            1 => Ok(matches.remove(0)),
+            _ => Err(AppError::ForgeAPIError(format!(
+                "More than one server is named {}, use the server ID instead",
+                server_name
+            ))),
+        }
+    }
+}
@@ -4,9 +4,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::{AppError, AppResult};
 
-use super::ForgeClient;
+use super::{Deletion, ForgeClient};
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateSiteRequest {
     pub domain: String,
     pub project_type: String,
@@ -76,13 +76,32 @@ impl Default for CreateSiteRequest {
     }
 }
 
+impl Site {
+    /// Folder the site is deployed into. `web_directory` is the public
+    /// directory inside it, e.g. `/home/forge/example.com/public`.
+    pub fn root_directory(&self) -> String {
+        match self.web_directory.strip_suffix(&self.directory) {
+            Some(root) if !self.directory.is_empty() && self.directory != "/" => root.to_string(),
+            _ => format!("/home/{}/{}", self.username, self.name),
+        }
+    }
+}
+
 impl ForgeClient {
     pub fn wait_for_site_ready(&self, server_id: &str, site_id: &str) -> AppResult<()> {
         let max_attempts = 10;
         let delay = Duration::from_secs(10);
 
         for attempt in 1..=max_attempts {
-            let site_status = self.get_site_status(server_id, site_id)?;
+            let site_status = match self.get_site_status(server_id, site_id) {
+                Err(e) if e.forge_error().is_some_and(|err| err.is_not_found()) => {
+                    return Err(AppError::ForgeAPIError(format!(
+                        "Site {} was removed from forge before it finished installing",
+                        site_id
+                    )))
+                }
+                result => result?,
+            };
 
             if site_status == "installed" {
                 return Ok(());
@@ -111,11 +130,11 @@ impl ForgeClient {
         self.get_request(server_id, "sites", site_id)
     }
 
-    pub fn delete_site(&self, server_id: &str, site_id: &str) -> AppResult<()> {
+    pub fn delete_site(&self, server_id: &str, site_id: &str) -> AppResult<Deletion> {
         self.delete_request(server_id, "sites", site_id)
     }
 
-    pub fn delete_site_by_name(&self, server_id: &str, site_name: &str) -> AppResult<()> {
+    pub fn delete_site_by_name(&self, server_id: &str, site_name: &str) -> AppResult<Deletion> {
         match self
             .list_sites(server_id)?
             .sites
@@ -124,10 +143,7 @@ impl ForgeClient {
             .map(|site| site.id)
         {
             Some(site_id) => self.delete_site(server_id, &site_id.to_string()),
-            None => Err(AppError::ForgeAPIError(format!(
-                "Could not find site with the name: {}",
-                site_name
-            ))),
+            None => Ok(Deletion::AlreadyAbsent),
         }
     }
 }
@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 
-use crate::error::{AppError, AppResult};
+use crate::error::AppResult;
 
-use super::ForgeClient;
+use super::{Deletion, ForgeClient};
 
 #[derive(Debug, Serialize)]
 pub struct CreateUserRequest {
@@ -13,21 +13,21 @@ pub struct CreateUserRequest {
 
 #[derive(Debug, Deserialize)]
 pub struct UserResponse {
-    user: User,
+    pub user: User,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ListUserResponse {
-    users: Vec<User>,
+    pub users: Vec<User>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct User {
-    id: u32,
-    name: String,
-    status: String,
-    created_at: String,
-    databases: Vec<u32>,
+    pub id: u32,
+    pub name: String,
+    pub status: String,
+    pub created_at: String,
+    pub databases: Vec<u32>,
 }
 
 #[derive(Debug, Serialize)]
@@ -53,10 +53,10 @@ impl ForgeClient {
     ) -> AppResult<UserResponse> {
         self.put_request(server_id, "database-users", user_id, uur)
     }
-    pub fn delete_user(&self, server_id: &str, user_id: &str) -> AppResult<()> {
+    pub fn delete_user(&self, server_id: &str, user_id: &str) -> AppResult<Deletion> {
         self.delete_request(server_id, "database-users", user_id)
     }
-    pub fn delete_user_by_name(&self, server_id: &str, user_name: &str) -> AppResult<()> {
+    pub fn delete_user_by_name(&self, server_id: &str, user_name: &str) -> AppResult<Deletion> {
         match self
             .list_users(server_id)?
             .users
@@ -65,10 +65,7 @@ impl ForgeClient {
             .map(|user| user.id)
         {
             Some(user_id) => self.delete_user(server_id, &user_id.to_string()),
-            None => Err(AppError::ForgeAPIError(format!(
-                "Could not find user with the name: {}",
-                user_name
-            ))),
+            None => Ok(Deletion::AlreadyAbsent),
         }
     }
 }
@@ -0,0 +1,262 @@
+// Run journal
+
+use std::{
+    fmt, fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::FinalConfig,
+    error::{AppError, AppResult},
+    feedback,
+    plan::Plan,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    BackupDatabase,
+    BackupFiles,
+    CreateSite,
+    CreateDatabase,
+    WaitForSite,
+    RestoreFiles,
+    RewriteConfig,
+    RestoreDatabase,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Step::BackupDatabase => "Backing up database",
+            Step::BackupFiles => "Backing up files",
+            Step::CreateSite => "Creating forge site",
+            Step::CreateDatabase => "Creating forge database",
+            Step::WaitForSite => "Waiting for forge to create the site",
+            Step::RestoreFiles => "Copying files via SSH",
+            Step::RewriteConfig => "Updating database settings on destination server",
+            Step::RestoreDatabase => "Restoring DB on destination server",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failure {
+    pub step: Step,
+    pub message: String,
+    pub failed_at: String,
+}
+
+/// Persisted record of a migration run, so a failed run can be resumed
+/// from the step that failed instead of starting over.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub run_id: String,
+    pub started_at: String,
+    pub source_folder: String,
+    pub dest_server_id: String,
+    pub dest_site_name: String,
+    pub plan: Plan,
+    pub db_archive: Option<PathBuf>,
+    pub files_archive: Option<PathBuf>,
+    pub site_id: Option<u32>,
+    /// Root folder of the new site, the archives are restored into it.
+    pub web_directory: Option<String>,
+    pub database_id: Option<u32>,
+    pub database_user_id: Option<u32>,
+    pub db_password: Option<String>,
+    pub completed_steps: Vec<Step>,
+    pub failure: Option<Failure>,
+    pub rolled_back_at: Option<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn create(config: &FinalConfig, plan: Plan) -> AppResult<Self> {
+        let run_id = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+        let path = journal_path(&config.temp_folder, &run_id);
+
+        if path.exists() {
+            return Err(AppError::JournalError(format!(
+                "A run with the id {} already exists",
+                run_id
+            )));
+        }
+
+        let journal = Journal {
+            run_id,
+            started_at: Utc::now().to_rfc3339(),
+            source_folder: config.source_folder.clone(),
+            dest_server_id: config.dest_server_id.clone(),
+            dest_site_name: config.dest_site_name.clone(),
+            plan,
+            db_archive: None,
+            files_archive: None,
+            site_id: None,
+            web_directory: None,
+            database_id: None,
+            database_user_id: None,
+            db_password: None,
+            completed_steps: vec![],
+            failure: None,
+            rolled_back_at: None,
+            path,
+        };
+        journal.save()?;
+
+        Ok(journal)
+    }
+
+    pub fn load(temp_folder: &str, run_id: &str) -> AppResult<Self> {
+        let path = journal_path(temp_folder, run_id);
+        let content =
+            fs::read_to_string(&path).map_err(|e| AppError::FileError(path.clone(), e))?;
+
+        let mut journal: Journal = serde_json::from_str(&content).map_err(|e| {
+            AppError::JournalError(format!("Unable to parse {}: {}", path.display(), e))
+        })?;
+        journal.path = path;
+
+        Ok(journal)
+    }
+
+    /// Ensures the journal belongs to the migration described by `config`.
+    pub fn check_matches(&self, config: &FinalConfig) -> AppResult<()> {
+        let mismatch = [
+            ("source folder", &self.source_folder, &config.source_folder),
+            (
+                "destination server",
+                &self.dest_server_id,
+                &config.dest_server_id,
+            ),
+            (
+                "destination site",
+                &self.dest_site_name,
+                &config.dest_site_name,
+            ),
+        ]
+        .into_iter()
+        .find(|(_, journal, config)| journal != config);
+
+        match mismatch {
+            Some((field, journal, config)) => Err(AppError::JournalError(format!(
+                "Run {} was started with {} '{}', but '{}' is configured",
+                self.run_id, field, journal, config
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether this run created anything on the destination server.
+    pub fn has_created_resources(&self) -> bool {
+        self.site_id.is_some() || self.database_id.is_some() || self.database_user_id.is_some()
+    }
+
+    pub fn is_complete(&self, step: Step) -> bool {
+        self.completed_steps.contains(&step)
+    }
+
+    /// Marks the steps that copy the site as pending again, so a finished run
+    /// that syncs its files can bring the same site up to date.
+    pub fn restart_sync(&mut self) -> AppResult<()> {
+        if self.plan.sync.is_none() {
+            return Err(AppError::JournalError(format!(
+                "Run {} copied its files with an archive, only runs with --file-transfer sync can be synced again",
+                self.run_id
+            )));
+        }
+
+        if !self.is_complete(Step::WaitForSite) {
+            return Err(AppError::JournalError(format!(
+                "Run {} has not created its site yet, finish it with --resume {}",
+                self.run_id, self.run_id
+            )));
+        }
+
+        self.completed_steps.retain(|step| {
+            !matches!(
+                step,
+                Step::BackupDatabase
+                    | Step::BackupFiles
+                    | Step::RestoreFiles
+                    | Step::RewriteConfig
+                    | Step::RestoreDatabase
+            )
+        });
+        self.failure = None;
+        self.save()
+    }
+
+    /// Runs `task` unless `step` has already completed, recording the outcome.
+    pub fn run_step<F>(&mut self, step: Step, task: F) -> AppResult<()>
+    where
+        F: FnOnce(&mut Self) -> AppResult<()>,
+    {
+        if self.is_complete(step) {
+            println!("↷  {} - already complete, skipping", step);
+            return Ok(());
+        }
+
+        match task(self) {
+            Ok(()) => {
+                self.completed_steps.push(step);
+                self.failure = None;
+                self.save()?;
+                // The step finished after Ctrl-C, stop before the next one.
+                match feedback::interrupted() {
+                    true => Err(AppError::Interrupted),
+                    false => Ok(()),
+                }
+            }
+            Err(e) => {
+                self.failure = Some(Failure {
+                    step,
+                    message: e.to_string(),
+                    failed_at: Utc::now().to_rfc3339(),
+                });
+                if let Err(save_error) = self.save() {
+                    eprintln!("Warning: unable to update run journal: {}", save_error);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    pub fn save(&self) -> AppResult<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| AppError::FileError(parent.to_path_buf(), e))?;
+        }
+
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::JournalError(format!("Unable to serialize journal: {}", e)))?;
+
+        // The journal holds the generated database password, keep it private.
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .map_err(|e| AppError::FileError(self.path.clone(), e))?;
+
+        file.write_all(content.as_bytes())
+            .map_err(|e| AppError::FileError(self.path.clone(), e))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn journal_path(temp_folder: &str, run_id: &str) -> PathBuf {
+    Path::new(temp_folder)
+        .join("runs")
+        .join(format!("{}.json", run_id))
+}
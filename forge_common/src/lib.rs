@@ -1,9 +1,24 @@
+pub mod archive;
 pub mod args;
 pub mod backup;
+pub mod compression;
 pub mod config;
 pub mod database;
 pub mod error;
+pub mod exclude;
 pub mod feedback;
 pub mod forge;
+pub mod journal;
+pub mod picker;
+pub mod plan;
+pub mod preflight;
+pub mod remote;
+pub mod rollback;
+pub mod runner;
 pub mod setup;
+pub mod shell;
 pub mod site_type;
+pub mod source;
+pub mod ssh;
+pub mod stream;
+pub mod sync;
@@ -0,0 +1,102 @@
+// Interactive pickers backed by the forge API
+
+use dialoguer::{FuzzySelect, Input};
+
+use crate::{
+    error::{AppError, AppResult},
+    forge::{database::Database, server::Server, site::Site, ForgeClient},
+};
+
+/// Lets the user choose one of the servers on their forge account.
+pub fn pick_server(client: &ForgeClient, prompt: &str) -> AppResult<Server> {
+    let mut servers = client.list_servers()?.servers;
+    if servers.is_empty() {
+        return Err(AppError::ForgeAPIError(
+            "There are no servers on this forge account".into(),
+        ));
+    }
+
+    let items: Vec<String> = servers
+        .iter()
+        .map(|server| {
+            let mut item = format!("{} ({})", server.name, server.ip_address);
+            if !server.is_ready {
+                item.push_str(" - not ready");
+            }
+            item
+        })
+        .collect();
+
+    let selection = FuzzySelect::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(0)
+        .interact()
+        .map_err(AppError::InputError)?;
+
+    Ok(servers.swap_remove(selection))
+}
+
+/// Lets the user choose one of the sites on a server.
+pub fn pick_site(client: &ForgeClient, server_id: &str, prompt: &str) -> AppResult<Site> {
+    let mut sites = client.list_sites(server_id)?.sites;
+    if sites.is_empty() {
+        return Err(AppError::ForgeAPIError(format!(
+            "There are no sites on server {}",
+            server_id
+        )));
+    }
+
+    let items: Vec<&str> = sites.iter().map(|site| site.name.as_str()).collect();
+
+    let selection = FuzzySelect::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(0)
+        .interact()
+        .map_err(AppError::InputError)?;
+
+    Ok(sites.swap_remove(selection))
+}
+
+/// Lets the user choose one of the databases on a server.
+pub fn pick_database(client: &ForgeClient, server_id: &str, prompt: &str) -> AppResult<Database> {
+    let mut databases = client.list_databases(server_id)?.databases;
+    if databases.is_empty() {
+        return Err(AppError::ForgeAPIError(format!(
+            "There are no databases on server {}",
+            server_id
+        )));
+    }
+
+    let items: Vec<&str> = databases
+        .iter()
+        .map(|database| database.name.as_str())
+        .collect();
+
+    let selection = FuzzySelect::new()
+        .with_prompt(prompt)
+        .items(&items)
+        .default(0)
+        .interact()
+        .map_err(AppError::InputError)?;
+
+    Ok(databases.swap_remove(selection))
+}
+
+/// Prompts for a name, refusing any that are already in use on the server.
+pub fn input_unused_name(prompt: &str, taken: Vec<String>, kind: &str) -> AppResult<String> {
+    Input::new()
+        .with_prompt(prompt)
+        .validate_with(|name: &String| -> Result<(), String> {
+            match taken.contains(name) {
+                true => Err(format!(
+                    "A {} named {} already exists on this server",
+                    kind, name
+                )),
+                false => Ok(()),
+            }
+        })
+        .interact_text()
+        .map_err(AppError::InputError)
+}
@@ -0,0 +1,454 @@
+// Migration plan
+
+use std::{
+    fmt, fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backup,
+    compression::Compression,
+    config::FinalConfig,
+    database::DatabaseCredentials,
+    error::{AppError, AppResult},
+    exclude::{self, Excludes},
+    forge::{database::CreateDatabaseRequest, site::CreateSiteRequest},
+    remote,
+    site_type::{self, SiteType},
+    source::SourceSite,
+    sync::{self, CompareBy, SyncOptions},
+};
+
+const REDACTED: &str = "********";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceDatabase {
+    pub database: String,
+    pub username: String,
+    /// Host and port or socket, when the site names one.
+    #[serde(default)]
+    pub server: Option<String>,
+}
+
+/// Everything a migration will do, worked out up front so it can be reviewed
+/// with `--dry-run` and executed unchanged with `--apply`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Plan {
+    pub source_folder: String,
+    /// Set when the source is a site on another forge server.
+    pub source_site: Option<SourceSite>,
+    pub site_type: String,
+    pub source_database: Option<SourceDatabase>,
+    pub db_archive: Option<PathBuf>,
+    pub files_archive: Option<PathBuf>,
+    pub dest_server_id: String,
+    pub dest_host: String,
+    pub user_name: Option<String>,
+    pub create_site: CreateSiteRequest,
+    pub create_database: CreateDatabaseRequest,
+    /// Application config rewritten with the new database credentials.
+    pub config_file: Option<String>,
+    /// New host for the application URL, when the site is renamed.
+    pub app_host: Option<String>,
+    /// Codec of both archives, plans written before it was configurable used gzip.
+    #[serde(default)]
+    pub compression: Compression,
+    /// Pipe the archives straight to the destination, `db_archive` and
+    /// `files_archive` are empty then.
+    #[serde(default)]
+    pub stream: bool,
+    /// Set when only changed files are copied, `files_archive` is empty then.
+    #[serde(default)]
+    pub sync: Option<SyncOptions>,
+    /// Paths left out of the file copy, in `.gitignore` syntax.
+    #[serde(default)]
+    pub excludes: Vec<String>,
+}
+
+impl Plan {
+    /// Detects the site type and reads its database settings without making
+    /// changes through the forge API or on the destination server.
+    pub fn build(config: &FinalConfig) -> AppResult<Self> {
+        let source_folder = Path::new(&config.source_folder);
+        let site_type =
+            detect_source_site_type(config, config.source_site.as_ref(), &config.source_folder)?;
+        let source_database = source_database_credentials(
+            config,
+            &site_type,
+            config.source_site.as_ref(),
+            &config.source_folder,
+        )?
+        .map(|creds| SourceDatabase {
+            server: creds.server(),
+            database: creds.database,
+            username: creds.username,
+        });
+
+        let db_archive = match source_database {
+            Some(_) if !config.stream => backup::generate_output_path(
+                &config.source_folder,
+                &config.temp_folder,
+                "-db.sql",
+                &config.compression,
+            ),
+            _ => None,
+        };
+        let source_name = source_folder
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let app_host = match source_name {
+            Some(name) if name == config.dest_site_name => None,
+            _ => Some(config.dest_site_name.clone()),
+        };
+
+        let excludes = exclude_patterns(
+            config,
+            &site_type,
+            config.source_site.as_ref(),
+            &config.source_folder,
+        )?;
+        Excludes::new(&excludes)?;
+
+        let files_archive = match config.stream || config.sync.is_some() {
+            true => None,
+            false => backup::generate_output_path(
+                &config.source_folder,
+                &config.temp_folder,
+                "-files.tar",
+                &config.compression,
+            ),
+        };
+
+        Ok(Plan {
+            source_folder: config.source_folder.clone(),
+            source_site: config.source_site.clone(),
+            site_type: site_type.to_string(),
+            source_database,
+            db_archive,
+            files_archive,
+            dest_server_id: config.dest_server_id.clone(),
+            dest_host: config.dest_host.clone(),
+            user_name: config.user_name.clone(),
+            create_site: create_site_request(config),
+            create_database: CreateDatabaseRequest {
+                name: config.dest_db.clone(),
+                user: config.user_name.clone().unwrap_or("forge".into()),
+                password: generate_password(20),
+            },
+            config_file: site_type.config_path().map(String::from),
+            app_host,
+            compression: config.compression,
+            stream: config.stream,
+            sync: config.sync,
+            excludes,
+        })
+    }
+
+    pub fn load(path: &Path) -> AppResult<Self> {
+        let content =
+            fs::read_to_string(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| AppError::PlanError(format!("Unable to parse {}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> AppResult<()> {
+        let content = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::PlanError(format!("Unable to serialize plan: {}", e)))?;
+
+        // The plan holds the password for the new database, keep it private.
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+
+        file.write_all(content.as_bytes())
+            .map_err(|e| AppError::FileError(path.to_path_buf(), e))
+    }
+
+    /// Whether there are files to copy, from an archive, streamed or synced.
+    pub fn restores_files(&self) -> bool {
+        self.stream || self.sync.is_some() || self.files_archive.is_some()
+    }
+
+    /// Whether there is a database to copy, from an archive or streamed.
+    pub fn restores_database(&self) -> bool {
+        match self.stream {
+            true => self.source_database.is_some(),
+            false => self.db_archive.is_some(),
+        }
+    }
+
+    /// The commands that will be run on the destination over SSH. The database
+    /// password only goes through stdin, into the option file.
+    pub fn remote_commands(&self) -> Vec<String> {
+        let mut commands = vec![];
+
+        if let Some(sync) = &self.sync {
+            commands.push(sync::manifest_command(
+                self.user_name.as_deref(),
+                "<site web directory>",
+            ));
+            if sync.compare == CompareBy::Checksum {
+                commands.push(sync::checksum_command(
+                    self.user_name.as_deref(),
+                    "<site web directory>",
+                ));
+            }
+        }
+
+        if self.restores_files() {
+            commands.push(backup::restore_files_command(
+                self.user_name.as_deref(),
+                "<site web directory>",
+                &self.compression,
+            ));
+        }
+
+        if self.restores_database() {
+            commands.push(remote::OPTION_FILE_COMMAND.to_string());
+            commands.push(backup::restore_database_command(
+                "/tmp/tmp.XXXXXXXXXX",
+                &self.create_database.name,
+                &self.compression,
+            ));
+        }
+
+        commands
+    }
+}
+
+/// Detects the site type of a local folder, or of the source site over SSH.
+pub fn detect_source_site_type(
+    config: &FinalConfig,
+    source_site: Option<&SourceSite>,
+    source_folder: &str,
+) -> AppResult<SiteType> {
+    match source_site {
+        Some(source_site) => site_type::detect_remote_site_type(
+            config.runner(&source_site.host).as_ref(),
+            source_site.isolated_user(),
+            &source_site.root_directory,
+        ),
+        None => {
+            let source_folder = Path::new(source_folder);
+            if !source_folder.is_dir() {
+                return Err(AppError::FileError(
+                    source_folder.to_path_buf(),
+                    std::io::Error::new(std::io::ErrorKind::NotFound, "Source folder not found"),
+                ));
+            }
+            site_type::detect_site_type(source_folder)
+        }
+    }
+}
+
+pub fn source_database_credentials(
+    config: &FinalConfig,
+    site_type: &SiteType,
+    source_site: Option<&SourceSite>,
+    source_folder: &str,
+) -> AppResult<Option<DatabaseCredentials>> {
+    match source_site {
+        Some(source_site) => site_type.get_remote_database_credentials(
+            config.runner(&source_site.host).as_ref(),
+            source_site.isolated_user(),
+            &source_site.root_directory,
+        ),
+        None => site_type.get_database_credentials(Path::new(source_folder)),
+    }
+}
+
+/// The site type's defaults, the configured patterns and the site's
+/// `.forgeignore`, in that order so later patterns can re-include paths.
+pub fn exclude_patterns(
+    config: &FinalConfig,
+    site_type: &SiteType,
+    source_site: Option<&SourceSite>,
+    source_folder: &str,
+) -> AppResult<Vec<String>> {
+    let mut patterns: Vec<String> = vec![];
+    if config.default_excludes {
+        patterns.extend(site_type.default_excludes().iter().map(|p| p.to_string()));
+    }
+    patterns.extend(config.excludes.iter().cloned());
+
+    let ignore_file = match source_site {
+        Some(source_site) => {
+            let runner = config.runner(&source_site.host);
+            let user_name = source_site.isolated_user();
+            let path = format!(
+                "{}/{}",
+                source_site.root_directory.trim_end_matches('/'),
+                exclude::IGNORE_FILE
+            );
+            match remote::path_exists(runner.as_ref(), user_name, &path)? {
+                true => Some(remote::read_file(runner.as_ref(), user_name, &path)?),
+                false => None,
+            }
+        }
+        None => {
+            let path = Path::new(source_folder).join(exclude::IGNORE_FILE);
+            match path.exists() {
+                true => Some(fs::read_to_string(&path).map_err(|e| AppError::FileError(path, e))?),
+                false => None,
+            }
+        }
+    };
+    if let Some(content) = ignore_file {
+        patterns.extend(exclude::parse_ignore_file(&content));
+    }
+
+    Ok(patterns)
+}
+
+fn create_site_request(config: &FinalConfig) -> CreateSiteRequest {
+    let request = CreateSiteRequest {
+        domain: config.dest_site_name.clone(),
+        isolated: config.isolated,
+        username: config.user_name.clone().unwrap_or_default(),
+        ..Default::default()
+    };
+
+    // Recreate the source site as it is configured on its forge server.
+    match &config.source_site {
+        Some(source_site) => CreateSiteRequest {
+            project_type: source_site.project_type.clone(),
+            php_version: source_site.php_version.clone(),
+            aliases: source_site.aliases.clone(),
+            directory: source_site.web_directory.clone(),
+            ..request
+        },
+        None => request,
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional_path = |path: &Option<PathBuf>| match path {
+            Some(path) => path.display().to_string(),
+            None => "(none)".into(),
+        };
+
+        writeln!(f, "Migration plan")?;
+        match &self.source_site {
+            Some(source_site) => writeln!(
+                f,
+                "  Source site:        {} (server {}, site {}) at {}:{}",
+                source_site.name,
+                source_site.server_id,
+                source_site.site_id,
+                source_site.host,
+                self.source_folder
+            )?,
+            None => writeln!(f, "  Source folder:      {}", self.source_folder)?,
+        }
+        writeln!(f, "  Site type:          {}", self.site_type)?;
+        match &self.source_database {
+            Some(db) => writeln!(
+                f,
+                "  Source database:    {} (user: {}, password: {}{})",
+                db.database,
+                db.username,
+                REDACTED,
+                db.server
+                    .as_ref()
+                    .map(|server| format!(", server: {}", server))
+                    .unwrap_or_default()
+            )?,
+            None => writeln!(f, "  Source database:    (none)")?,
+        }
+        if let Some(sync) = &self.sync {
+            writeln!(
+                f,
+                "  Files:              synced, comparing by {}{}",
+                sync.compare,
+                match sync.delete {
+                    true => ", deleting files gone from the source",
+                    false => "",
+                }
+            )?;
+        }
+        if self.stream {
+            writeln!(f, "  Archives:           streamed to the destination")?;
+        } else {
+            writeln!(
+                f,
+                "  Database archive:   {}",
+                optional_path(&self.db_archive)
+            )?;
+            writeln!(
+                f,
+                "  Files archive:      {}",
+                optional_path(&self.files_archive)
+            )?;
+        }
+        writeln!(
+            f,
+            "  Compression:        {} (level {}, {} threads)",
+            self.compression.codec, self.compression.level, self.compression.threads
+        )?;
+        match self.excludes.is_empty() {
+            true => writeln!(f, "  Excludes:           (none)")?,
+            false => writeln!(f, "  Excludes:           {}", self.excludes.join(", "))?,
+        }
+        writeln!(
+            f,
+            "  Destination server: {} (host: {})",
+            self.dest_server_id, self.dest_host
+        )?;
+
+        let create_site =
+            serde_json::to_string_pretty(&self.create_site).map_err(|_| fmt::Error)?;
+        let create_database = serde_json::to_string_pretty(&CreateDatabaseRequest {
+            password: REDACTED.into(),
+            ..self.create_database.clone()
+        })
+        .map_err(|_| fmt::Error)?;
+
+        writeln!(f)?;
+        writeln!(f, "Forge site to create:")?;
+        writeln!(f, "{}", create_site)?;
+        writeln!(f, "Forge database to create:")?;
+        writeln!(f, "{}", create_database)?;
+        writeln!(f)?;
+        writeln!(f, "Remote commands on {}:", self.dest_host)?;
+        for command in self.remote_commands() {
+            writeln!(f, "  {}", command)?;
+        }
+
+        if let Some(config_file) = &self.config_file {
+            writeln!(f)?;
+            writeln!(
+                f,
+                "Rewrite <site web directory>/{} with the new database credentials",
+                config_file
+            )?;
+            if let Some(app_host) = &self.app_host {
+                writeln!(f, "  and point the application URL at {}", app_host)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn generate_password(length: usize) -> String {
+    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+    let mut rng = rand::rng();
+
+    (0..length)
+        .map(|_| {
+            let idx = rng.random_range(0..CHARSET.len());
+            CHARSET[idx] as char
+        })
+        .collect()
+}
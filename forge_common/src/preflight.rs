@@ -0,0 +1,356 @@
+// Pre-flight checks
+
+use std::{
+    fmt,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    backup,
+    config::FinalConfig,
+    error::{AppError, AppResult},
+    exclude::Excludes,
+    feedback,
+    forge::ForgeClient,
+    plan::Plan,
+    remote,
+    runner::{CommandRunner, Stdin, Stdout},
+    shell::RemoteCommand,
+    sync::{self, CompareBy},
+};
+
+pub struct CheckResult {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+/// Outcome of every check run before a migration starts touching anything.
+pub struct Preflight {
+    pub results: Vec<CheckResult>,
+}
+
+impl Preflight {
+    pub fn run(config: &FinalConfig, client: &ForgeClient, plan: &Plan) -> Self {
+        let mut preflight = Preflight { results: vec![] };
+
+        let api_ok = preflight.check("Forge API key", || {
+            client
+                .list_servers()
+                .map(|response| format!("valid, {} servers visible", response.servers.len()))
+                .map_err(|e| e.to_string())
+        });
+
+        if api_ok {
+            preflight.check("Destination server", || {
+                let server = client
+                    .get_server(&plan.dest_server_id)
+                    .map_err(|e| e.to_string())?
+                    .server;
+                match server.is_ready {
+                    true => Ok(format!("{} ({}) is ready", server.name, server.ip_address)),
+                    false => Err(format!("{} is not ready", server.name)),
+                }
+            });
+
+            preflight.check("Site name available", || {
+                let taken = client
+                    .list_sites(&plan.dest_server_id)
+                    .map_err(|e| e.to_string())?
+                    .sites
+                    .iter()
+                    .any(|site| site.name == plan.create_site.domain);
+                match taken {
+                    true => Err(format!("{} already exists", plan.create_site.domain)),
+                    false => Ok(plan.create_site.domain.clone()),
+                }
+            });
+
+            preflight.check("Database name available", || {
+                let taken = client
+                    .list_databases(&plan.dest_server_id)
+                    .map_err(|e| e.to_string())?
+                    .databases
+                    .iter()
+                    .any(|database| database.name == plan.create_database.name);
+                match taken {
+                    true => Err(format!("{} already exists", plan.create_database.name)),
+                    false => Ok(plan.create_database.name.clone()),
+                }
+            });
+        }
+
+        let dest = config.runner(&config.dest_host);
+        let dest_ssh_ok = preflight.check("SSH to destination", || {
+            remote::run_command(dest.as_ref(), "true")
+                .map(|_| config.dest_host.clone())
+                .map_err(|e| e.to_string())
+        });
+
+        if dest_ssh_ok {
+            preflight.check("Destination tools", || {
+                missing_remote_tools(dest.as_ref(), &remote_tools(plan, "mysql"))
+            });
+
+            if let Some(user_name) = &config.user_name {
+                preflight.check("Destination sudo", || sudo_check(dest.as_ref(), user_name));
+            }
+        }
+
+        if let Some(source_site) = &plan.source_site {
+            let source = config.runner(&source_site.host);
+            let source_ssh_ok = preflight.check("SSH to source", || {
+                remote::run_command(source.as_ref(), "true")
+                    .map(|_| source_site.host.clone())
+                    .map_err(|e| e.to_string())
+            });
+
+            if source_ssh_ok {
+                preflight.check("Source tools", || {
+                    missing_remote_tools(source.as_ref(), &remote_tools(plan, "mysqldump"))
+                });
+            }
+        }
+
+        let source_size = source_size_kb(config, plan);
+        // Streamed archives never touch the temp folder.
+        if !plan.stream {
+            preflight.check("Local temp space", || {
+                let required = source_size.clone()?;
+                let available = local_free_kb(&config.temp_folder)?;
+                enough_space(required, available)
+            });
+        }
+
+        if dest_ssh_ok {
+            preflight.check("Destination disk space", || {
+                let required = source_size.clone()?;
+                let available = remote_free_kb(dest.as_ref())?;
+                enough_space(required, available)
+            });
+        }
+
+        preflight
+    }
+
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.passed)
+    }
+
+    /// Fails with an error naming the checks that did not pass.
+    pub fn ensure_passed(&self) -> AppResult<()> {
+        let failed: Vec<&str> = self
+            .results
+            .iter()
+            .filter(|result| !result.passed)
+            .map(|result| result.name.as_str())
+            .collect();
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err(AppError::PreflightError(failed.join(", "))),
+        }
+    }
+
+    fn check<F>(&mut self, name: &str, check: F) -> bool
+    where
+        F: FnOnce() -> Result<String, String>,
+    {
+        let (passed, detail) = match check() {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+
+        self.results.push(CheckResult {
+            name: name.to_string(),
+            passed,
+            detail,
+        });
+
+        passed
+    }
+}
+
+impl fmt::Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .results
+            .iter()
+            .map(|result| result.name.len())
+            .max()
+            .unwrap_or(0);
+
+        writeln!(f, "Pre-flight checks")?;
+        for result in &self.results {
+            let mark = match result.passed {
+                true => "✔ ",
+                false => "✖ ",
+            };
+            writeln!(
+                f,
+                "  {} {:width$}  {}",
+                mark,
+                result.name,
+                result.detail,
+                width = width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the SSH user can run commands as the isolated user without a
+/// password. Forge only creates that user along with the site, so when it
+/// doesn't exist yet plain `sudo` is checked instead.
+fn sudo_check(runner: &dyn CommandRunner, user_name: &str) -> Result<String, String> {
+    let id = RemoteCommand::new("id").args(["-u", user_name]);
+    let exists = runner
+        .run(&id.to_string(), Stdin::Null, Stdout::Null)
+        .map_err(|e| e.to_string())?
+        .success();
+
+    let (sudo, detail) = match exists {
+        true => (
+            RemoteCommand::new("sudo").args(["-n", "-u", user_name, "true"]),
+            format!("can run commands as {}", user_name),
+        ),
+        false => (
+            RemoteCommand::new("sudo").args(["-n", "true"]),
+            format!("{} will be created, sudo works", user_name),
+        ),
+    };
+
+    remote::run_command(runner, &sudo.to_string())
+        .map(|_| detail)
+        .map_err(|_| format!("`{}` is not allowed without a password", sudo))
+}
+
+/// `tar`, the database client and whatever the plan's codec needs, `mktemp`
+/// for the database option file, plus the checksum tools when streaming and
+/// the listing tools when syncing or excluding.
+fn remote_tools<'a>(plan: &Plan, database_tool: &'a str) -> Vec<&'a str> {
+    let mut tools = vec!["tar", database_tool];
+    tools.extend(plan.compression.codec.remote_tool());
+    if plan.stream || plan.sync.is_some() {
+        tools.extend(["sha256sum", "mkfifo"]);
+    }
+    if plan.stream || plan.sync.is_some() || plan.restores_database() {
+        tools.push("mktemp");
+    }
+    if plan.sync.is_some() || !plan.excludes.is_empty() {
+        tools.push("find");
+    }
+    if plan.sync.is_some() {
+        tools.push("xargs");
+    }
+    tools
+}
+
+fn missing_remote_tools(runner: &dyn CommandRunner, tools: &[&str]) -> Result<String, String> {
+    let remote_command = tools
+        .iter()
+        .map(|tool| {
+            RemoteCommand::new("command")
+                .args(["-v", tool])
+                .raw("> /dev/null || echo")
+                .arg(tool)
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let missing = remote::run_command(runner, &remote_command).map_err(|e| e.to_string())?;
+    let missing: Vec<&str> = missing.split_whitespace().collect();
+
+    match missing.is_empty() {
+        true => Ok(tools.join(", ")),
+        false => Err(format!("missing {}", missing.join(", "))),
+    }
+}
+
+/// Size of the source site, used as an upper bound for the archives.
+fn source_size_kb(config: &FinalConfig, plan: &Plan) -> Result<u64, String> {
+    match &plan.source_site {
+        Some(source_site) => {
+            let output = remote::run_command(
+                config.runner(&source_site.host).as_ref(),
+                &RemoteCommand::new("du")
+                    .args(["-sk", &source_site.root_directory])
+                    .to_string(),
+            )
+            .map_err(|e| e.to_string())?;
+            parse_kb(output.split_whitespace().next())
+        }
+        None => local_size(plan).map(|bytes| bytes / 1024),
+    }
+}
+
+/// Bytes of the local files that will be archived, leaving out the excluded
+/// ones. Symlinks aren't followed, as in the archive.
+fn local_size(plan: &Plan) -> Result<u64, String> {
+    let excludes = Excludes::new(&plan.excludes).map_err(|e| e.to_string())?;
+    let (manifest, _) =
+        sync::local_manifest(Path::new(&plan.source_folder), CompareBy::Mtime, &excludes)
+            .map_err(|e| format!("Unable to size {}: {}", plan.source_folder, e))?;
+
+    Ok(backup::file_bytes(&manifest))
+}
+
+fn local_free_kb(temp_folder: &str) -> Result<u64, String> {
+    // The temp folder is created on demand, measure the closest existing parent.
+    let existing = Path::new(temp_folder)
+        .ancestors()
+        .find(|path| path.exists())
+        .unwrap_or(Path::new("/"));
+
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(existing)
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("Unable to run df: {}", e))?;
+
+    parse_df(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn remote_free_kb(runner: &dyn CommandRunner) -> Result<u64, String> {
+    let remote_command = RemoteCommand::new("df").arg("-Pk").raw("~");
+    let output =
+        remote::run_command(runner, &remote_command.to_string()).map_err(|e| e.to_string())?;
+    parse_df(&output)
+}
+
+/// Reads the available column from POSIX `df -Pk` output.
+fn parse_df(output: &str) -> Result<u64, String> {
+    parse_kb(
+        output
+            .lines()
+            .nth(1)
+            .and_then(|line| line.split_whitespace().nth(3)),
+    )
+}
+
+fn parse_kb(value: Option<&str>) -> Result<u64, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| "Unable to determine size".to_string())
+}
+
+fn enough_space(required_kb: u64, available_kb: u64) -> Result<String, String> {
+    let detail = format!(
+        "{} needed, {} available",
+        format_kb(required_kb),
+        format_kb(available_kb)
+    );
+
+    match available_kb >= required_kb {
+        true => Ok(detail),
+        false => Err(detail),
+    }
+}
+
+fn format_kb(kb: u64) -> String {
+    feedback::format_bytes(kb * 1024)
+}
@@ -0,0 +1,192 @@
+// Remote file access over SSH
+
+use std::io;
+
+use crate::{
+    database::DatabaseCredentials,
+    error::{AppError, AppResult},
+    runner::{CommandRunner, Stdin, Stdout},
+    shell::RemoteCommand,
+};
+
+/// Writes stdin to a new file only the SSH user can read, printing its path.
+pub const OPTION_FILE_COMMAND: &str =
+    "umask 077; f=$(mktemp) || exit 1; cat > \"$f\" && echo \"$f\" || { rm -f \"$f\"; exit 1; }";
+
+/// A folder on a server, `user_name` owns it when the site is isolated.
+#[derive(Clone, Copy)]
+pub struct RemoteFolder<'a> {
+    pub runner: &'a dyn CommandRunner,
+    pub user_name: Option<&'a str>,
+    pub path: &'a str,
+}
+
+/// Runs a command on a remote host without any interactive prompts, returning its output.
+pub fn run_command(runner: &dyn CommandRunner, remote_command: &str) -> AppResult<String> {
+    runner
+        .run_checked(remote_command, Stdin::Null, Stdout::Capture)
+        .map(|output| output.stdout_string())
+}
+
+pub fn path_exists(
+    runner: &dyn CommandRunner,
+    user_name: Option<&str>,
+    path: &str,
+) -> AppResult<bool> {
+    let remote_command = RemoteCommand::new("test")
+        .args(["-e", path])
+        .as_user(user_name);
+
+    let output = runner.run(&remote_command.to_string(), Stdin::Null, Stdout::Null)?;
+
+    // `test` exits with 1 when the path is missing, ssh itself exits with 255.
+    match output.code {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => Err(AppError::CommandError(
+            runner.program().into(),
+            io::Error::other(format!(
+                "Unable to check {} on {}: {}",
+                path,
+                runner.host(),
+                output.stderr.trim()
+            )),
+        )),
+    }
+}
+
+pub fn read_file(
+    runner: &dyn CommandRunner,
+    user_name: Option<&str>,
+    path: &str,
+) -> AppResult<String> {
+    let remote_command = RemoteCommand::new("cat").arg(path).as_user(user_name);
+
+    let output = runner.run(&remote_command.to_string(), Stdin::Null, Stdout::Capture)?;
+
+    if !output.success() {
+        return Err(AppError::CommandError(
+            runner.program().into(),
+            io::Error::other(format!("Unable to read {}: {}", path, output.stderr.trim())),
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| {
+        AppError::CommandError(
+            runner.program().into(),
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        )
+    })
+}
+
+/// Replaces the contents of an existing remote file, keeping its owner and mode.
+pub fn write_file(
+    runner: &dyn CommandRunner,
+    user_name: Option<&str>,
+    path: &str,
+    content: &str,
+) -> AppResult<()> {
+    let remote_command = RemoteCommand::new("tee")
+        .arg(path)
+        .raw("> /dev/null")
+        .as_user(user_name);
+
+    let output = runner.run(
+        &remote_command.to_string(),
+        Stdin::Bytes(content.as_bytes()),
+        Stdout::Null,
+    )?;
+
+    if !output.success() {
+        return Err(AppError::CommandError(
+            runner.program().into(),
+            io::Error::other(format!(
+                "Unable to write {}: {}",
+                path,
+                output.stderr.trim()
+            )),
+        ));
+    }
+
+    Ok(())
+}
+
+/// MySQL option file on a server for `--defaults-extra-file`, so database
+/// passwords never show up in a command line or process list there. The
+/// credentials are sent over stdin and the file is removed again when dropped.
+pub struct RemoteOptionFile<'a> {
+    runner: &'a dyn CommandRunner,
+    path: String,
+}
+
+impl<'a> RemoteOptionFile<'a> {
+    pub fn create(runner: &'a dyn CommandRunner, creds: &DatabaseCredentials) -> AppResult<Self> {
+        let options = creds.client_options();
+        let output = runner.run_checked(
+            OPTION_FILE_COMMAND,
+            Stdin::Bytes(options.as_bytes()),
+            Stdout::Capture,
+        )?;
+
+        let path = output.stdout_string().trim().to_string();
+        if path.is_empty() {
+            return Err(AppError::CommandError(
+                runner.program().into(),
+                io::Error::other(format!("No option file was created on {}", runner.host())),
+            ));
+        }
+
+        Ok(RemoteOptionFile { runner, path })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for RemoteOptionFile<'_> {
+    fn drop(&mut self) {
+        remove_file(self.runner, &self.path);
+    }
+}
+
+fn remove_file(runner: &dyn CommandRunner, path: &str) {
+    let _ = runner.run(
+        &RemoteCommand::new("rm").args(["-f", path]).to_string(),
+        Stdin::Null,
+        Stdout::Null,
+    );
+}
+
+/// A file uploaded to a temporary path on a server, removed again when dropped.
+pub struct RemoteUpload<'a> {
+    runner: &'a dyn CommandRunner,
+    path: String,
+}
+
+impl<'a> RemoteUpload<'a> {
+    pub fn create(runner: &'a dyn CommandRunner, input: Stdin) -> AppResult<Self> {
+        let output = runner.run_checked("mktemp", Stdin::Null, Stdout::Capture)?;
+        let path = output.stdout_string().trim().to_string();
+        if path.is_empty() {
+            return Err(AppError::CommandError(
+                runner.program().into(),
+                io::Error::other(format!("No upload file was created on {}", runner.host())),
+            ));
+        }
+
+        let upload = RemoteUpload { runner, path };
+        runner.upload(input, &upload.path)?;
+        Ok(upload)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for RemoteUpload<'_> {
+    fn drop(&mut self) {
+        remove_file(self.runner, &self.path);
+    }
+}
@@ -0,0 +1,86 @@
+// Rollback
+
+use std::sync::Arc;
+
+use chrono::Utc;
+use dialoguer::Confirm;
+
+use crate::{
+    config::FinalConfig,
+    error::AppResult,
+    feedback,
+    forge::ForgeClient,
+    journal::{Journal, Step},
+};
+
+/// Deletes the forge resources recorded in the journal, newest first, and
+/// resets the journal so a resumed run recreates them.
+pub fn rollback(client: &Arc<ForgeClient>, journal: &mut Journal) -> AppResult<()> {
+    feedback::clear_interrupt();
+    let server_id = journal.dest_server_id.clone();
+
+    if let Some(user_id) = journal.database_user_id {
+        let client = Arc::clone(client);
+        let server_id = server_id.clone();
+        feedback::show_spinner(
+            move || client.delete_user(&server_id, &user_id.to_string()),
+            "Deleting database user created by this run",
+        )?;
+        journal.database_user_id = None;
+        journal.save()?;
+    }
+
+    if let Some(database_id) = journal.database_id {
+        let client = Arc::clone(client);
+        let server_id = server_id.clone();
+        feedback::show_spinner(
+            move || client.delete_database(&server_id, &database_id.to_string()),
+            "Deleting database created by this run",
+        )?;
+        journal.database_id = None;
+        journal.db_password = None;
+        journal.save()?;
+    }
+
+    if let Some(site_id) = journal.site_id {
+        let client = Arc::clone(client);
+        let server_id = server_id.clone();
+        feedback::show_spinner(
+            move || client.delete_site(&server_id, &site_id.to_string()),
+            "Deleting site created by this run",
+        )?;
+        journal.site_id = None;
+        journal.web_directory = None;
+        journal.save()?;
+    }
+
+    // Local backups are still valid, everything on the destination has to be redone.
+    journal
+        .completed_steps
+        .retain(|step| matches!(step, Step::BackupDatabase | Step::BackupFiles));
+    journal.rolled_back_at = Some(Utc::now().to_rfc3339());
+    journal.save()
+}
+
+/// Decides whether a failed run should be rolled back, asking the user when
+/// `auto_rollback` has not been configured. When the question can't be asked,
+/// e.g. without a terminal, the resources are kept.
+pub fn should_rollback(config: &FinalConfig, journal: &Journal) -> bool {
+    if !journal.has_created_resources() {
+        return false;
+    }
+
+    match config.auto_rollback {
+        Some(auto_rollback) => auto_rollback,
+        None => Confirm::new()
+            .with_prompt("Delete the forge site and database created by this run?")
+            .interact()
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "Keeping the forge resources created by this run, couldn't ask about a rollback: {}",
+                    e
+                );
+                false
+            }),
+    }
+}
@@ -0,0 +1,383 @@
+// Command execution, locally or on a remote host
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{
+    error::{AppError, AppResult},
+    shell::RemoteCommand,
+    ssh::{self, SshOptions},
+};
+
+/// Where a command reads its standard input from.
+pub enum Stdin<'a> {
+    Null,
+    File(&'a Path),
+    Bytes(&'a [u8]),
+    Reader(&'a mut (dyn Read + Send)),
+}
+
+/// Where a command's standard output goes.
+pub enum Stdout<'a> {
+    Null,
+    Capture,
+    File(&'a Path),
+    Writer(&'a mut dyn Write),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandOutput {
+    /// Exit code, `None` when the command was killed by a signal.
+    pub code: Option<i32>,
+    /// Output of the command, only filled in for `Stdout::Capture`.
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+
+    pub fn stdout_string(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).to_string()
+    }
+}
+
+/// Runs shell command lines on one machine. Backups and restores go through
+/// this so they can be pointed at a fake, or at a local folder, in tests.
+pub trait CommandRunner: Send + Sync {
+    /// Machine the commands run on, for messages.
+    fn host(&self) -> &str;
+
+    /// Program reported when a command can't be run.
+    fn program(&self) -> &str;
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput>;
+
+    /// Like `run`, but a non-zero exit status is an error.
+    fn run_checked(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let output = self.run(command, stdin, stdout)?;
+
+        if !output.success() {
+            return Err(AppError::CommandError(
+                self.program().into(),
+                io::Error::other(match output.stderr.trim() {
+                    "" => format!("`{}` failed on {}", command, self.host()),
+                    stderr => stderr.to_string(),
+                }),
+            ));
+        }
+
+        Ok(output)
+    }
+
+    /// Writes `input` to `remote_path`, replacing the file if it exists.
+    fn upload(&self, input: Stdin, remote_path: &str) -> AppResult<()> {
+        let command = RemoteCommand::new("cat").raw(">").arg(remote_path);
+        self.run_checked(&command.to_string(), input, Stdout::Null)?;
+        Ok(())
+    }
+
+    /// Whether archives are better uploaded with `upload` than piped into the
+    /// command restoring them.
+    fn prefers_upload(&self) -> bool {
+        false
+    }
+}
+
+/// Runs commands on this machine through `sh`.
+pub struct LocalRunner;
+
+impl CommandRunner for LocalRunner {
+    fn host(&self) -> &str {
+        "localhost"
+    }
+
+    fn program(&self) -> &str {
+        "sh"
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let mut sh = Command::new("sh");
+        sh.arg("-c").arg(command);
+        execute(sh, self.program(), stdin, stdout)
+    }
+}
+
+/// Runs commands on a remote host over SSH, without any interactive prompts.
+pub struct RemoteRunner {
+    host: String,
+    ssh_args: Vec<String>,
+}
+
+impl RemoteRunner {
+    pub fn new(host: &str, options: &SshOptions) -> Self {
+        if options.multiplex {
+            // Left to `ssh` to report if it still can't be created.
+            let _ = ssh::create_control_dir();
+        }
+
+        RemoteRunner {
+            host: host.to_string(),
+            ssh_args: options.args(),
+        }
+    }
+}
+
+impl CommandRunner for RemoteRunner {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn program(&self) -> &str {
+        "ssh"
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let mut ssh = Command::new("ssh");
+        ssh.arg("-o")
+            .arg("BatchMode=yes")
+            .args(&self.ssh_args)
+            .arg(&self.host)
+            .arg(command);
+        execute(ssh, self.program(), stdin, stdout)
+    }
+}
+
+/// Stands in for a remote host by running its commands on this machine, from
+/// a folder that plays the part of the remote home directory.
+pub struct LocalAsRemoteRunner {
+    host: String,
+    root: PathBuf,
+}
+
+impl LocalAsRemoteRunner {
+    pub fn new(host: &str, root: &Path) -> Self {
+        LocalAsRemoteRunner {
+            host: host.to_string(),
+            root: root.to_path_buf(),
+        }
+    }
+}
+
+impl CommandRunner for LocalAsRemoteRunner {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn program(&self) -> &str {
+        "sh"
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        fs::create_dir_all(&self.root).map_err(|e| AppError::FileError(self.root.clone(), e))?;
+
+        let mut sh = Command::new("sh");
+        sh.arg("-c")
+            .arg(command)
+            .current_dir(&self.root)
+            .env("HOME", &self.root);
+        execute(sh, self.program(), stdin, stdout)
+    }
+}
+
+/// A command seen by a `RecordingRunner`.
+#[derive(Debug, Clone)]
+pub struct RecordedCommand {
+    pub command: String,
+    pub stdin: Vec<u8>,
+}
+
+/// Fake runner that records every command instead of running it. Commands
+/// succeed with no output unless a response has been set up for them.
+pub struct RecordingRunner {
+    host: String,
+    responses: Mutex<Vec<(String, CommandOutput)>>,
+    calls: Mutex<Vec<RecordedCommand>>,
+}
+
+impl RecordingRunner {
+    pub fn new(host: &str) -> Self {
+        RecordingRunner {
+            host: host.to_string(),
+            responses: Mutex::new(Vec::new()),
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Answers commands containing `pattern` with an exit code and output.
+    pub fn respond(&self, pattern: &str, code: i32, stdout: &str) {
+        self.responses.lock().unwrap().push((
+            pattern.to_string(),
+            CommandOutput {
+                code: Some(code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: String::new(),
+            },
+        ));
+    }
+
+    pub fn calls(&self) -> Vec<RecordedCommand> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn commands(&self) -> Vec<String> {
+        self.calls().into_iter().map(|call| call.command).collect()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn program(&self) -> &str {
+        "sh"
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let input = match stdin {
+            Stdin::Null => Vec::new(),
+            Stdin::File(path) => {
+                fs::read(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?
+            }
+            Stdin::Bytes(bytes) => bytes.to_vec(),
+            Stdin::Reader(reader) => {
+                let mut input = Vec::new();
+                reader
+                    .read_to_end(&mut input)
+                    .map_err(|e| AppError::CommandError(self.program().into(), e))?;
+                input
+            }
+        };
+        self.calls.lock().unwrap().push(RecordedCommand {
+            command: command.to_string(),
+            stdin: input,
+        });
+
+        let mut output = self
+            .responses
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(pattern, _)| command.contains(pattern.as_str()))
+            .map(|(_, output)| output.clone())
+            .unwrap_or(CommandOutput {
+                code: Some(0),
+                ..Default::default()
+            });
+
+        match stdout {
+            Stdout::Null => output.stdout.clear(),
+            Stdout::Capture => {}
+            Stdout::File(path) => {
+                fs::write(path, &output.stdout)
+                    .map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+                output.stdout.clear();
+            }
+            Stdout::Writer(writer) => {
+                writer
+                    .write_all(&output.stdout)
+                    .map_err(|e| AppError::CommandError(self.program().into(), e))?;
+                output.stdout.clear();
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+fn execute(
+    mut command: Command,
+    program: &str,
+    stdin: Stdin,
+    stdout: Stdout,
+) -> AppResult<CommandOutput> {
+    match stdin {
+        Stdin::Null => command.stdin(Stdio::null()),
+        Stdin::File(path) => command.stdin(Stdio::from(
+            File::open(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?,
+        )),
+        Stdin::Bytes(_) | Stdin::Reader(_) => command.stdin(Stdio::piped()),
+    };
+
+    let mut forward_to = None;
+    match stdout {
+        Stdout::Null => command.stdout(Stdio::null()),
+        Stdout::Capture => command.stdout(Stdio::piped()),
+        Stdout::File(path) => command.stdout(Stdio::from(
+            File::create(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?,
+        )),
+        Stdout::Writer(writer) => {
+            forward_to = Some(writer);
+            command.stdout(Stdio::piped())
+        }
+    };
+
+    let mut child = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::CommandError(program.into(), e))?;
+
+    let command_error = |e| AppError::CommandError(program.into(), e);
+    let mut child_stdin = child.stdin.take();
+    let child_stdout = child.stdout.take();
+    let mut child_stderr = child.stderr.take();
+
+    // Feed stdin and drain stderr on the side, so neither pipe can fill up and
+    // stall the command while its output is being read.
+    let (captured, stderr) = thread::scope(|scope| {
+        let writer = scope.spawn(move || match (stdin, child_stdin.as_mut()) {
+            (Stdin::Bytes(bytes), Some(child_stdin)) => match child_stdin.write_all(bytes) {
+                // The command may finish without reading all of its input.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            },
+            (Stdin::Reader(reader), Some(child_stdin)) => match io::copy(reader, child_stdin) {
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result.map(|_| ()),
+            },
+            _ => Ok(()),
+        });
+        let reader = scope.spawn(move || {
+            let mut stderr = String::new();
+            if let Some(child_stderr) = child_stderr.as_mut() {
+                let _ = child_stderr.read_to_string(&mut stderr);
+            }
+            stderr
+        });
+
+        // Owned here so an early return closes the pipe and the command can't
+        // block on it while the threads above are joined.
+        let mut child_stdout = child_stdout;
+        let mut captured = Vec::new();
+        match (child_stdout.as_mut(), forward_to) {
+            (Some(child_stdout), Some(writer)) => {
+                io::copy(child_stdout, writer)?;
+            }
+            (Some(child_stdout), None) => {
+                child_stdout.read_to_end(&mut captured)?;
+            }
+            _ => {}
+        }
+        writer.join().unwrap_or(Ok(()))?;
+
+        Ok::<_, io::Error>((captured, reader.join().unwrap_or_default()))
+    })
+    .map_err(command_error)?;
+
+    let status = child.wait().map_err(command_error)?;
+
+    Ok(CommandOutput {
+        code: status.code(),
+        stdout: captured,
+        stderr,
+    })
+}
@@ -1,11 +1,24 @@
 use std::{path::PathBuf, process::Command};
 
-use crate::error::{AppError, AppResult};
+use crate::{
+    config::FinalConfig,
+    error::{AppError, AppResult},
+    ssh::SshTransport,
+};
 
-pub fn check_prerequisites() -> AppResult<()> {
-    let commands = ["cat", "ssh", "gzip", "tar", "mariadb-dump"];
+/// Checks the local tools are installed, a source on another forge server is
+/// archived over SSH and doesn't need `mariadb-dump` locally. The `ssh` binary
+/// is only needed by the OpenSSH transport.
+pub fn check_prerequisites(config: &FinalConfig) -> AppResult<()> {
+    let mut commands = vec![];
+    if config.local_remote_root.is_none() && config.ssh_options.transport == SshTransport::OpenSsh {
+        commands.push("ssh");
+    }
+    if config.source_site.is_none() {
+        commands.push("mariadb-dump");
+    }
 
-    for cmd in &commands {
+    for cmd in commands {
         let output = Command::new("which")
             .arg(cmd)
             .output()
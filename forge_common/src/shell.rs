@@ -0,0 +1,116 @@
+// Command lines for the remote POSIX shell
+
+use std::{borrow::Cow, fmt};
+
+use crate::error::{AppError, AppResult};
+
+/// Quotes `arg` as a single word for a POSIX shell. Plain words are left as
+/// they are so the commands stay readable.
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:@,+%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return Cow::Borrowed(arg);
+    }
+
+    Cow::Owned(format!("'{}'", arg.replace('\'', "'\\''")))
+}
+
+/// Pipes `producer` into `consumer`, failing when either of them does. A plain
+/// `|` only reports the consumer's exit status, so a failed dump piped through
+/// `gzip` would look like a success. The producer's status comes back on fd 3
+/// while the consumer writes to fd 4, the real standard output.
+pub fn pipe(producer: &str, consumer: &str) -> String {
+    format!(
+        concat!(
+            "( {{ status=$({{ {{ ({}) 3>&- 4>&-; echo $? >&3; }} ",
+            "| ({}) >&4 3>&- 4>&-; }} 3>&1) || exit; exit \"$status\"; }} 4>&1 )"
+        ),
+        producer, consumer
+    )
+}
+
+/// A command for a remote shell, built an argument at a time so every value
+/// ends up quoted.
+#[derive(Debug, Clone)]
+pub struct RemoteCommand {
+    words: Vec<String>,
+}
+
+impl RemoteCommand {
+    pub fn new(program: &str) -> Self {
+        RemoteCommand {
+            words: vec![quote(program).into_owned()],
+        }
+    }
+
+    /// Runs the command as `user_name` through `sudo` when the site is isolated.
+    pub fn as_user(mut self, user_name: Option<&str>) -> Self {
+        if let Some(user_name) = user_name {
+            let sudo = ["sudo".into(), "-u".into(), quote(user_name).into_owned()];
+            self.words.splice(0..0, sudo);
+        }
+        self
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.words.push(quote(arg).into_owned());
+        self
+    }
+
+    pub fn args<'a>(self, args: impl IntoIterator<Item = &'a str>) -> Self {
+        args.into_iter().fold(self, RemoteCommand::arg)
+    }
+
+    /// Appends shell syntax, such as a redirection or an option made up of
+    /// fixed values, without quoting it. Never pass user input here.
+    pub fn raw(mut self, text: &str) -> Self {
+        self.words.push(text.to_string());
+        self
+    }
+}
+
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.words.join(" "))
+    }
+}
+
+impl From<RemoteCommand> for String {
+    fn from(command: RemoteCommand) -> Self {
+        command.to_string()
+    }
+}
+
+/// Checks a system user name the way Forge does for isolated sites: lower
+/// case letters, digits, `-` and `_`, starting with a letter or `_`.
+pub fn validate_user_name(user_name: &str) -> AppResult<()> {
+    let valid = user_name.len() <= 32
+        && user_name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && user_name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+    match valid {
+        true => Ok(()),
+        false => Err(AppError::ConfigError(format!(
+            "Invalid user name `{}`: use up to 32 lower case letters, digits, `-` and `_`, starting with a letter or `_`",
+            user_name
+        ))),
+    }
+}
+
+/// Checks a database or database user name the way Forge does: letters,
+/// digits and `_`, at most 64 characters.
+pub fn validate_database_name(name: &str) -> AppResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    match valid {
+        true => Ok(()),
+        false => Err(AppError::ConfigError(format!(
+            "Invalid database name `{}`: use up to 64 letters, digits and `_`",
+            name
+        ))),
+    }
+}
@@ -1,29 +1,74 @@
-use std::{fs, path::Path};
-
 use crate::{
     database::{DatabaseConfigProvider, DatabaseCredentials},
     error::{AppError, AppResult},
 };
 
-#[derive(Debug)]
+const CONFIG_PATH: &str = ".env";
+
+/// Caches, compiled views and logs are rebuilt on the destination, and
+/// `node_modules` is only needed to build the assets.
+const DEFAULT_EXCLUDES: &[&str] = &[
+    "/node_modules/",
+    "/storage/framework/cache/data/",
+    "/storage/framework/views/*.php",
+    "/storage/logs/*.log",
+    "/bootstrap/cache/*.php",
+];
+
+#[derive(Debug, Clone)]
 pub struct LaravelSite;
 
-impl DatabaseConfigProvider for LaravelSite {
-    fn get_database_credentials(&self, root_path: &Path) -> AppResult<Option<DatabaseCredentials>> {
-        let config_path = root_path.join(".env");
-        let config_content = fs::read_to_string(&config_path)
-            .map_err(|e| AppError::FileError(config_path.clone(), e))?;
+impl LaravelSite {
+    pub fn default_excludes(&self) -> &'static [&'static str] {
+        DEFAULT_EXCLUDES
+    }
+}
 
-        let username = extract_env_value(&config_content, "DB_USERNAME")?;
-        let password = extract_env_value(&config_content, "DB_PASSWORD")?;
-        let database = extract_env_value(&config_content, "DB_DATABASE")?;
+impl DatabaseConfigProvider for LaravelSite {
+    fn parse_database_credentials(&self, content: &str) -> AppResult<Option<DatabaseCredentials>> {
+        let username = extract_env_value(content, "DB_USERNAME")?;
+        let password = extract_env_value(content, "DB_PASSWORD")?;
+        let database = extract_env_value(content, "DB_DATABASE")?;
+        let port = match optional_env_value(content, "DB_PORT") {
+            Some(port) => Some(
+                port.parse()
+                    .map_err(|_| AppError::CredentialParseError("DB_PORT".into()))?,
+            ),
+            None => None,
+        };
 
         Ok(Some(DatabaseCredentials {
             username,
             password,
             database,
+            host: optional_env_value(content, "DB_HOST"),
+            port,
+            socket: optional_env_value(content, "DB_SOCKET"),
         }))
     }
+
+    fn config_path(&self) -> Option<&'static str> {
+        Some(CONFIG_PATH)
+    }
+
+    fn rewrite_config(
+        &self,
+        content: &str,
+        creds: &DatabaseCredentials,
+        app_host: Option<&str>,
+    ) -> AppResult<String> {
+        let mut content = set_env_value(content, "DB_DATABASE", &creds.database);
+        content = set_env_value(&content, "DB_USERNAME", &creds.username);
+        content = set_env_value(&content, "DB_PASSWORD", &creds.password);
+
+        if let Some(host) = app_host {
+            if let Some(app_url) = env_value(&content, "APP_URL") {
+                content = set_env_value(&content, "APP_URL", &replace_url_host(&app_url, host));
+            }
+        }
+
+        Ok(content)
+    }
 }
 
 fn extract_env_value(content: &str, key: &str) -> AppResult<String> {
@@ -40,3 +85,246 @@ fn extract_env_value(content: &str, key: &str) -> AppResult<String> {
 
     Err(AppError::CredentialParseError(key.to_string()))
 }
+
+/// A setting that may be missing or left empty, like `DB_SOCKET=`.
+fn optional_env_value(content: &str, key: &str) -> Option<String> {
+    env_value(content, key).filter(|value| !value.is_empty())
+}
+
+/// Location of a key's value within a `.env` line.
+struct EnvValue {
+    start: usize,
+    end: usize,
+    quote: Option<char>,
+}
+
+fn find_env_value(line: &str, key: &str) -> Option<EnvValue> {
+    let trimmed = line.trim_start();
+    let after_key = trimmed.strip_prefix(key)?;
+    let value = after_key.trim_start().strip_prefix('=')?.trim_start();
+    let start = line.len() - value.len();
+
+    match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let end = closing_quote(value, quote)
+                .map(|i| i + 1)
+                .unwrap_or(value.len());
+            Some(EnvValue {
+                start,
+                end: start + end,
+                quote: Some(quote),
+            })
+        }
+        // An unquoted value ends where an inline comment begins.
+        _ => {
+            let end = value.find(" #").unwrap_or(value.trim_end().len());
+            Some(EnvValue {
+                start,
+                end: start + end,
+                quote: None,
+            })
+        }
+    }
+}
+
+/// Byte offset of the quote closing a value that starts with `quote`,
+/// skipping backslash escapes inside double quotes.
+fn closing_quote(value: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+
+    for (index, c) in value.char_indices().skip(1) {
+        match c {
+            '\\' if quote == '"' && !escaped => escaped = true,
+            c if c == quote && !escaped => return Some(index),
+            _ => escaped = false,
+        }
+    }
+
+    None
+}
+
+fn env_value(content: &str, key: &str) -> Option<String> {
+    content.lines().find_map(|line| {
+        let found = find_env_value(line, key)?;
+        let raw = &line[found.start..found.end];
+        Some(match found.quote {
+            Some(_) if raw.len() >= 2 => raw[1..raw.len() - 1].to_string(),
+            _ => raw.trim().to_string(),
+        })
+    })
+}
+
+/// Sets `key` to `value`, keeping the existing quoting, comments and line
+/// order. The key is appended when it is not present yet.
+fn set_env_value(content: &str, key: &str, value: &str) -> String {
+    let mut found = false;
+    let mut output = String::with_capacity(content.len());
+
+    for line in content.split_inclusive('\n') {
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+
+        match find_env_value(text, key) {
+            Some(existing) if !found => {
+                found = true;
+                output.push_str(&text[..existing.start]);
+                output.push_str(&quote_env_value(value, existing.quote));
+                output.push_str(&text[existing.end..]);
+            }
+            _ => output.push_str(text),
+        }
+        output.push_str(newline);
+    }
+
+    if !found {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&format!("{}={}\n", key, quote_env_value(value, None)));
+    }
+
+    output
+}
+
+fn quote_env_value(value: &str, quote: Option<char>) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '#' | '"' | '\'' | '$' | '\\'));
+
+    match quote {
+        Some('\'') if !value.contains('\'') => format!("'{}'", value),
+        Some(_) => format!("\"{}\"", escape_double_quoted(value)),
+        None if needs_quotes => format!("\"{}\"", escape_double_quoted(value)),
+        None => value.to_string(),
+    }
+}
+
+fn escape_double_quoted(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('$', "\\$")
+}
+
+fn replace_url_host(url: &str, host: &str) -> String {
+    match url.find("://") {
+        Some(index) => {
+            let rest = &url[index + 3..];
+            let end = rest.find(['/', ':', '?', '#']).unwrap_or(rest.len());
+            format!("{}{}{}", &url[..index + 3], host, &rest[end..])
+        }
+        None => format!("https://{}", host),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_env_value_keeps_single_quotes() {
+        let content = "APP_NAME=Shop\nDB_PASSWORD='old'\n";
+
+        assert_eq!(
+            set_env_value(content, "DB_PASSWORD", "new"),
+            "APP_NAME=Shop\nDB_PASSWORD='new'\n"
+        );
+    }
+
+    #[test]
+    fn set_env_value_keeps_double_quotes() {
+        let content = "DB_PASSWORD=\"old secret\"\n";
+
+        assert_eq!(
+            set_env_value(content, "DB_PASSWORD", "new"),
+            "DB_PASSWORD=\"new\"\n"
+        );
+    }
+
+    #[test]
+    fn set_env_value_keeps_inline_comments() {
+        let content = "DB_USERNAME=old # set by forge\nDB_PASSWORD=\"old\" # rotated\n";
+
+        let content = set_env_value(content, "DB_USERNAME", "new");
+        let content = set_env_value(&content, "DB_PASSWORD", "secret");
+
+        assert_eq!(
+            content,
+            "DB_USERNAME=new # set by forge\nDB_PASSWORD=\"secret\" # rotated\n"
+        );
+    }
+
+    #[test]
+    fn set_env_value_escapes_special_characters() {
+        let content = "DB_PASSWORD=old\n";
+
+        assert_eq!(
+            set_env_value(content, "DB_PASSWORD", r#"a$b"c\d"#),
+            "DB_PASSWORD=\"a\\$b\\\"c\\\\d\"\n"
+        );
+    }
+
+    #[test]
+    fn set_env_value_replaces_escaped_double_quoted_values() {
+        let content = "DB_PASSWORD=\"say \\\"hi\\\"\"\nDB_HOST=127.0.0.1\n";
+
+        assert_eq!(
+            set_env_value(content, "DB_PASSWORD", "new"),
+            "DB_PASSWORD=\"new\"\nDB_HOST=127.0.0.1\n"
+        );
+    }
+
+    #[test]
+    fn set_env_value_appends_a_missing_key() {
+        assert_eq!(
+            set_env_value("APP_NAME=Shop", "DB_SOCKET", "/run/mysqld.sock"),
+            "APP_NAME=Shop\nDB_SOCKET=/run/mysqld.sock\n"
+        );
+        assert_eq!(set_env_value("", "DB_PASSWORD", ""), "DB_PASSWORD=\"\"\n");
+    }
+
+    #[test]
+    fn set_env_value_only_matches_the_whole_key() {
+        let content = "DB_PASSWORD_OLD=keep\nDB_PASSWORD=old\n";
+
+        assert_eq!(
+            set_env_value(content, "DB_PASSWORD", "new"),
+            "DB_PASSWORD_OLD=keep\nDB_PASSWORD=new\n"
+        );
+    }
+
+    #[test]
+    fn quote_env_value_only_quotes_when_needed() {
+        assert_eq!(quote_env_value("plain", None), "plain");
+        assert_eq!(quote_env_value("two words", None), "\"two words\"");
+        assert_eq!(quote_env_value("a#b", None), "\"a#b\"");
+        assert_eq!(quote_env_value("", None), "\"\"");
+    }
+
+    #[test]
+    fn quote_env_value_keeps_the_existing_quotes() {
+        assert_eq!(quote_env_value("a$b", Some('\'')), "'a$b'");
+        assert_eq!(quote_env_value("a$b", Some('"')), "\"a\\$b\"");
+        // A single quote can't be escaped inside single quotes.
+        assert_eq!(quote_env_value("it's", Some('\'')), "\"it's\"");
+    }
+
+    #[test]
+    fn replace_url_host_keeps_the_port_and_path() {
+        assert_eq!(
+            replace_url_host("https://old.example.com:8443/shop?ref=1", "new.example.com"),
+            "https://new.example.com:8443/shop?ref=1"
+        );
+        assert_eq!(
+            replace_url_host("http://old.example.com", "new.example.com"),
+            "http://new.example.com"
+        );
+        assert_eq!(
+            replace_url_host("old.example.com", "new.example.com"),
+            "https://new.example.com"
+        );
+    }
+}
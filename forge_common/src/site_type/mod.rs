@@ -2,7 +2,10 @@ mod laravel;
 mod static_html;
 mod wordpress;
 
-use std::path::Path;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
 pub use laravel::LaravelSite;
 pub use static_html::StaticHtmlSite;
@@ -11,9 +14,11 @@ pub use wordpress::WordPressSite;
 use crate::{
     database::{DatabaseConfigProvider, DatabaseCredentials},
     error::{AppError, AppResult},
+    remote,
+    runner::CommandRunner,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SiteType {
     Wordpress(WordPressSite),
     Laravel(LaravelSite),
@@ -31,16 +36,97 @@ impl SiteType {
             SiteType::StaticHtml(site) => site.get_database_credentials(root_path),
         }
     }
+
+    /// Reads the database settings from the site's config file on a remote host.
+    pub fn get_remote_database_credentials(
+        &self,
+        runner: &dyn CommandRunner,
+        user_name: Option<&str>,
+        root_directory: &str,
+    ) -> AppResult<Option<DatabaseCredentials>> {
+        let Some(config_path) = self.config_path() else {
+            return Ok(None);
+        };
+
+        let remote_path = format!("{}/{}", root_directory.trim_end_matches('/'), config_path);
+        let content = remote::read_file(runner, user_name, &remote_path)?;
+
+        match self {
+            SiteType::Wordpress(site) => site.parse_database_credentials(&content),
+            SiteType::Laravel(site) => site.parse_database_credentials(&content),
+            SiteType::StaticHtml(site) => site.parse_database_credentials(&content),
+        }
+    }
+
+    pub fn config_path(&self) -> Option<&'static str> {
+        match self {
+            SiteType::Wordpress(site) => site.config_path(),
+            SiteType::Laravel(site) => site.config_path(),
+            SiteType::StaticHtml(site) => site.config_path(),
+        }
+    }
+
+    /// Exclude patterns that are safe to leave out for this kind of site.
+    pub fn default_excludes(&self) -> &'static [&'static str] {
+        match self {
+            SiteType::Wordpress(site) => site.default_excludes(),
+            SiteType::Laravel(site) => site.default_excludes(),
+            SiteType::StaticHtml(site) => site.default_excludes(),
+        }
+    }
+
+    pub fn rewrite_config(
+        &self,
+        content: &str,
+        creds: &DatabaseCredentials,
+        app_host: Option<&str>,
+    ) -> AppResult<String> {
+        match self {
+            SiteType::Wordpress(site) => site.rewrite_config(content, creds, app_host),
+            SiteType::Laravel(site) => site.rewrite_config(content, creds, app_host),
+            SiteType::StaticHtml(site) => site.rewrite_config(content, creds, app_host),
+        }
+    }
+}
+
+impl fmt::Display for SiteType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SiteType::Wordpress(_) => "WordPress",
+            SiteType::Laravel(_) => "Laravel",
+            SiteType::StaticHtml(_) => "Static HTML",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 pub fn detect_site_type(root_path: &Path) -> AppResult<SiteType> {
-    if root_path.join("public/wp-config.php").exists() {
-        Ok(SiteType::Wordpress(WordPressSite))
-    } else if root_path.join(".env").exists() && root_path.join("artisan").exists() {
-        Ok(SiteType::Laravel(LaravelSite))
-    } else if root_path.join("index.html").exists() {
-        Ok(SiteType::StaticHtml(StaticHtmlSite))
+    detect(|file| Ok(root_path.join(file).exists()))?
+        .ok_or_else(|| AppError::UnknownSiteType(root_path.to_path_buf()))
+}
+
+pub fn detect_remote_site_type(
+    runner: &dyn CommandRunner,
+    user_name: Option<&str>,
+    root_directory: &str,
+) -> AppResult<SiteType> {
+    let root_directory = root_directory.trim_end_matches('/');
+
+    detect(|file| remote::path_exists(runner, user_name, &format!("{}/{}", root_directory, file)))?
+        .ok_or_else(|| AppError::UnknownSiteType(PathBuf::from(root_directory)))
+}
+
+fn detect<F>(exists: F) -> AppResult<Option<SiteType>>
+where
+    F: Fn(&str) -> AppResult<bool>,
+{
+    if exists("public/wp-config.php")? {
+        Ok(Some(SiteType::Wordpress(WordPressSite)))
+    } else if exists(".env")? && exists("artisan")? {
+        Ok(Some(SiteType::Laravel(LaravelSite)))
+    } else if exists("index.html")? {
+        Ok(Some(SiteType::StaticHtml(StaticHtmlSite)))
     } else {
-        Err(AppError::UnknownSiteType(root_path.to_path_buf()))
+        Ok(None)
     }
 }
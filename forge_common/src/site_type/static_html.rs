@@ -1,18 +1,32 @@
-use std::path::Path;
-
 use crate::{
     database::{DatabaseConfigProvider, DatabaseCredentials},
     error::AppResult,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct StaticHtmlSite;
 
+impl StaticHtmlSite {
+    pub fn default_excludes(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
 impl DatabaseConfigProvider for StaticHtmlSite {
-    fn get_database_credentials(
-        &self,
-        _root_path: &Path,
-    ) -> AppResult<Option<DatabaseCredentials>> {
+    fn config_path(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn parse_database_credentials(&self, _content: &str) -> AppResult<Option<DatabaseCredentials>> {
         Ok(None)
     }
+
+    fn rewrite_config(
+        &self,
+        content: &str,
+        _creds: &DatabaseCredentials,
+        _app_host: Option<&str>,
+    ) -> AppResult<String> {
+        Ok(content.to_string())
+    }
 }
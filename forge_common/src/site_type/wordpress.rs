@@ -1,29 +1,58 @@
-use std::{fs, path::Path};
+use regex::Captures;
 
 use crate::{
     database::{DatabaseConfigProvider, DatabaseCredentials},
     error::{AppError, AppResult},
 };
 
-#[derive(Debug)]
+const CONFIG_PATH: &str = "public/wp-config.php";
+
+/// Page caches and the scratch folder for updates.
+const DEFAULT_EXCLUDES: &[&str] = &["/public/wp-content/cache/", "/public/wp-content/upgrade/"];
+
+#[derive(Debug, Clone)]
 pub struct WordPressSite;
 
-impl DatabaseConfigProvider for WordPressSite {
-    fn get_database_credentials(&self, root_path: &Path) -> AppResult<Option<DatabaseCredentials>> {
-        let config_path = root_path.join("public/wp-config.php");
-        let config_content = fs::read_to_string(&config_path)
-            .map_err(|e| AppError::FileError(config_path.clone(), e))?;
+impl WordPressSite {
+    pub fn default_excludes(&self) -> &'static [&'static str] {
+        DEFAULT_EXCLUDES
+    }
+}
 
-        let username = extract_value(&config_content, "DB_USER")?;
-        let password = extract_value(&config_content, "DB_PASSWORD")?;
-        let database = extract_value(&config_content, "DB_NAME")?;
+impl DatabaseConfigProvider for WordPressSite {
+    fn parse_database_credentials(&self, content: &str) -> AppResult<Option<DatabaseCredentials>> {
+        let username = extract_value(content, "DB_USER")?;
+        let password = extract_value(content, "DB_PASSWORD")?;
+        let database = extract_value(content, "DB_NAME")?;
+        let (host, port, socket) = match extract_value(content, "DB_HOST") {
+            Ok(db_host) => split_db_host(&db_host)?,
+            Err(_) => (None, None, None),
+        };
 
         Ok(Some(DatabaseCredentials {
             username,
             password,
             database,
+            host,
+            port,
+            socket,
         }))
     }
+
+    fn config_path(&self) -> Option<&'static str> {
+        Some(CONFIG_PATH)
+    }
+
+    fn rewrite_config(
+        &self,
+        content: &str,
+        creds: &DatabaseCredentials,
+        _app_host: Option<&str>,
+    ) -> AppResult<String> {
+        let content = set_define(content, "DB_NAME", &creds.database)?;
+        let content = set_define(&content, "DB_USER", &creds.username)?;
+        set_define(&content, "DB_PASSWORD", &creds.password)
+    }
 }
 
 fn extract_value(content: &str, key: &str) -> AppResult<String> {
@@ -34,3 +63,127 @@ fn extract_value(content: &str, key: &str) -> AppResult<String> {
         .ok_or(AppError::CredentialParseError(key.to_string()))?;
     Ok(captures[1].to_string())
 }
+
+/// WordPress puts the port or socket after the host, e.g. `localhost:3307` or
+/// `localhost:/var/run/mysqld/mysqld.sock`.
+fn split_db_host(db_host: &str) -> AppResult<(Option<String>, Option<u16>, Option<String>)> {
+    let (host, rest) = match db_host.split_once(':') {
+        Some((host, rest)) => (host, Some(rest)),
+        None => (db_host, None),
+    };
+    let host = Some(host.to_string()).filter(|host| !host.is_empty());
+
+    match rest {
+        Some(socket) if socket.starts_with('/') => Ok((host, None, Some(socket.to_string()))),
+        Some(port) => {
+            let port = port
+                .parse()
+                .map_err(|_| AppError::CredentialParseError("DB_HOST".into()))?;
+            Ok((host, Some(port), None))
+        }
+        None => Ok((host, None, None)),
+    }
+}
+
+/// Replaces the value of a `define('KEY', 'value')` call, keeping its quotes
+/// and spacing. The value runs to the quote it was opened with, skipping
+/// backslash escapes, so it may contain the other kind of quote.
+fn set_define(content: &str, key: &str, value: &str) -> AppResult<String> {
+    let pattern = format!(
+        r#"(define\(\s*['"]{}['"]\s*,\s*)(?:'((?:[^'\\]|\\.)*)'|"((?:[^"\\]|\\.)*)")(\s*\))"#,
+        regex::escape(key)
+    );
+    let re = regex::Regex::new(&pattern).map_err(|e| {
+        AppError::RegexParseError(format!("Failed to compile regex for key '{}': {}", key, e))
+    })?;
+
+    if !re.is_match(content) {
+        return Err(AppError::CredentialParseError(key.to_string()));
+    }
+
+    let replaced = re.replacen(content, 1, |captures: &Captures| {
+        let (quote, escaped) = match captures.get(2) {
+            Some(_) => ('\'', value.replace('\\', "\\\\").replace('\'', "\\'")),
+            None => (
+                '"',
+                value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('$', "\\$"),
+            ),
+        };
+        format!(
+            "{}{}{}{}{}",
+            &captures[1], quote, escaped, quote, &captures[4]
+        )
+    });
+
+    Ok(replaced.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_define_keeps_single_quotes() {
+        let content = "define( 'DB_NAME', 'old_db' );\n";
+
+        assert_eq!(
+            set_define(content, "DB_NAME", "new_db").unwrap(),
+            "define( 'DB_NAME', 'new_db' );\n"
+        );
+    }
+
+    #[test]
+    fn set_define_keeps_double_quotes() {
+        let content = "define(\"DB_NAME\", \"old_db\");\n";
+
+        assert_eq!(
+            set_define(content, "DB_NAME", "new_db").unwrap(),
+            "define(\"DB_NAME\", \"new_db\");\n"
+        );
+    }
+
+    #[test]
+    fn set_define_replaces_values_holding_the_other_quote() {
+        let content = "define('DB_PASSWORD', 'pa\"ss');\ndefine('DB_HOST', 'localhost');\n";
+
+        assert_eq!(
+            set_define(content, "DB_PASSWORD", "secret").unwrap(),
+            "define('DB_PASSWORD', 'secret');\ndefine('DB_HOST', 'localhost');\n"
+        );
+    }
+
+    #[test]
+    fn set_define_replaces_values_with_escaped_quotes() {
+        let content =
+            "define('DB_PASSWORD', 'it\\'s');\ndefine(\"DB_USER\", \"say \\\"hi\\\"\");\n";
+
+        let content = set_define(content, "DB_PASSWORD", "new").unwrap();
+        let content = set_define(&content, "DB_USER", "wp").unwrap();
+
+        assert_eq!(
+            content,
+            "define('DB_PASSWORD', 'new');\ndefine(\"DB_USER\", \"wp\");\n"
+        );
+    }
+
+    #[test]
+    fn set_define_escapes_the_new_value_for_its_quotes() {
+        let single = set_define("define('DB_PASSWORD', 'old');", "DB_PASSWORD", r"it's a\b");
+        let double = set_define(
+            "define('DB_PASSWORD', \"old\");",
+            "DB_PASSWORD",
+            r#"$a"b\c"#,
+        );
+
+        assert_eq!(single.unwrap(), r"define('DB_PASSWORD', 'it\'s a\\b');");
+        assert_eq!(double.unwrap(), r#"define('DB_PASSWORD', "\$a\"b\\c");"#);
+    }
+
+    #[test]
+    fn set_define_needs_the_key() {
+        assert!(set_define("define('DB_USER', 'wp');", "DB_NAME", "new").is_err());
+    }
+}
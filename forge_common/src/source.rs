@@ -0,0 +1,64 @@
+// Source site on a forge server
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppResult, forge::ForgeClient, remote::RemoteFolder};
+
+/// Where the site files are copied from.
+#[derive(Clone, Copy)]
+pub enum SourceFolder<'a> {
+    Local(&'a str),
+    Remote(RemoteFolder<'a>),
+}
+
+/// A site being migrated straight off another forge server, looked up by its
+/// forge ids instead of a local folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceSite {
+    pub server_id: String,
+    pub site_id: String,
+    pub host: String,
+    pub name: String,
+    pub root_directory: String,
+    pub web_directory: String,
+    pub project_type: String,
+    pub php_version: String,
+    pub aliases: Vec<String>,
+    pub isolated: bool,
+    pub username: String,
+}
+
+impl SourceSite {
+    /// Looks the site up, it is reached over SSH as `ssh_user`.
+    pub fn resolve(
+        client: &ForgeClient,
+        server_id: &str,
+        site_id: &str,
+        ssh_user: &str,
+    ) -> AppResult<Self> {
+        let site = client.get_site(server_id, site_id)?.site;
+        let server = client.get_server(server_id)?.server;
+
+        Ok(SourceSite {
+            server_id: server_id.to_string(),
+            site_id: site_id.to_string(),
+            host: server.ssh_host(ssh_user),
+            root_directory: site.root_directory(),
+            name: site.name,
+            web_directory: site.directory,
+            project_type: site.project_type,
+            php_version: site.php_version,
+            aliases: site.aliases,
+            isolated: site.isolated.unwrap_or(false),
+            username: site.username,
+        })
+    }
+
+    /// User that owns the site's files, when it differs from the `forge` user.
+    pub fn isolated_user(&self) -> Option<&str> {
+        match self.isolated {
+            true => Some(&self.username),
+            false => None,
+        }
+    }
+}
@@ -0,0 +1,211 @@
+// SSH connection settings
+
+use std::{fmt, fs::DirBuilder, io, os::unix::fs::DirBuilderExt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+
+#[cfg(feature = "native-ssh")]
+pub mod native;
+
+/// Where multiplexed connections keep their sockets, `%C` is a hash of the
+/// host, port and user so every server gets its own.
+const CONTROL_PATH: &str = "~/.ssh/forge-migrate-%C";
+
+/// Creates `~/.ssh` for the control sockets when it doesn't exist yet, `ssh`
+/// won't and fails to connect without it.
+pub fn create_control_dir() -> io::Result<()> {
+    match dirs::home_dir() {
+        Some(home) => DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(home.join(".ssh")),
+        None => Ok(()),
+    }
+}
+
+/// How long, in seconds, a shared connection stays open after the last call.
+const CONTROL_PERSIST: u32 = 60;
+
+/// What `ssh` does with a host key it doesn't know yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Only connect to hosts already in `known_hosts`.
+    Yes,
+    /// Add new hosts, but refuse a changed key.
+    AcceptNew,
+    /// Connect whatever the key, for throwaway servers only.
+    No,
+}
+
+impl FromStr for HostKeyChecking {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "yes" => Ok(HostKeyChecking::Yes),
+            "accept-new" => Ok(HostKeyChecking::AcceptNew),
+            "no" => Ok(HostKeyChecking::No),
+            other => Err(format!(
+                "unknown host key checking `{}`, expected yes, accept-new or no",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HostKeyChecking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HostKeyChecking::Yes => "yes",
+            HostKeyChecking::AcceptNew => "accept-new",
+            HostKeyChecking::No => "no",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How the SSH connections are made.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SshTransport {
+    /// Run the `ssh` binary, honouring `~/.ssh/config`.
+    #[default]
+    OpenSsh,
+    /// Speak SSH in-process, for full diagnostics. Needs the `native-ssh` feature.
+    Native,
+}
+
+impl FromStr for SshTransport {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "openssh" => Ok(SshTransport::OpenSsh),
+            "native" => Ok(SshTransport::Native),
+            other => Err(format!(
+                "unknown SSH transport `{}`, expected openssh or native",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SshTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SshTransport::OpenSsh => "openssh",
+            SshTransport::Native => "native",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// `-o` options the native transport understands.
+const NATIVE_OPTIONS: [&str; 2] = ["ConnectTimeout", "UserKnownHostsFile"];
+
+/// Settings for every SSH call, unset values are left to `ssh` and `~/.ssh/config`.
+#[derive(Debug, Clone, Default)]
+pub struct SshOptions {
+    /// Login user, it wins over a user given with the host.
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    /// Bastion to connect through, `[user@]host[:port]` as for `ssh -J`.
+    pub jump_host: Option<String>,
+    pub host_key_checking: Option<HostKeyChecking>,
+    /// More `ssh -o` options, each `Key=Value`.
+    pub options: Vec<String>,
+    /// Share one authenticated connection per server between the calls.
+    pub multiplex: bool,
+    pub transport: SshTransport,
+}
+
+impl SshOptions {
+    /// `ssh` arguments that go before the destination.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        let mut option = |value: String| args.extend(["-o".to_string(), value]);
+
+        if let Some(host_key_checking) = self.host_key_checking {
+            option(format!("StrictHostKeyChecking={}", host_key_checking));
+        }
+        if self.multiplex {
+            option("ControlMaster=auto".into());
+            option(format!("ControlPath={}", CONTROL_PATH));
+            option(format!("ControlPersist={}", CONTROL_PERSIST));
+        }
+        for extra in &self.options {
+            option(extra.clone());
+        }
+
+        if let Some(user) = &self.user {
+            args.extend(["-l".into(), user.clone()]);
+        }
+        if let Some(port) = self.port {
+            args.extend(["-p".into(), port.to_string()]);
+        }
+        if let Some(identity_file) = &self.identity_file {
+            args.extend(["-i".into(), identity_file.clone()]);
+        }
+        if let Some(jump_host) = &self.jump_host {
+            args.extend(["-J".into(), jump_host.clone()]);
+        }
+
+        args
+    }
+
+    /// Checks the chosen transport is built in and can honour the options.
+    pub fn validate_transport(&self) -> AppResult<()> {
+        if self.transport == SshTransport::OpenSsh {
+            return Ok(());
+        }
+        if cfg!(not(feature = "native-ssh")) {
+            return Err(AppError::ConfigError(
+                "The native SSH transport needs a build with the native-ssh feature".into(),
+            ));
+        }
+
+        for option in &self.options {
+            let key = option
+                .split_once('=')
+                .map_or(option.as_str(), |(key, _)| key);
+            if !NATIVE_OPTIONS
+                .iter()
+                .any(|supported| supported.eq_ignore_ascii_case(key))
+            {
+                return Err(AppError::ConfigError(format!(
+                    "SSH option `{}` isn't supported by the native transport, only {}",
+                    option,
+                    NATIVE_OPTIONS.join(" and ")
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Value of an extra `-o` option. Like `ssh`, keys match without case and
+    /// the first value given wins.
+    pub fn option(&self, key: &str) -> Option<&str> {
+        self.options.iter().find_map(|option| {
+            let (name, value) = option.split_once('=')?;
+            name.eq_ignore_ascii_case(key).then_some(value)
+        })
+    }
+}
+
+/// Checks an extra `ssh -o` option is a `Key=Value` pair.
+pub fn validate_option(option: &str) -> AppResult<()> {
+    match option.split_once('=') {
+        Some((key, _)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            Ok(())
+        }
+        _ => Err(AppError::ConfigError(format!(
+            "Invalid SSH option `{}`, expected Key=Value",
+            option
+        ))),
+    }
+}
@@ -0,0 +1,555 @@
+// SSH in-process with russh, so failures come back with the server's reasons
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use russh::{
+    client::{self, Handle},
+    keys::{
+        agent::client::AgentClient, known_hosts, load_secret_key, PrivateKeyWithHashAlg,
+        PublicKeyOrCertificate,
+    },
+    ChannelMsg,
+};
+use russh_sftp::client::SftpSession;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    runtime::Runtime,
+    sync::{mpsc, Notify},
+};
+
+use super::{HostKeyChecking, SshOptions};
+use crate::{
+    error::{AppError, AppResult},
+    runner::{CommandOutput, CommandRunner, Stdin, Stdout},
+};
+
+/// Size of the pieces stdin and uploads are sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Keys `ssh` looks for when no identity file is given.
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+type Failure = Box<dyn Error + Send + Sync>;
+
+/// Runs commands on a remote host over SSH without the `ssh` binary. One
+/// connection is opened on first use and shared by all the calls.
+pub struct NativeRunner {
+    host: String,
+    options: SshOptions,
+    connection: Mutex<Option<Arc<Connection>>>,
+}
+
+struct Connection {
+    session: Handle<HostKeys>,
+    /// Kept open while the session is tunnelled through it.
+    _jump: Option<Handle<HostKeys>>,
+    // Dropped last, the handles above need it to shut down.
+    runtime: Runtime,
+}
+
+impl NativeRunner {
+    pub fn new(host: &str, options: &SshOptions) -> Self {
+        NativeRunner {
+            host: host.to_string(),
+            options: options.clone(),
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn connection(&self) -> AppResult<Arc<Connection>> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(connection) = connection.as_ref() {
+            return Ok(Arc::clone(connection));
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|e| AppError::CommandError(self.program().into(), e))?;
+        let (session, jump) = runtime.block_on(self.open()).map_err(|e| self.error(e))?;
+        let opened = Arc::new(Connection {
+            session,
+            _jump: jump,
+            runtime,
+        });
+        *connection = Some(Arc::clone(&opened));
+
+        Ok(opened)
+    }
+
+    async fn open(&self) -> Result<(Handle<HostKeys>, Option<Handle<HostKeys>>), Failure> {
+        let (user, host) = split_user(&self.host);
+        // Like `ssh -l`, the user option is for the target only, not the jump host.
+        let user = self.options.user.as_deref().or(user);
+        let port = self.options.port.unwrap_or(22);
+        let timeout = match self.options.option("ConnectTimeout") {
+            Some(seconds) => Duration::from_secs(
+                seconds
+                    .parse()
+                    .map_err(|_| format!("Invalid ConnectTimeout `{}`", seconds))?,
+            ),
+            None => Duration::MAX,
+        };
+
+        let opening = async {
+            let Some(jump_host) = &self.options.jump_host else {
+                let stream = TcpStream::connect((host, port))
+                    .await
+                    .map_err(|e| format!("Unable to connect to {}:{}: {}", host, port, e))?;
+                return Ok((self.handshake(stream, user, host, port).await?, None));
+            };
+
+            let (jump_user, jump_host, jump_port) = split_jump_host(jump_host)?;
+            let stream = TcpStream::connect((jump_host, jump_port))
+                .await
+                .map_err(|e| format!("Unable to connect to {}:{}: {}", jump_host, jump_port, e))?;
+            let jump = self
+                .handshake(stream, jump_user, jump_host, jump_port)
+                .await?;
+            let tunnel = jump
+                .channel_open_direct_tcpip(host, port as u32, "127.0.0.1", 0)
+                .await
+                .map_err(|e| {
+                    format!(
+                        "{} refused to forward to {}:{}: {}",
+                        jump_host, host, port, e
+                    )
+                })?;
+            let session = self
+                .handshake(tunnel.into_stream(), user, host, port)
+                .await?;
+
+            Ok((session, Some(jump)))
+        };
+
+        tokio::time::timeout(timeout, opening)
+            .await
+            .map_err(|_| format!("Timed out connecting to {}", host))?
+    }
+
+    /// Checks the host key and logs in over an open stream.
+    async fn handshake<S>(
+        &self,
+        stream: S,
+        user: Option<&str>,
+        host: &str,
+        port: u16,
+    ) -> Result<Handle<HostKeys>, Failure>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let problem = Arc::new(Mutex::new(None));
+        let handler = HostKeys {
+            host: host.to_string(),
+            port,
+            known_hosts: self.known_hosts(),
+            checking: self.options.host_key_checking,
+            problem: Arc::clone(&problem),
+        };
+        let config = Arc::new(client::Config {
+            inactivity_timeout: None,
+            ..Default::default()
+        });
+
+        let mut session = match client::connect_stream(config, stream, handler).await {
+            Ok(session) => session,
+            Err(e) => {
+                let problem = problem.lock().unwrap_or_else(|e| e.into_inner()).take();
+                return Err(problem.unwrap_or_else(|| e.to_string()).into());
+            }
+        };
+
+        let user = match user {
+            Some(user) => user.to_string(),
+            None => std::env::var("USER").map_err(|_| "No SSH user given and $USER isn't set")?,
+        };
+        self.authenticate(&mut session, &user, host).await?;
+
+        Ok(session)
+    }
+
+    /// Tries the identity file, or the default key files when there is none,
+    /// then the agent's keys.
+    async fn authenticate(
+        &self,
+        session: &mut Handle<HostKeys>,
+        user: &str,
+        host: &str,
+    ) -> Result<(), Failure> {
+        let rsa_hash = session.best_supported_rsa_hash().await?.flatten();
+        let mut tried = vec![];
+
+        let key_files = match &self.options.identity_file {
+            Some(identity_file) => vec![(expand_home(identity_file), true)],
+            None => DEFAULT_KEYS
+                .iter()
+                .map(|name| (expand_home(&format!("~/.ssh/{}", name)), false))
+                .filter(|(path, _)| path.exists())
+                .collect(),
+        };
+        for (path, required) in &key_files {
+            let key = match load_secret_key(path, None) {
+                Ok(key) => key,
+                Err(e) if *required => {
+                    return Err(format!("Unable to load {}: {}", path.display(), e).into())
+                }
+                // Skipped like `ssh` does in batch mode, e.g. when it needs a passphrase.
+                Err(_) => continue,
+            };
+            let key = PrivateKeyWithHashAlg::new(Arc::new(key), rsa_hash);
+            if session.authenticate_publickey(user, key).await?.success() {
+                return Ok(());
+            }
+            tried.push(path.display().to_string());
+        }
+
+        if let Ok(mut agent) = AgentClient::connect_env().await {
+            let identities = agent.request_identities().await.unwrap_or_default();
+            for identity in &identities {
+                let key = identity.public_key().into_owned();
+                let hash = rsa_hash.filter(|_| key.algorithm().is_rsa());
+                if session
+                    .authenticate_publickey_with(user, key, hash, &mut agent)
+                    .await?
+                    .success()
+                {
+                    return Ok(());
+                }
+            }
+            tried.push(format!("{} agent key(s)", identities.len()));
+        }
+
+        Err(match tried.is_empty() {
+            true => format!(
+                "Permission denied for {}@{}: no identity file or agent keys to try",
+                user, host
+            ),
+            false => format!(
+                "Permission denied for {}@{}, tried {}",
+                user,
+                host,
+                tried.join(", ")
+            ),
+        }
+        .into())
+    }
+
+    fn known_hosts(&self) -> PathBuf {
+        expand_home(
+            self.options
+                .option("UserKnownHostsFile")
+                .unwrap_or("~/.ssh/known_hosts"),
+        )
+    }
+
+    fn error(&self, error: impl ToString) -> AppError {
+        AppError::CommandError(
+            self.program().into(),
+            io::Error::other(format!("{}: {}", self.host, error.to_string())),
+        )
+    }
+}
+
+impl CommandRunner for NativeRunner {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn program(&self) -> &str {
+        "ssh"
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let connection = self.connection()?;
+
+        let mut captured = Vec::new();
+        let mut file;
+        let sink: Option<&mut dyn Write> = match stdout {
+            Stdout::Null => None,
+            Stdout::Capture => Some(&mut captured),
+            Stdout::File(path) => {
+                file =
+                    File::create(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+                Some(&mut file)
+            }
+            Stdout::Writer(writer) => Some(writer),
+        };
+
+        // Input is read on its own thread, so a slow or blocking reader can't
+        // hold up the output.
+        let (sender, input) = mpsc::channel(4);
+        let output = thread::scope(|scope| {
+            let feeder = match stdin {
+                Stdin::Null => None,
+                Stdin::File(path) => {
+                    let file =
+                        File::open(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+                    Some(scope.spawn(move || feed(file, sender)))
+                }
+                Stdin::Bytes(bytes) => Some(scope.spawn(move || feed(bytes, sender))),
+                Stdin::Reader(reader) => Some(scope.spawn(move || feed(reader, sender))),
+            };
+
+            let output = connection
+                .runtime
+                .block_on(exec(&connection.session, command, input, sink))
+                .map_err(|e| self.error(e))?;
+            if let Some(feeder) = feeder {
+                feeder
+                    .join()
+                    .unwrap_or(Ok(()))
+                    .map_err(|e| AppError::CommandError(self.program().into(), e))?;
+            }
+
+            Ok::<_, AppError>(output)
+        })?;
+
+        Ok(CommandOutput {
+            stdout: captured,
+            ..output
+        })
+    }
+
+    /// Writes the file over SFTP.
+    fn upload(&self, input: Stdin, remote_path: &str) -> AppResult<()> {
+        let connection = self.connection()?;
+        let mut opened;
+        let mut empty = io::empty();
+        let mut slice;
+        let file: &mut dyn Read = match input {
+            Stdin::Null => &mut empty,
+            Stdin::File(path) => {
+                opened =
+                    File::open(path).map_err(|e| AppError::FileError(path.to_path_buf(), e))?;
+                &mut opened
+            }
+            Stdin::Bytes(bytes) => {
+                slice = bytes;
+                &mut slice
+            }
+            Stdin::Reader(reader) => reader,
+        };
+
+        connection
+            .runtime
+            .block_on(async {
+                let channel = connection.session.channel_open_session().await?;
+                channel.request_subsystem(true, "sftp").await?;
+                let sftp = SftpSession::new(channel.into_stream()).await?;
+                let mut remote = sftp
+                    .create(remote_path)
+                    .await
+                    .map_err(|e| format!("Unable to create {}: {}", remote_path, e))?;
+
+                let mut buffer = vec![0; CHUNK_SIZE];
+                loop {
+                    let read = file.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    remote.write_all(&buffer[..read]).await?;
+                }
+                remote.shutdown().await?;
+                sftp.close().await?;
+
+                Ok::<_, Failure>(())
+            })
+            .map_err(|e| self.error(e))
+    }
+
+    /// Archives go over SFTP rather than through a command's stdin.
+    fn prefers_upload(&self) -> bool {
+        true
+    }
+}
+
+/// Runs `command` on a new channel, sending it `input` and writing its output
+/// to `sink`.
+async fn exec(
+    session: &Handle<HostKeys>,
+    command: &str,
+    mut input: mpsc::Receiver<Vec<u8>>,
+    mut sink: Option<&mut dyn Write>,
+) -> Result<CommandOutput, Failure> {
+    let channel = session.channel_open_session().await?;
+    channel.exec(true, command).await?;
+    let (mut read, write) = channel.split();
+
+    let finished = Notify::new();
+    let send = async {
+        while let Some(chunk) = input.recv().await {
+            // The command may finish without reading all of its input.
+            if write.data_bytes(chunk).await.is_err() {
+                return;
+            }
+        }
+        let _ = write.eof().await;
+    };
+    let receive = async {
+        let mut code = None;
+        let mut stderr = Vec::new();
+        while let Some(message) = read.wait().await {
+            match message {
+                ChannelMsg::Data { data } => {
+                    if let Some(sink) = sink.as_mut() {
+                        sink.write_all(&data)?;
+                    }
+                }
+                ChannelMsg::ExtendedData { data, ext: 1 } => stderr.extend_from_slice(&data),
+                ChannelMsg::ExitStatus { exit_status } => code = Some(exit_status as i32),
+                ChannelMsg::Failure => return Err("the server refused to run the command".into()),
+                ChannelMsg::Close => break,
+                _ => {}
+            }
+        }
+        Ok::<_, Failure>(CommandOutput {
+            code,
+            stdout: vec![],
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+        })
+    };
+
+    let (_, output) = tokio::join!(
+        async {
+            tokio::select! {
+                _ = send => {}
+                _ = finished.notified() => {}
+            }
+        },
+        async {
+            let output = receive.await;
+            finished.notify_one();
+            output
+        },
+    );
+
+    output
+}
+
+/// Sends `reader` to the channel a chunk at a time.
+fn feed(mut reader: impl Read, sender: mpsc::Sender<Vec<u8>>) -> io::Result<()> {
+    loop {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = reader.read(&mut chunk)?;
+        chunk.truncate(read);
+        // Nothing is listening anymore once the command has finished.
+        if read == 0 || sender.blocking_send(chunk).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Checks server keys against `known_hosts`.
+struct HostKeys {
+    host: String,
+    port: u16,
+    known_hosts: PathBuf,
+    checking: Option<HostKeyChecking>,
+    /// Why the key was refused, the connection error alone doesn't say.
+    problem: Arc<Mutex<Option<String>>>,
+}
+
+impl HostKeys {
+    fn refuse(&self, problem: String) -> Result<bool, russh::Error> {
+        *self.problem.lock().unwrap_or_else(|e| e.into_inner()) = Some(problem);
+        Ok(false)
+    }
+}
+
+impl client::Handler for HostKeys {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let PublicKeyOrCertificate::PublicKey { key, .. } = server_key else {
+            return self.refuse(format!(
+                "{} offered a host certificate, which isn't supported",
+                self.host
+            ));
+        };
+        if self.checking == Some(HostKeyChecking::No) {
+            return Ok(true);
+        }
+
+        let fingerprint = key.fingerprint(Default::default());
+        match known_hosts::check_known_hosts_path(&self.host, self.port, key, &self.known_hosts) {
+            Ok(true) => Ok(true),
+            Ok(false) if self.checking == Some(HostKeyChecking::AcceptNew) => {
+                match known_hosts::learn_known_hosts_path(
+                    &self.host,
+                    self.port,
+                    key,
+                    &self.known_hosts,
+                ) {
+                    Ok(()) => Ok(true),
+                    Err(e) => self.refuse(format!(
+                        "Unable to add the host key for {} to {}: {}",
+                        self.host,
+                        self.known_hosts.display(),
+                        e
+                    )),
+                }
+            }
+            Ok(false) => self.refuse(format!(
+                "Host key {} for {} isn't in {}, add it or use accept-new host key checking",
+                fingerprint,
+                self.host,
+                self.known_hosts.display()
+            )),
+            Err(russh::keys::Error::KeyChanged { line }) => self.refuse(format!(
+                "Host key for {} has changed to {}, it no longer matches line {} of {}",
+                self.host,
+                fingerprint,
+                line,
+                self.known_hosts.display()
+            )),
+            Err(e) => self.refuse(format!(
+                "Unable to check the host key for {} in {}: {}",
+                self.host,
+                self.known_hosts.display(),
+                e
+            )),
+        }
+    }
+}
+
+/// Splits `user@host`, the user is optional.
+fn split_user(destination: &str) -> (Option<&str>, &str) {
+    match destination.rsplit_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, destination),
+    }
+}
+
+/// Splits a `[user@]host[:port]` jump host.
+fn split_jump_host(jump_host: &str) -> Result<(Option<&str>, &str, u16), Failure> {
+    let (user, address) = split_user(jump_host);
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("Invalid port in jump host `{}`", jump_host))?;
+            Ok((user, host, port))
+        }
+        None => Ok((user, address, 22)),
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,206 @@
+// Streaming archives straight from the source to the destination
+
+use std::{
+    io::{self, BufWriter, Write},
+    thread,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{AppError, AppResult},
+    runner::{CommandRunner, Stdin, Stdout},
+    shell,
+};
+
+/// What went through a stream, the checksum is the hex SHA-256 of the bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamSummary {
+    pub bytes: u64,
+    pub checksum: String,
+}
+
+const CHECKSUM_PREFIX: &str = "sha256:";
+
+/// Wraps a source command so its output is also hashed, the checksum is
+/// printed to stderr as `sha256:<hex>`. The exit status is the command's own,
+/// not `tee`'s, unless `tee` itself fails.
+pub fn source_command(command: &str) -> String {
+    format!(
+        concat!(
+            "dir=$(mktemp -d) || exit 1; mkfifo \"$dir/out\" || exit 1; ",
+            "sha256sum < \"$dir/out\" > \"$dir/sum\" & ",
+            "{}; status=$?; wait; ",
+            "echo \"{}$(cut -d ' ' -f 1 \"$dir/sum\")\" >&2; ",
+            "rm -rf \"$dir\"; exit $status"
+        ),
+        shell::pipe(command, "tee \"$dir/out\""),
+        CHECKSUM_PREFIX
+    )
+}
+
+/// Wraps a destination command so the input it was fed is hashed, the
+/// checksum is printed to stdout as `sha256:<hex>` and the exit status kept.
+pub fn sink_command(command: &str) -> String {
+    format!(
+        concat!(
+            "dir=$(mktemp -d) || exit 1; mkfifo \"$dir/in\" || exit 1; ",
+            "sha256sum < \"$dir/in\" > \"$dir/sum\" & ",
+            "tee \"$dir/in\" | {{ {}; }} > /dev/null; status=$?; wait; ",
+            "echo \"{}$(cut -d ' ' -f 1 \"$dir/sum\")\"; ",
+            "rm -rf \"$dir\"; exit $status"
+        ),
+        command, CHECKSUM_PREFIX
+    )
+}
+
+/// Finds the checksum printed by `source_command` or `sink_command`,
+/// returning it along with the rest of the output.
+pub fn split_checksum(output: &str) -> (Option<String>, String) {
+    let mut checksum = None;
+    let mut rest = vec![];
+
+    for line in output.lines() {
+        match line.strip_prefix(CHECKSUM_PREFIX) {
+            Some(sum) => checksum = Some(sum.trim().to_string()),
+            None => rest.push(line),
+        }
+    }
+
+    (checksum, rest.join("\n"))
+}
+
+/// Runs `dest_command` on the destination, feeding it whatever `source`
+/// writes. The stream is hashed here and on the destination, and `source`
+/// can return the checksum the source side worked out; they all have to match.
+pub fn pipe<S>(dest: &dyn CommandRunner, dest_command: &str, source: S) -> AppResult<StreamSummary>
+where
+    S: FnOnce(&mut dyn Write) -> AppResult<Option<String>>,
+{
+    let (mut reader, writer) =
+        io::pipe().map_err(|e| AppError::CommandError(dest.program().into(), e))?;
+    let sink = sink_command(dest_command);
+
+    let (source_result, summary, dest_result) = thread::scope(|scope| {
+        let dest_thread = scope.spawn(move || {
+            let result = dest.run(&sink, Stdin::Reader(&mut reader), Stdout::Capture);
+            // Closing our end makes the source stop if the destination gave up early.
+            drop(reader);
+            result
+        });
+
+        let mut hashing = HashingWriter::new(BufWriter::new(writer));
+        let source_result = source(&mut hashing).and_then(|checksum| {
+            hashing
+                .flush()
+                .map_err(|e| AppError::CommandError(dest.program().into(), e))?;
+            Ok(checksum)
+        });
+        // Dropping the writer is the end of the stream for the destination.
+        let summary = hashing.finish();
+
+        let dest_result = dest_thread.join().expect("destination thread panicked");
+        (source_result, summary, dest_result)
+    });
+
+    // When the destination fails the source only sees a broken pipe, so its
+    // error is the one worth reporting.
+    let dest_output = dest_result?;
+    if !dest_output.success() {
+        let (_, stderr) = split_checksum(&dest_output.stderr);
+        return Err(failed(dest, dest_command, &stderr));
+    }
+    let source_checksum = source_result?;
+
+    if let Some(checksum) = source_checksum.filter(|sum| *sum != summary.checksum) {
+        return Err(AppError::ChecksumMismatch(format!(
+            "the source sent {}, {} arrived here",
+            checksum, summary.checksum
+        )));
+    }
+
+    match split_checksum(&dest_output.stdout_string()) {
+        (Some(checksum), _) if checksum == summary.checksum => {}
+        (Some(checksum), _) => {
+            return Err(AppError::ChecksumMismatch(format!(
+                "{} was sent, {} received {}",
+                summary.checksum,
+                dest.host(),
+                checksum
+            )));
+        }
+        (None, _) => {
+            return Err(AppError::ChecksumMismatch(format!(
+                "no checksum came back from {}",
+                dest.host()
+            )));
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Runs `command` on the source server, wrapped by `source_command`, writing
+/// its output to `writer` and returning the checksum worked out over there.
+pub fn from_remote(
+    source: &dyn CommandRunner,
+    command: &str,
+    stdin: Stdin,
+    writer: &mut dyn Write,
+) -> AppResult<Option<String>> {
+    let output = source.run(&source_command(command), stdin, Stdout::Writer(writer))?;
+    let (checksum, stderr) = split_checksum(&output.stderr);
+
+    if !output.success() {
+        return Err(failed(source, command, &stderr));
+    }
+
+    Ok(checksum)
+}
+
+fn failed(runner: &dyn CommandRunner, command: &str, stderr: &str) -> AppError {
+    AppError::CommandError(
+        runner.program().into(),
+        io::Error::other(match stderr.trim() {
+            "" => format!("`{}` failed on {}", command, runner.host()),
+            stderr => stderr.to_string(),
+        }),
+    )
+}
+
+/// Passes writes through while hashing them.
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    fn finish(self) -> StreamSummary {
+        StreamSummary {
+            bytes: self.bytes,
+            checksum: format!("{:x}", self.hasher.finalize()),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,510 @@
+// Incremental file sync, only copying what changed since the last pass
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    fs::{self, File},
+    io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    archive::{self, ArchiveProgress},
+    backup,
+    compression::Compression,
+    error::{AppError, AppResult},
+    exclude::{self, Excludes},
+    feedback::{Progress, ProgressWriter},
+    remote::RemoteFolder,
+    runner::{Stdin, Stdout},
+    shell::RemoteCommand,
+    source::SourceFolder,
+    stream,
+};
+
+/// How the site files get to the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileTransfer {
+    /// One full tarball, written to the temp folder or streamed.
+    #[default]
+    Archive,
+    /// Only the files that differ from the destination.
+    Sync,
+}
+
+impl FromStr for FileTransfer {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "archive" => Ok(FileTransfer::Archive),
+            "sync" => Ok(FileTransfer::Sync),
+            other => Err(format!(
+                "unknown file transfer `{}`, expected archive or sync",
+                other
+            )),
+        }
+    }
+}
+
+/// How files are told apart from their copy on the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompareBy {
+    /// Size and modification time.
+    #[default]
+    Mtime,
+    /// Size and SHA-256 of the contents, slower but exact.
+    Checksum,
+}
+
+impl FromStr for CompareBy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "mtime" => Ok(CompareBy::Mtime),
+            "checksum" => Ok(CompareBy::Checksum),
+            other => Err(format!(
+                "unknown comparison `{}`, expected mtime or checksum",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CompareBy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CompareBy::Mtime => "mtime",
+            CompareBy::Checksum => "checksum",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncOptions {
+    pub compare: CompareBy,
+    /// Remove files from the destination that are gone from the source.
+    pub delete: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// What is known about one path of a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub size: u64,
+    pub mtime: i64,
+    /// Target of a symlink.
+    pub link: Option<String>,
+    /// Only worked out when comparing by checksum.
+    pub checksum: Option<String>,
+}
+
+/// Every path below a folder, relative to it.
+pub type Manifest = BTreeMap<String, Entry>;
+
+/// What a sync has to do to bring the destination up to date.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncChanges {
+    /// Paths to copy, parents before their contents.
+    pub transfer: Vec<String>,
+    /// Paths to remove from the destination first.
+    pub delete: Vec<String>,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncSummary {
+    pub transferred: usize,
+    pub deleted: usize,
+    pub unchanged: usize,
+    /// Size of the compressed stream of changed files.
+    pub bytes: u64,
+    pub excluded_files: u64,
+    pub excluded_bytes: u64,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} copied, {} deleted, {} unchanged",
+            self.transferred, self.deleted, self.unchanged
+        )
+    }
+}
+
+/// Brings `dest` up to date with `source`, copying only what differs. It can
+/// be run again and again against the same folder, e.g. a final pass at cutover.
+pub fn sync_files(
+    source: SourceFolder,
+    dest: RemoteFolder,
+    compression: &Compression,
+    options: &SyncOptions,
+    excludes: &Excludes,
+    progress: &Progress,
+) -> AppResult<SyncSummary> {
+    let (source_manifest, excluded) = match source {
+        SourceFolder::Local(folder) => {
+            local_manifest(Path::new(folder), options.compare, excludes)?
+        }
+        SourceFolder::Remote(folder) => {
+            let mut manifest = remote_manifest(folder, options.compare)?;
+            let excluded = excludes.filter(&mut manifest);
+            (manifest, excluded)
+        }
+    };
+    // Excluded paths are left alone on the destination as well, never deleted.
+    let mut dest_manifest = remote_manifest(dest, options.compare)?;
+    excludes.filter(&mut dest_manifest);
+    let changes = compare(&source_manifest, &dest_manifest, options);
+
+    if !changes.delete.is_empty() {
+        delete_paths(dest, &changes.delete)?;
+    }
+
+    let mut bytes = 0;
+    if !changes.transfer.is_empty() {
+        let changed: Manifest = changes
+            .transfer
+            .iter()
+            .filter_map(|path| Some((path.clone(), source_manifest.get(path)?.clone())))
+            .collect();
+        bytes = transfer_paths(source, dest, &changed, compression, progress)?.bytes;
+    }
+
+    Ok(SyncSummary {
+        transferred: changes.transfer.len(),
+        deleted: changes.delete.len(),
+        unchanged: changes.unchanged,
+        bytes,
+        excluded_files: excluded.excluded_files,
+        excluded_bytes: excluded.excluded_bytes,
+    })
+}
+
+/// Works out which paths differ between the trees.
+pub fn compare(source: &Manifest, dest: &Manifest, options: &SyncOptions) -> SyncChanges {
+    let mut changes = SyncChanges::default();
+
+    for (path, entry) in source {
+        let changed = match dest.get(path) {
+            None => true,
+            Some(existing) if existing.kind != entry.kind => {
+                // A file can't be unpacked over a folder, or the other way round.
+                changes.delete.push(path.clone());
+                true
+            }
+            Some(existing) => match entry.kind {
+                EntryKind::File => {
+                    existing.size != entry.size
+                        || match options.compare {
+                            CompareBy::Mtime => existing.mtime != entry.mtime,
+                            CompareBy::Checksum => existing.checksum != entry.checksum,
+                        }
+                }
+                EntryKind::Symlink => existing.link != entry.link,
+                EntryKind::Directory | EntryKind::Other => false,
+            },
+        };
+
+        match changed {
+            true => changes.transfer.push(path.clone()),
+            false => changes.unchanged += 1,
+        }
+    }
+
+    if options.delete {
+        let mut removed: HashSet<&str> = HashSet::new();
+        for path in dest.keys().filter(|path| !source.contains_key(*path)) {
+            // Removing a folder takes everything in it along.
+            let parent_removed = Path::new(path)
+                .ancestors()
+                .skip(1)
+                .any(|parent| removed.contains(parent.to_str().unwrap_or_default()));
+            if !parent_removed {
+                changes.delete.push(path.clone());
+            }
+            removed.insert(path);
+        }
+    }
+
+    changes
+}
+
+/// Lists a folder on this machine, leaving out the excluded paths and
+/// returning how much they hold.
+pub fn local_manifest(
+    folder: &Path,
+    compare_by: CompareBy,
+    excludes: &Excludes,
+) -> AppResult<(Manifest, ArchiveProgress)> {
+    let mut manifest = Manifest::new();
+    let mut excluded = ArchiveProgress::default();
+    add_local_folder(
+        folder,
+        "",
+        compare_by,
+        excludes,
+        &mut manifest,
+        &mut excluded,
+    )?;
+    Ok((manifest, excluded))
+}
+
+fn add_local_folder(
+    folder: &Path,
+    relative: &str,
+    compare_by: CompareBy,
+    excludes: &Excludes,
+    manifest: &mut Manifest,
+    excluded: &mut ArchiveProgress,
+) -> AppResult<()> {
+    let entries = fs::read_dir(folder)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|e| AppError::FileError(folder.into(), e))?;
+
+    for entry in entries {
+        let path = entry.path();
+        let name = match relative {
+            "" => entry.file_name().to_string_lossy().to_string(),
+            relative => format!("{}/{}", relative, entry.file_name().to_string_lossy()),
+        };
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|e| AppError::FileError(path.clone(), e))?;
+
+        if excludes.is_excluded(Path::new(&name), metadata.is_dir()) {
+            let (files, bytes) = match metadata.is_dir() {
+                true => exclude::folder_size(&path),
+                false if metadata.is_file() => (0, metadata.len()),
+                false => (0, 0),
+            };
+            excluded.excluded_files += files + 1;
+            excluded.excluded_bytes += bytes;
+            continue;
+        }
+
+        let kind = match metadata.file_type() {
+            file_type if file_type.is_file() => EntryKind::File,
+            file_type if file_type.is_dir() => EntryKind::Directory,
+            file_type if file_type.is_symlink() => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let link = match kind {
+            EntryKind::Symlink => Some(
+                fs::read_link(&path)
+                    .map_err(|e| AppError::FileError(path.clone(), e))?
+                    .to_string_lossy()
+                    .to_string(),
+            ),
+            _ => None,
+        };
+        let checksum = match (kind, compare_by) {
+            (EntryKind::File, CompareBy::Checksum) => Some(file_checksum(&path)?),
+            _ => None,
+        };
+
+        manifest.insert(
+            name.clone(),
+            Entry {
+                kind,
+                size: metadata.len(),
+                mtime: metadata.mtime(),
+                link,
+                checksum,
+            },
+        );
+
+        if kind == EntryKind::Directory {
+            add_local_folder(&path, &name, compare_by, excludes, manifest, excluded)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn file_checksum(path: &Path) -> AppResult<String> {
+    let mut file = File::open(path).map_err(|e| AppError::FileError(path.into(), e))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| AppError::FileError(path.into(), e))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Lists a folder on a server with `find`, and `sha256sum` when comparing by checksum.
+pub fn remote_manifest(folder: RemoteFolder, compare_by: CompareBy) -> AppResult<Manifest> {
+    let output = folder.runner.run_checked(
+        &manifest_command(folder.user_name, folder.path),
+        Stdin::Null,
+        Stdout::Capture,
+    )?;
+    let mut manifest = parse_manifest(&output.stdout)?;
+
+    if compare_by == CompareBy::Checksum {
+        let output = folder.runner.run_checked(
+            &checksum_command(folder.user_name, folder.path),
+            Stdin::Null,
+            Stdout::Capture,
+        )?;
+        let prefix = format!("{}/", folder.path.trim_end_matches('/'));
+        for line in output.stdout.split(|byte| *byte == 0) {
+            let line = String::from_utf8_lossy(line);
+            let Some((checksum, path)) = line.split_once("  ") else {
+                continue;
+            };
+            let path = path.strip_prefix(&prefix).unwrap_or(path);
+            if let Some(entry) = manifest.get_mut(path) {
+                entry.checksum = Some(checksum.to_string());
+            }
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Prints type, size, modification time, relative path and link target of
+/// everything below `folder`, each field NUL terminated.
+pub fn manifest_command(user_name: Option<&str>, folder: &str) -> String {
+    RemoteCommand::new("find")
+        .args([
+            folder,
+            "-mindepth",
+            "1",
+            "-printf",
+            "%y\\0%s\\0%T@\\0%P\\0%l\\0",
+        ])
+        .as_user(user_name)
+        .to_string()
+}
+
+/// Prints `<sha256>  <path>` for every file below `folder`, NUL terminated.
+pub fn checksum_command(user_name: Option<&str>, folder: &str) -> String {
+    RemoteCommand::new("find")
+        .args([folder, "-type", "f", "-exec", "sha256sum", "-z", "{}", "+"])
+        .as_user(user_name)
+        .to_string()
+}
+
+/// Reads the output of `manifest_command`.
+pub fn parse_manifest(output: &[u8]) -> AppResult<Manifest> {
+    let fields: Vec<String> = output
+        .split(|byte| *byte == 0)
+        .map(|field| String::from_utf8_lossy(field).to_string())
+        .collect();
+    let invalid = |field: &str| {
+        AppError::CommandError(
+            "find".into(),
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected file listing: {}", field),
+            ),
+        )
+    };
+
+    let mut manifest = Manifest::new();
+    // The output ends with a NUL, leaving an empty field after the last entry.
+    for entry in fields.chunks_exact(5) {
+        let [kind, size, mtime, path, link] = entry else {
+            continue;
+        };
+        let kind = match kind.as_str() {
+            "f" => EntryKind::File,
+            "d" => EntryKind::Directory,
+            "l" => EntryKind::Symlink,
+            _ => EntryKind::Other,
+        };
+        let size = size.parse().map_err(|_| invalid(size))?;
+        let mtime = mtime
+            .split('.')
+            .next()
+            .and_then(|seconds| seconds.parse().ok())
+            .ok_or_else(|| invalid(mtime))?;
+
+        manifest.insert(
+            path.clone(),
+            Entry {
+                kind,
+                size,
+                mtime,
+                link: (kind == EntryKind::Symlink).then(|| link.clone()),
+                checksum: None,
+            },
+        );
+    }
+
+    Ok(manifest)
+}
+
+fn delete_paths(dest: RemoteFolder, paths: &[String]) -> AppResult<()> {
+    let folder = dest.path.trim_end_matches('/');
+    let list: Vec<u8> = paths
+        .iter()
+        .flat_map(|path| format!("{}/{}\0", folder, path).into_bytes())
+        .collect();
+    let remote_command = RemoteCommand::new("xargs")
+        .args(["-0", "-r", "rm", "-rf", "--"])
+        .as_user(dest.user_name);
+
+    dest.runner.run_checked(
+        &remote_command.to_string(),
+        Stdin::Bytes(&list),
+        Stdout::Null,
+    )?;
+
+    Ok(())
+}
+
+/// Sends the `changed` entries of the source over. The progress has a total
+/// for a local folder, a remote one only counts the bytes sent.
+fn transfer_paths(
+    source: SourceFolder,
+    dest: RemoteFolder,
+    changed: &Manifest,
+    compression: &Compression,
+    progress: &Progress,
+) -> AppResult<stream::StreamSummary> {
+    let remote_command = backup::restore_files_command(dest.user_name, dest.path, compression);
+    let paths: Vec<String> = changed.keys().cloned().collect();
+
+    stream::pipe(dest.runner, &remote_command, |writer| match source {
+        SourceFolder::Local(folder) => {
+            progress.set_total(backup::file_bytes(changed));
+            archive::write_paths(
+                Path::new(folder),
+                &paths,
+                writer,
+                compression,
+                |_, totals| backup::report_archive(progress, totals),
+            )?;
+            Ok(None)
+        }
+        SourceFolder::Remote(folder) => {
+            let list: Vec<u8> = paths
+                .iter()
+                .flat_map(|path| format!("{}\0", path).into_bytes())
+                .collect();
+            stream::from_remote(
+                folder.runner,
+                &backup::archive_paths_command(folder.user_name, folder.path, compression),
+                Stdin::Bytes(&list),
+                &mut ProgressWriter::new(writer, progress),
+            )
+        }
+    })
+}
@@ -0,0 +1,180 @@
+use std::{
+    fs,
+    os::unix::fs::{symlink, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use filetime::FileTime;
+use forge_common::{
+    archive::{self, ArchiveProgress},
+    compression::{Codec, Compression},
+    error::AppError,
+    exclude::Excludes,
+};
+
+mod common;
+
+use common::scratch_dir;
+
+fn site(dir: &Path) -> PathBuf {
+    let site = dir.join("site");
+    fs::create_dir_all(site.join("public")).unwrap();
+    fs::create_dir_all(site.join("storage/logs")).unwrap();
+    fs::write(site.join("public/index.php"), "<?php echo 'hi';\n").unwrap();
+    fs::write(site.join("artisan"), "#!/usr/bin/env php\n").unwrap();
+    fs::set_permissions(site.join("artisan"), fs::Permissions::from_mode(0o750)).unwrap();
+    symlink("public/index.php", site.join("index.php")).unwrap();
+    filetime::set_file_mtime(
+        site.join("public/index.php"),
+        FileTime::from_unix_time(1_600_000_000, 0),
+    )
+    .unwrap();
+    filetime::set_file_mtime(
+        site.join("public"),
+        FileTime::from_unix_time(1_500_000_000, 0),
+    )
+    .unwrap();
+    site
+}
+
+#[test]
+fn round_trip_keeps_modes_links_and_times() {
+    for codec in [Codec::Gzip, Codec::Zstd, Codec::None] {
+        let dir = scratch_dir(&format!("round_trip_{}", codec));
+        let site = site(&dir);
+        let tarball = dir.join(format!("out/site.tar{}", codec.extension()));
+        let restored = dir.join("restored");
+        let compression = Compression::new(codec, None, Some(4)).unwrap();
+
+        archive::create_archive(
+            &site,
+            &tarball,
+            &compression,
+            &Excludes::default(),
+            |_, _| {},
+        )
+        .unwrap();
+        archive::extract_archive(&tarball, &restored, |_, _| {}).unwrap();
+
+        assert_restored(&site, &restored);
+    }
+}
+
+fn assert_restored(site: &Path, restored: &Path) {
+    assert_eq!(
+        fs::read_to_string(restored.join("public/index.php")).unwrap(),
+        "<?php echo 'hi';\n"
+    );
+    assert!(restored.join("storage/logs").is_dir());
+
+    let artisan = fs::metadata(restored.join("artisan")).unwrap();
+    assert_eq!(artisan.permissions().mode() & 0o777, 0o750);
+    assert_eq!(
+        artisan.uid(),
+        fs::metadata(site.join("artisan")).unwrap().uid()
+    );
+
+    assert_eq!(
+        fs::read_link(restored.join("index.php")).unwrap(),
+        Path::new("public/index.php")
+    );
+    assert_eq!(
+        fs::metadata(restored.join("public/index.php"))
+            .unwrap()
+            .mtime(),
+        1_600_000_000
+    );
+    assert_eq!(
+        fs::metadata(restored.join("public")).unwrap().mtime(),
+        1_500_000_000
+    );
+}
+
+#[test]
+fn progress_is_reported_per_entry() {
+    let dir = scratch_dir("progress");
+    let site = site(&dir);
+    let mut seen = vec![];
+
+    let totals = archive::create_archive(
+        &site,
+        &dir.join("site.tar.gz"),
+        &Compression::default(),
+        &Excludes::default(),
+        |path, _| seen.push(path.to_path_buf()),
+    )
+    .unwrap();
+
+    assert_eq!(
+        seen,
+        [
+            "artisan",
+            "index.php",
+            "public",
+            "public/index.php",
+            "storage",
+            "storage/logs"
+        ]
+        .map(PathBuf::from)
+    );
+    assert_eq!(
+        totals,
+        ArchiveProgress {
+            files: 6,
+            bytes: 19 + 17,
+            ..Default::default()
+        }
+    );
+
+    let extracted =
+        archive::extract_archive(&dir.join("site.tar.gz"), &dir.join("out"), |_, _| {}).unwrap();
+    assert_eq!(extracted, totals);
+}
+
+#[test]
+fn errors_name_the_file() {
+    let dir = scratch_dir("errors");
+    let missing = dir.join("missing");
+
+    let result = archive::create_archive(
+        &missing,
+        &dir.join("site.tar.gz"),
+        &Compression::default(),
+        &Excludes::default(),
+        |_, _| {},
+    );
+
+    assert!(matches!(result, Err(AppError::FileError(path, _)) if path == missing));
+
+    let corrupt = dir.join("corrupt.tar.gz");
+    fs::write(&corrupt, "not a tarball").unwrap();
+
+    let result = archive::extract_archive(&corrupt, &dir.join("out"), |_, _| {});
+
+    assert!(matches!(result, Err(AppError::FileError(path, _)) if path == corrupt));
+}
+
+#[test]
+fn archives_are_readable_by_the_system_tools() {
+    let dir = scratch_dir("system_tools");
+    let site = site(&dir);
+    let compression = Compression::new(Codec::Zstd, Some(19), Some(2)).unwrap();
+    let tarball = dir.join("site.tar.zst");
+
+    archive::create_archive(
+        &site,
+        &tarball,
+        &compression,
+        &Excludes::default(),
+        |_, _| {},
+    )
+    .unwrap();
+    let listing = std::process::Command::new("tar")
+        .args(["-I", "zstd", "-tf"])
+        .arg(&tarball)
+        .output()
+        .unwrap();
+
+    assert!(listing.status.success());
+    assert!(String::from_utf8_lossy(&listing.stdout).contains("public/index.php"));
+}
@@ -0,0 +1,24 @@
+// Helpers shared by the integration tests
+
+use std::{ops::Deref, path::Path};
+
+use tempfile::TempDir;
+
+/// An empty folder for one test, deleted with everything in it when dropped.
+pub struct ScratchDir(TempDir);
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        self.0.path()
+    }
+}
+
+pub fn scratch_dir(name: &str) -> ScratchDir {
+    let dir = tempfile::Builder::new()
+        .prefix(&format!("forge_{}_", name))
+        .tempdir()
+        .unwrap();
+    ScratchDir(dir)
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use forge_common::{
+    backup,
+    compression::{Codec, Compression},
+    error::AppError,
+    shell,
+};
+
+#[test]
+fn codecs_parse_from_names() {
+    assert_eq!("zstd".parse::<Codec>(), Ok(Codec::Zstd));
+    assert_eq!("GZIP".parse::<Codec>(), Ok(Codec::Gzip));
+    assert_eq!("none".parse::<Codec>(), Ok(Codec::None));
+    assert!("lz4".parse::<Codec>().is_err());
+}
+
+#[test]
+fn levels_are_checked_against_the_codec() {
+    assert_eq!(
+        Compression::new(Codec::Zstd, None, Some(1)).unwrap().level,
+        3
+    );
+    assert!(Compression::new(Codec::Zstd, Some(19), Some(1)).is_ok());
+    assert!(matches!(
+        Compression::new(Codec::Gzip, Some(19), Some(1)),
+        Err(AppError::ConfigError(_))
+    ));
+    assert!(Compression::new(Codec::None, Some(42), Some(1)).is_ok());
+}
+
+#[test]
+fn zero_threads_means_one_per_core() {
+    let compression = Compression::new(Codec::Zstd, None, Some(0)).unwrap();
+
+    assert!(compression.threads >= 1);
+}
+
+#[test]
+fn output_path_extension_follows_the_codec() {
+    let path = |codec| {
+        let compression = Compression::new(codec, None, Some(1)).unwrap();
+        backup::generate_output_path(
+            "/var/www/example.com",
+            "/tmp/forge",
+            "-files.tar",
+            &compression,
+        )
+        .unwrap()
+    };
+
+    for (codec, name) in [
+        (Codec::Gzip, "example.com-files.tar.gz"),
+        (Codec::Zstd, "example.com-files.tar.zst"),
+        (Codec::None, "example.com-files.tar"),
+    ] {
+        let path = path(codec);
+        assert_eq!(path.file_name().unwrap(), name);
+        assert!(path.starts_with(Path::new("/tmp/forge")));
+    }
+}
+
+#[test]
+fn remote_commands_match_the_codec() {
+    let zstd = Compression::new(Codec::Zstd, Some(9), Some(8)).unwrap();
+    let none = Compression::new(Codec::None, None, Some(8)).unwrap();
+
+    assert_eq!(
+        backup::restore_files_command(None, "/home/forge/new.com", &zstd),
+        "tar -I zstd -xpvf - -C /home/forge/new.com"
+    );
+    assert_eq!(
+        backup::restore_database_command("/tmp/tmp.abc", "new_db", &zstd),
+        shell::pipe(
+            "zstd -d -c -q",
+            "mysql --defaults-extra-file=/tmp/tmp.abc new_db"
+        )
+    );
+    assert_eq!(zstd.compress_command().unwrap(), "zstd -c -q -9 -T8");
+    assert_eq!(
+        backup::restore_database_command("/tmp/tmp.abc", "new_db", &none),
+        "mysql --defaults-extra-file=/tmp/tmp.abc new_db"
+    );
+}
@@ -0,0 +1,98 @@
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use forge_common::{
+    database::{DatabaseConfigProvider, DatabaseCredentials, OptionFile},
+    error::AppError,
+    site_type::{LaravelSite, WordPressSite},
+};
+
+fn wp_config(db_host: &str) -> String {
+    format!(
+        "define( 'DB_NAME', 'wp' );\ndefine( 'DB_USER', 'wp_user' );\ndefine( 'DB_PASSWORD', 'secret' );\ndefine( 'DB_HOST', '{}' );\n",
+        db_host
+    )
+}
+
+#[test]
+fn laravel_connection_settings_are_read() {
+    let env = "DB_HOST=10.0.0.5\nDB_PORT=3307\nDB_DATABASE=app\nDB_USERNAME=app\nDB_PASSWORD=\"p#ss word\"\nDB_SOCKET=\n";
+
+    let creds = LaravelSite
+        .parse_database_credentials(env)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(creds.host.as_deref(), Some("10.0.0.5"));
+    assert_eq!(creds.port, Some(3307));
+    assert_eq!(creds.socket, None);
+    assert_eq!(creds.server().as_deref(), Some("10.0.0.5:3307"));
+}
+
+#[test]
+fn laravel_port_has_to_be_a_number() {
+    let env = "DB_PORT=mysql\nDB_DATABASE=app\nDB_USERNAME=app\nDB_PASSWORD=secret\n";
+
+    let result = LaravelSite.parse_database_credentials(env);
+
+    assert!(matches!(result, Err(AppError::CredentialParseError(key)) if key == "DB_PORT"));
+}
+
+#[test]
+fn wordpress_host_can_carry_a_port_or_socket() {
+    let parse = |db_host| {
+        let creds = WordPressSite
+            .parse_database_credentials(&wp_config(db_host))
+            .unwrap()
+            .unwrap();
+        (creds.host, creds.port, creds.socket)
+    };
+
+    assert_eq!(parse("localhost"), (Some("localhost".into()), None, None));
+    assert_eq!(parse("db:3307"), (Some("db".into()), Some(3307), None));
+    assert_eq!(
+        parse("localhost:/run/mysqld/mysqld.sock"),
+        (
+            Some("localhost".into()),
+            None,
+            Some("/run/mysqld/mysqld.sock".into())
+        )
+    );
+}
+
+#[test]
+fn option_files_quote_the_values() {
+    let creds = DatabaseCredentials {
+        username: "app".into(),
+        password: "p#ss \"word\"\\".into(),
+        database: "app".into(),
+        host: Some("db.internal".into()),
+        port: Some(3307),
+        socket: None,
+    };
+
+    assert_eq!(
+        creds.client_options(),
+        "[client]\nuser=\"app\"\npassword=\"p#ss \\\"word\\\"\\\\\"\nhost=\"db.internal\"\nport=3307\n"
+    );
+}
+
+#[test]
+fn option_files_are_private_and_removed_after_use() {
+    let creds = DatabaseCredentials {
+        username: "app".into(),
+        password: "secret".into(),
+        ..Default::default()
+    };
+
+    let option_file = OptionFile::create(&creds).unwrap();
+    let path = option_file.path().to_path_buf();
+
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains("password=\"secret\""));
+
+    drop(option_file);
+    assert!(!path.exists());
+}
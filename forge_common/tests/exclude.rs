@@ -0,0 +1,155 @@
+use std::{fs, path::Path};
+
+use forge_common::{
+    archive, backup,
+    compression::Compression,
+    exclude::{self, Excludes},
+    feedback::Progress,
+    remote::RemoteFolder,
+    runner::LocalAsRemoteRunner,
+    source::SourceFolder,
+    sync::{self, SyncOptions},
+};
+
+mod common;
+
+use common::scratch_dir;
+
+fn excludes(patterns: &[&str]) -> Excludes {
+    Excludes::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+}
+
+fn site(root: &Path) {
+    for (path, content) in [
+        ("artisan", "#!/usr/bin/env php\n"),
+        ("public/index.php", "<?php\n"),
+        ("node_modules/left-pad/index.js", "module.exports = 1;\n"),
+        ("storage/logs/laravel.log", "0123456789"),
+        ("storage/logs/.gitignore", "*\n"),
+    ] {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+#[test]
+fn patterns_follow_gitignore_rules() {
+    let excludes = excludes(&["/node_modules/", "*.log", "!keep.log", "/cache"]);
+
+    assert!(excludes.is_excluded(Path::new("node_modules"), true));
+    assert!(excludes.is_excluded(Path::new("node_modules/a/b.js"), false));
+    assert!(!excludes.is_excluded(Path::new("app/node_modules"), true));
+    assert!(excludes.is_excluded(Path::new("storage/logs/laravel.log"), false));
+    assert!(!excludes.is_excluded(Path::new("storage/logs/keep.log"), false));
+    assert!(excludes.is_excluded(Path::new("cache/data/x"), false));
+    assert!(!excludes.is_excluded(Path::new("public/cache"), true));
+}
+
+#[test]
+fn ignore_files_skip_comments_and_blank_lines() {
+    let patterns = exclude::parse_ignore_file("# uploads\n/public/uploads/\n\n  \n*.bak  \n");
+
+    assert_eq!(patterns, vec!["/public/uploads/", "*.bak"]);
+}
+
+#[test]
+fn invalid_patterns_are_rejected() {
+    assert!(Excludes::new(&["/cache/{a,b".to_string()]).is_err());
+}
+
+#[test]
+fn archives_count_what_was_left_out() {
+    let dir = scratch_dir("archive");
+    let source = dir.join("source");
+    site(&source);
+
+    let totals = archive::create_archive(
+        &source,
+        &dir.join("site.tar.gz"),
+        &Compression::default(),
+        &excludes(&["/node_modules/", "*.log"]),
+        |_, _| {},
+    )
+    .unwrap();
+
+    // node_modules, left-pad, index.js and laravel.log
+    assert_eq!((totals.excluded_files, totals.excluded_bytes), (4, 20 + 10));
+    archive::extract_archive(&dir.join("site.tar.gz"), &dir.join("out"), |_, _| {}).unwrap();
+    assert!(dir.join("out/public/index.php").exists());
+    assert!(dir.join("out/storage/logs/.gitignore").exists());
+    assert!(!dir.join("out/node_modules").exists());
+    assert!(!dir.join("out/storage/logs/laravel.log").exists());
+}
+
+#[test]
+fn remote_archives_leave_out_excluded_paths() {
+    let dir = scratch_dir("remote");
+    site(&dir.join("source"));
+    fs::create_dir_all(dir.join("dest")).unwrap();
+    let runner = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+    let compression = Compression::default();
+    let archive = dir.join("files.tar.gz");
+
+    let totals = backup::backup_remote_files(
+        RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: "source",
+        },
+        &compression,
+        &excludes(&["/node_modules/", "*.log"]),
+        &archive,
+        &Progress::default(),
+    )
+    .unwrap();
+    backup::restore_files(
+        &runner,
+        &archive,
+        None,
+        "dest",
+        &compression,
+        &Progress::default(),
+    )
+    .unwrap();
+
+    assert_eq!((totals.excluded_files, totals.excluded_bytes), (4, 20 + 10));
+    assert!(dir.join("dest/artisan").exists());
+    assert!(dir.join("dest/storage/logs/.gitignore").exists());
+    assert!(!dir.join("dest/node_modules").exists());
+    assert!(!dir.join("dest/storage/logs/laravel.log").exists());
+}
+
+#[test]
+fn syncs_leave_excluded_destination_paths_alone() {
+    let dir = scratch_dir("sync");
+    let source_root = dir.join("source");
+    site(&source_root);
+    fs::create_dir_all(dir.join("dest/storage/logs")).unwrap();
+    fs::write(dir.join("dest/storage/logs/worker.log"), "running").unwrap();
+    let runner = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+    let source_path = source_root.to_str().unwrap().to_string();
+    let options = SyncOptions {
+        delete: true,
+        ..Default::default()
+    };
+
+    let summary = sync::sync_files(
+        SourceFolder::Local(&source_path),
+        RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: "dest",
+        },
+        &Compression::default(),
+        &options,
+        &excludes(&["/node_modules/", "*.log"]),
+        &Progress::default(),
+    )
+    .unwrap();
+
+    assert_eq!(summary.deleted, 0);
+    assert_eq!(summary.excluded_files, 4);
+    assert!(dir.join("dest/storage/logs/worker.log").exists());
+    assert!(!dir.join("dest/node_modules").exists());
+}
@@ -0,0 +1,60 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use forge_common::feedback::{self, Progress, ProgressReader, ProgressWriter};
+
+#[test]
+fn progress_shows_throughput_and_time_left() {
+    let line = feedback::format_progress(1 << 30, Some(4 << 30), Duration::from_secs(80));
+
+    assert_eq!(line, "1.0 GB of 4.0 GB (25%), 12.8 MB/s, 4m 00s left");
+}
+
+#[test]
+fn progress_without_a_total_only_counts() {
+    assert_eq!(
+        feedback::format_progress(3 << 20, None, Duration::from_secs(2)),
+        "3.0 MB, 1.5 MB/s"
+    );
+    // No rate until a second has gone by.
+    assert_eq!(
+        feedback::format_progress(512, Some(1024), Duration::from_millis(300)),
+        "512 B of 1.0 KB (50%)"
+    );
+}
+
+#[test]
+fn an_underestimated_total_stops_at_full() {
+    let line = feedback::format_progress(150, Some(100), Duration::from_secs(1));
+
+    assert_eq!(line, "150 B of 100 B (100%), 150 B/s");
+}
+
+#[test]
+fn durations_are_short() {
+    assert_eq!(feedback::format_duration(Duration::from_secs(45)), "45s");
+    assert_eq!(
+        feedback::format_duration(Duration::from_secs(245)),
+        "4m 05s"
+    );
+    assert_eq!(
+        feedback::format_duration(Duration::from_secs(3720)),
+        "1h 02m"
+    );
+}
+
+#[test]
+fn readers_and_writers_count_into_the_progress() {
+    let progress = Progress::default();
+    progress.set_total(22);
+
+    let mut input = ProgressReader::new(&b"hello world"[..], &progress);
+    let mut output = ProgressWriter::new(io::sink(), &progress);
+    io::copy(&mut input, &mut output).unwrap();
+    output.flush().unwrap();
+    input.read_to_end(&mut vec![]).unwrap();
+
+    assert_eq!((progress.bytes(), progress.total()), (22, Some(22)));
+}
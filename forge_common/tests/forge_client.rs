@@ -0,0 +1,129 @@
+use forge_common::forge::{
+    server::FORGE_USER, site::CreateSiteRequest, Deletion, ForgeClient, ForgeClientOptions,
+};
+use forge_mock::MockForge;
+
+fn client(forge: &MockForge, api_key: &str) -> ForgeClient {
+    let options = ForgeClientOptions {
+        base_url: Some(forge.url().into()),
+        max_retries: Some(0),
+        requests_per_minute: Some(0),
+        ..Default::default()
+    };
+    ForgeClient::with_options(api_key, &options).unwrap()
+}
+
+#[test]
+fn finds_servers_by_name() {
+    let forge = MockForge::start();
+    forge.add_server("staging", "10.0.0.1");
+    let id = forge.add_server("production", "10.0.0.2");
+
+    let server = client(&forge, "test-key")
+        .find_server_by_name("production")
+        .unwrap();
+
+    assert_eq!(server.id, id);
+    assert_eq!(server.ssh_host(FORGE_USER), "forge@10.0.0.2");
+    assert_eq!(server.ssh_host("deploy"), "deploy@10.0.0.2");
+}
+
+#[test]
+fn rejected_api_key_is_unauthorized() {
+    let forge = MockForge::start();
+
+    let result = client(&forge, "wrong-key").list_servers();
+
+    assert!(result.is_err_and(|e| e.forge_error().is_some_and(|e| e.is_unauthorized())));
+}
+
+#[test]
+fn created_site_becomes_ready() {
+    let forge = MockForge::start();
+    forge.set_install_polls(1);
+    let server_id = forge.add_server("web", "10.0.0.1").to_string();
+    let client = client(&forge, "test-key");
+
+    let site = client
+        .create_site(
+            &server_id,
+            &CreateSiteRequest {
+                domain: "example.com".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+        .site;
+
+    assert_eq!(site.status, "installing");
+    assert_eq!(site.root_directory(), "/home/forge/example.com");
+    assert_eq!(
+        client
+            .get_site_status(&server_id, &site.id.to_string())
+            .unwrap(),
+        "installing"
+    );
+    assert_eq!(
+        client
+            .get_site_status(&server_id, &site.id.to_string())
+            .unwrap(),
+        "installed"
+    );
+}
+
+#[test]
+fn deletes_are_idempotent() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "10.0.0.1");
+    let site_id = forge.add_site(server_id, "example.com").to_string();
+    let client = client(&forge, "test-key");
+
+    assert_eq!(
+        client
+            .delete_site(&server_id.to_string(), &site_id)
+            .unwrap(),
+        Deletion::Removed
+    );
+    assert_eq!(
+        client
+            .delete_site(&server_id.to_string(), &site_id)
+            .unwrap(),
+        Deletion::AlreadyAbsent
+    );
+    assert!(forge.site_names(server_id).is_empty());
+}
+
+#[test]
+fn deleting_by_name_reports_missing_resources_as_absent() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "10.0.0.1");
+    let database_id = forge.add_database(server_id, "app");
+    forge.add_user(server_id, "app", &[database_id]);
+    let client = client(&forge, "test-key");
+    let server_id_text = server_id.to_string();
+
+    assert_eq!(
+        client
+            .delete_database_by_name(&server_id_text, "app")
+            .unwrap(),
+        Deletion::Removed
+    );
+    assert_eq!(
+        client
+            .delete_database_by_name(&server_id_text, "app")
+            .unwrap(),
+        Deletion::AlreadyAbsent
+    );
+    assert_eq!(
+        client.delete_user_by_name(&server_id_text, "app").unwrap(),
+        Deletion::Removed
+    );
+    assert_eq!(
+        client
+            .delete_user_by_name(&server_id_text, "missing")
+            .unwrap(),
+        Deletion::AlreadyAbsent
+    );
+    assert!(forge.database_names(server_id).is_empty());
+    assert!(forge.user_names(server_id).is_empty());
+}
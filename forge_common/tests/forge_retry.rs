@@ -0,0 +1,168 @@
+use std::time::{Duration, Instant};
+
+use forge_common::{
+    error::AppError,
+    forge::{database::CreateDatabaseRequest, error::ForgeError, ForgeClient, ForgeClientOptions},
+};
+use forge_mock::{Fault, MockForge};
+
+fn client(forge: &MockForge) -> ForgeClient {
+    let options = ForgeClientOptions {
+        base_url: Some(forge.url().into()),
+        max_retries: Some(3),
+        requests_per_minute: Some(0),
+        ..Default::default()
+    };
+    let mut client = ForgeClient::with_options("test-key", &options).unwrap();
+    client.retry.base_delay = Duration::from_millis(10);
+    client
+}
+
+fn create_database_request() -> CreateDatabaseRequest {
+    CreateDatabaseRequest {
+        name: "app".into(),
+        user: "forge".into(),
+        password: "secret".into(),
+    }
+}
+
+#[test]
+fn get_is_retried_after_server_error() {
+    let forge = MockForge::start();
+    forge.inject("GET", "/servers", Fault::Status(503), 1);
+    forge.inject("GET", "/servers", Fault::Status(502), 1);
+
+    let result = client(&forge).list_servers();
+
+    assert!(result.is_ok());
+    assert_eq!(forge.count("GET", "/api/v1/servers"), 3);
+}
+
+#[test]
+fn gives_up_after_max_retries() {
+    let forge = MockForge::start();
+    forge.inject("GET", "/servers", Fault::Status(500), 4);
+
+    let result = client(&forge).list_servers();
+
+    assert!(matches!(
+        result,
+        Err(AppError::ForgeResponseError(ForgeError::Server {
+            status: 500,
+            ..
+        }))
+    ));
+    assert_eq!(forge.count("GET", "/api/v1/servers"), 4);
+}
+
+#[test]
+fn rate_limited_request_waits_for_retry_after() {
+    let forge = MockForge::start();
+    forge.inject("GET", "/servers", Fault::RateLimited { retry_after: 1 }, 1);
+
+    let started = Instant::now();
+    let result = client(&forge).list_servers();
+
+    assert!(result.is_ok());
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(forge.count("GET", "/api/v1/servers"), 2);
+}
+
+#[test]
+fn post_is_not_retried_after_server_error() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    let path = format!("/servers/{}/databases", server_id);
+    forge.inject("POST", &path, Fault::Status(500), 1);
+
+    let result = client(&forge).create_database(&server_id.to_string(), &create_database_request());
+
+    assert!(matches!(
+        result,
+        Err(AppError::ForgeResponseError(ForgeError::Server { .. }))
+    ));
+    assert_eq!(forge.count("POST", &format!("/api/v1{}", path)), 1);
+    assert!(forge.database_names(server_id).is_empty());
+}
+
+#[test]
+fn post_is_retried_when_rate_limited() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    let path = format!("/servers/{}/databases", server_id);
+    forge.inject("POST", &path, Fault::RateLimited { retry_after: 0 }, 1);
+
+    let result = client(&forge).create_database(&server_id.to_string(), &create_database_request());
+
+    assert_eq!(result.unwrap().database.name, "app");
+    assert_eq!(forge.count("POST", &format!("/api/v1{}", path)), 2);
+    assert_eq!(forge.database_names(server_id), vec!["app"]);
+}
+
+#[test]
+fn requests_are_throttled() {
+    let forge = MockForge::start();
+    let client = client(&forge).with_rate_limit(600);
+
+    let started = Instant::now();
+    for _ in 0..3 {
+        client.list_servers().unwrap();
+    }
+
+    // 600 a minute leaves 100ms between requests.
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn validation_errors_are_parsed_by_field() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    forge.add_database(server_id, "app");
+
+    let result = client(&forge).create_database(&server_id.to_string(), &create_database_request());
+
+    match result {
+        Err(AppError::ForgeResponseError(ForgeError::Validation { errors, .. })) => {
+            assert_eq!(errors["name"], vec!["The name has already been taken."]);
+        }
+        other => panic!("expected a validation error, got {:?}", other),
+    }
+}
+
+#[test]
+fn api_version_and_base_url_are_configurable() {
+    let forge = MockForge::start();
+    let options = ForgeClientOptions {
+        base_url: Some(format!("{}/", forge.url())),
+        api_version: Some("v2".into()),
+        requests_per_minute: Some(0),
+        ..Default::default()
+    };
+
+    let result = ForgeClient::with_options("test-key", &options)
+        .unwrap()
+        .list_servers();
+
+    // The mock only speaks v1.
+    assert!(result.is_err_and(|e| e.forge_error().is_some_and(|e| e.is_not_found())));
+    assert_eq!(forge.count("GET", "/api/v2/servers"), 1);
+}
+
+#[test]
+fn slow_responses_time_out() {
+    let forge = MockForge::start();
+    forge.inject("GET", "/servers", Fault::Slow(Duration::from_secs(3)), 1);
+    let options = ForgeClientOptions {
+        base_url: Some(forge.url().into()),
+        timeout: Some(1),
+        max_retries: Some(0),
+        requests_per_minute: Some(0),
+        ..Default::default()
+    };
+
+    let result = ForgeClient::with_options("test-key", &options)
+        .unwrap()
+        .list_servers();
+
+    assert!(matches!(result, Err(AppError::ReqwestError(e)) if e.is_timeout()));
+}
@@ -0,0 +1,335 @@
+use std::{fs, path::Path};
+
+use forge_common::{
+    backup,
+    compression::Compression,
+    feedback::Progress,
+    remote::RemoteFolder,
+    runner::{CommandRunner, Stdin, Stdout},
+    ssh::{native::NativeRunner, HostKeyChecking, SshOptions, SshTransport},
+};
+use forge_mock::{MockSsh, SshRequest};
+
+mod common;
+
+use common::scratch_dir;
+
+/// A mock server with an authorized key and a `known_hosts` file listing it.
+fn server(dir: &Path) -> (MockSsh, SshOptions) {
+    let ssh = MockSsh::start(&dir.join("remote"));
+    ssh.authorized_key(&dir.join("id_ed25519"));
+    fs::write(
+        dir.join("known_hosts"),
+        format!("{}\n", ssh.known_hosts_line()),
+    )
+    .unwrap();
+
+    let options = SshOptions {
+        port: Some(ssh.port()),
+        identity_file: Some(dir.join("id_ed25519").display().to_string()),
+        host_key_checking: Some(HostKeyChecking::Yes),
+        options: vec![format!(
+            "UserKnownHostsFile={}",
+            dir.join("known_hosts").display()
+        )],
+        transport: SshTransport::Native,
+        ..Default::default()
+    };
+
+    (ssh, options)
+}
+
+#[test]
+fn commands_stream_their_input_output_and_exit_code() {
+    let dir = scratch_dir("exec");
+    let (ssh, options) = server(&dir);
+    let runner = NativeRunner::new("forge@127.0.0.1", &options);
+
+    let output = runner
+        .run(
+            "cat; echo 'disk full' >&2; exit 3",
+            Stdin::Bytes(b"hello"),
+            Stdout::Capture,
+        )
+        .unwrap();
+
+    assert_eq!(output.code, Some(3));
+    assert_eq!(output.stdout_string(), "hello");
+    assert_eq!(output.stderr, "disk full\n");
+    assert_eq!(
+        ssh.requests(),
+        vec![SshRequest::Exec {
+            user: "forge".into(),
+            command: "cat; echo 'disk full' >&2; exit 3".into(),
+        }]
+    );
+
+    let failure = runner
+        .run_checked(
+            "echo 'no such table' >&2; exit 1",
+            Stdin::Null,
+            Stdout::Null,
+        )
+        .unwrap_err();
+    assert!(failure.to_string().contains("no such table"));
+}
+
+#[test]
+fn large_inputs_and_outputs_go_through_in_chunks() {
+    let dir = scratch_dir("large");
+    let (_ssh, options) = server(&dir);
+    let runner = NativeRunner::new("forge@127.0.0.1", &options);
+    let input: Vec<u8> = (0..3_000_000).map(|i| (i % 251) as u8).collect();
+    let mut reader = input.as_slice();
+    let mut echoed = Vec::new();
+
+    let output = runner
+        .run(
+            "cat",
+            Stdin::Reader(&mut reader),
+            Stdout::Writer(&mut echoed),
+        )
+        .unwrap();
+
+    assert!(output.success());
+    assert_eq!(echoed, input);
+}
+
+#[test]
+fn the_ssh_user_wins_over_the_one_in_the_host() {
+    let dir = scratch_dir("user");
+    let (ssh, options) = server(&dir);
+    let options = SshOptions {
+        user: Some("deploy".into()),
+        ..options
+    };
+
+    NativeRunner::new("forge@127.0.0.1", &options)
+        .run_checked("true", Stdin::Null, Stdout::Null)
+        .unwrap();
+
+    assert_eq!(
+        ssh.requests(),
+        vec![SshRequest::Exec {
+            user: "deploy".into(),
+            command: "true".into(),
+        }]
+    );
+}
+
+#[test]
+fn unknown_host_keys_are_refused_with_the_reason() {
+    let dir = scratch_dir("unknown_host");
+    let (ssh, options) = server(&dir);
+    fs::write(dir.join("known_hosts"), "").unwrap();
+
+    let error = NativeRunner::new("forge@127.0.0.1", &options)
+        .run("true", Stdin::Null, Stdout::Null)
+        .unwrap_err();
+
+    let message = error.to_string();
+    assert!(message.contains("isn't in"), "{}", message);
+    assert!(message.contains("known_hosts"), "{}", message);
+    assert!(ssh.requests().is_empty());
+}
+
+#[test]
+fn new_host_keys_are_learned_with_accept_new() {
+    let dir = scratch_dir("accept_new");
+    let (ssh, options) = server(&dir);
+    fs::write(dir.join("known_hosts"), "").unwrap();
+    let options = SshOptions {
+        host_key_checking: Some(HostKeyChecking::AcceptNew),
+        ..options
+    };
+
+    NativeRunner::new("forge@127.0.0.1", &options)
+        .run_checked("true", Stdin::Null, Stdout::Null)
+        .unwrap();
+
+    let known_hosts = fs::read_to_string(dir.join("known_hosts")).unwrap();
+    assert_eq!(known_hosts.trim(), ssh.known_hosts_line());
+}
+
+#[test]
+fn changed_host_keys_are_refused_even_with_accept_new() {
+    let dir = scratch_dir("changed_host");
+    let (_ssh, options) = server(&dir);
+    let other = MockSsh::start(&dir.join("other"));
+    let stale = other.known_hosts_line().replace(
+        &other.port().to_string(),
+        &options.port.unwrap().to_string(),
+    );
+    fs::write(dir.join("known_hosts"), format!("{}\n", stale)).unwrap();
+    let options = SshOptions {
+        host_key_checking: Some(HostKeyChecking::AcceptNew),
+        ..options
+    };
+
+    let error = NativeRunner::new("forge@127.0.0.1", &options)
+        .run("true", Stdin::Null, Stdout::Null)
+        .unwrap_err();
+
+    let message = error.to_string();
+    assert!(message.contains("has changed"), "{}", message);
+    assert!(message.contains("line 1"), "{}", message);
+}
+
+#[test]
+fn rejected_keys_say_what_was_tried() {
+    let dir = scratch_dir("rejected_key");
+    let (ssh, options) = server(&dir);
+    forge_mock::write_key(&dir.join("stranger"));
+    let options = SshOptions {
+        identity_file: Some(dir.join("stranger").display().to_string()),
+        ..options
+    };
+
+    let error = NativeRunner::new("forge@127.0.0.1", &options)
+        .run("true", Stdin::Null, Stdout::Null)
+        .unwrap_err();
+
+    let message = error.to_string();
+    assert!(
+        message.contains("Permission denied for forge@127.0.0.1"),
+        "{}",
+        message
+    );
+    assert!(message.contains("stranger"), "{}", message);
+    assert!(ssh.requests().is_empty());
+}
+
+#[test]
+fn uploads_go_over_sftp() {
+    let dir = scratch_dir("upload");
+    let (ssh, options) = server(&dir);
+    let local = dir.join("backup.sql");
+    fs::write(&local, "CREATE TABLE users (id int);\n").unwrap();
+
+    NativeRunner::new("forge@127.0.0.1", &options)
+        .upload(Stdin::File(&local), "backup.sql")
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dir.join("remote/backup.sql")).unwrap(),
+        "CREATE TABLE users (id int);\n"
+    );
+    assert_eq!(
+        ssh.requests(),
+        vec![SshRequest::Upload {
+            user: "forge".into(),
+            path: "backup.sql".into(),
+        }]
+    );
+}
+
+#[test]
+fn connections_can_go_through_a_jump_host() {
+    let dir = scratch_dir("jump");
+    let (ssh, options) = server(&dir);
+    let options = SshOptions {
+        jump_host: Some(format!("ops@127.0.0.1:{}", ssh.port())),
+        ..options
+    };
+
+    let output = NativeRunner::new("forge@127.0.0.1", &options)
+        .run_checked("echo through", Stdin::Null, Stdout::Capture)
+        .unwrap();
+
+    assert_eq!(output.stdout_string(), "through\n");
+    assert_eq!(
+        ssh.requests(),
+        vec![
+            SshRequest::Forward {
+                user: "ops".into(),
+                host: "127.0.0.1".into(),
+                port: ssh.port() as u32,
+            },
+            SshRequest::Exec {
+                user: "forge".into(),
+                command: "echo through".into(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn ssh_user_is_for_the_target_not_the_jump_host() {
+    let dir = scratch_dir("jump_user");
+    let (ssh, options) = server(&dir);
+    let options = SshOptions {
+        user: Some("deploy".into()),
+        jump_host: Some(format!("ops@127.0.0.1:{}", ssh.port())),
+        ..options
+    };
+
+    NativeRunner::new("127.0.0.1", &options)
+        .run_checked("true", Stdin::Null, Stdout::Null)
+        .unwrap();
+
+    assert_eq!(
+        ssh.requests(),
+        vec![
+            SshRequest::Forward {
+                user: "ops".into(),
+                host: "127.0.0.1".into(),
+                port: ssh.port() as u32,
+            },
+            SshRequest::Exec {
+                user: "deploy".into(),
+                command: "true".into(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn site_files_round_trip_through_the_native_transport() {
+    let dir = scratch_dir("round_trip");
+    let (ssh, options) = server(&dir);
+    let site = dir.join("remote/example.com");
+    fs::create_dir_all(site.join("public")).unwrap();
+    fs::write(site.join("public/index.php"), "<?php echo 'hi';\n").unwrap();
+    fs::create_dir_all(dir.join("remote/new.example.com")).unwrap();
+    let runner = NativeRunner::new("forge@127.0.0.1", &options);
+    let compression = Compression::default();
+    let archive = dir.join("files.tar.gz");
+
+    backup::backup_remote_files(
+        RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: "example.com",
+        },
+        &compression,
+        &Default::default(),
+        &archive,
+        &Progress::default(),
+    )
+    .unwrap();
+    backup::restore_files(
+        &runner,
+        &archive,
+        None,
+        "new.example.com",
+        &compression,
+        &Progress::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(dir.join("remote/new.example.com/public/index.php")).unwrap(),
+        "<?php echo 'hi';\n"
+    );
+    // The archive is uploaded over SFTP and removed once extracted.
+    let uploaded: Vec<String> = ssh
+        .requests()
+        .into_iter()
+        .filter_map(|request| match request {
+            SshRequest::Upload { path, .. } => Some(path),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(uploaded.len(), 1);
+    assert!(!Path::new(&uploaded[0]).exists());
+}
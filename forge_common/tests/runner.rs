@@ -0,0 +1,343 @@
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use forge_common::{
+    backup,
+    compression::{Codec, Compression},
+    database::DatabaseCredentials,
+    error::{AppError, AppResult},
+    exclude::Excludes,
+    feedback::Progress,
+    remote::{self, RemoteFolder},
+    runner::{CommandOutput, CommandRunner, LocalAsRemoteRunner, RecordingRunner, Stdin, Stdout},
+    shell,
+    site_type::{LaravelSite, SiteType},
+};
+
+mod common;
+
+use common::scratch_dir;
+
+fn creds() -> DatabaseCredentials {
+    DatabaseCredentials {
+        username: "app".into(),
+        password: "secret".into(),
+        database: "app_db".into(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn remote_database_backup_is_written_to_the_archive() {
+    let dir = scratch_dir("backup_db");
+    let archive = dir.join("2024-10-15/app-db.sql.gz");
+    let runner = RecordingRunner::new("forge@10.0.0.1");
+    runner.respond("mktemp", 0, "/tmp/tmp.abc\n");
+    runner.respond("mysqldump", 0, "dump");
+
+    backup::backup_remote_database(
+        &runner,
+        &creds(),
+        &Compression::default(),
+        &archive,
+        &Progress::default(),
+    )
+    .unwrap();
+
+    let calls = runner.calls();
+    assert_eq!(
+        runner.commands(),
+        vec![
+            remote::OPTION_FILE_COMMAND,
+            shell::pipe(
+                "mysqldump --defaults-extra-file=/tmp/tmp.abc app_db --no-tablespaces",
+                "gzip -c -6"
+            )
+            .as_str(),
+            "rm -f /tmp/tmp.abc",
+        ]
+    );
+    assert!(String::from_utf8_lossy(&calls[0].stdin).contains("password=\"secret\""));
+    assert!(calls.iter().all(|call| !call.command.contains("secret")));
+    assert_eq!(fs::read_to_string(&archive).unwrap(), "dump");
+}
+
+/// Runs commands from its folder with the program named swapped for the
+/// script of that name in there.
+struct Scripted(LocalAsRemoteRunner, &'static str);
+
+impl Scripted {
+    fn new(dir: &std::path::Path, program: &'static str, script: &str) -> Self {
+        let path = dir.join(program);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        Scripted(LocalAsRemoteRunner::new("forge@10.0.0.1", dir), program)
+    }
+}
+
+impl CommandRunner for Scripted {
+    fn host(&self) -> &str {
+        self.0.host()
+    }
+
+    fn program(&self) -> &str {
+        self.0.program()
+    }
+
+    fn run(&self, command: &str, stdin: Stdin, stdout: Stdout) -> AppResult<CommandOutput> {
+        let command = command.replace(&format!("{} ", self.1), &format!("./{} ", self.1));
+        self.0.run(&command, stdin, stdout)
+    }
+}
+
+#[test]
+fn failed_remote_dump_is_an_error_whatever_the_codec() {
+    for codec in [Codec::Gzip, Codec::Zstd, Codec::None] {
+        let dir = scratch_dir(&format!("failed_dump_{}", codec));
+        let runner = Scripted::new(
+            &dir,
+            "mysqldump",
+            "#!/bin/sh\necho 'CREATE TABLE'\necho 'Access denied' >&2\nexit 2\n",
+        );
+
+        let result = backup::backup_remote_database(
+            &runner,
+            &creds(),
+            &Compression::new(codec, None, None).unwrap(),
+            &dir.join("app-db.sql"),
+            &Progress::default(),
+        );
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("Access denied"), "{}: {}", codec, error);
+    }
+}
+
+#[test]
+fn corrupt_database_archive_is_not_restored_whatever_the_codec() {
+    for codec in [Codec::Gzip, Codec::Zstd] {
+        let dir = scratch_dir(&format!("corrupt_restore_{}", codec));
+        let archive = dir.join("db.sql");
+        fs::write(&archive, "not compressed").unwrap();
+        let runner = Scripted::new(&dir, "mysql", "#!/bin/sh\ncat > /dev/null\n");
+
+        let result = backup::restore_database(
+            &runner,
+            &archive,
+            &creds(),
+            &Compression::new(codec, None, None).unwrap(),
+            &Progress::default(),
+        );
+
+        assert!(
+            matches!(result, Err(AppError::CommandError(..))),
+            "{}: {:?}",
+            codec,
+            result
+        );
+    }
+}
+
+#[test]
+fn files_are_archived_as_the_isolated_user() {
+    let dir = scratch_dir("backup_files");
+    let runner = RecordingRunner::new("forge@10.0.0.1");
+
+    backup::backup_remote_files(
+        RemoteFolder {
+            runner: &runner,
+            user_name: Some("app"),
+            path: "/home/app/example.com",
+        },
+        &Compression::default(),
+        &Excludes::default(),
+        &dir.join("files.tar.gz"),
+        &Progress::default(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        runner.commands(),
+        vec!["sudo -u app tar -I 'gzip -6' -cpf - -C /home/app/example.com ."]
+    );
+}
+
+#[test]
+fn restores_feed_the_archive_to_the_remote_command() {
+    let dir = scratch_dir("restore");
+    let archive = dir.join("db.sql.gz");
+    fs::write(&archive, "archive").unwrap();
+    let runner = RecordingRunner::new("forge@10.0.0.2");
+    runner.respond("mktemp", 0, "/tmp/tmp.abc\n");
+
+    let compression = Compression::default();
+
+    let progress = Progress::default();
+
+    backup::restore_database(&runner, &archive, &creds(), &compression, &progress).unwrap();
+    backup::restore_files(
+        &runner,
+        &archive,
+        Some("app"),
+        "/home/app/new.com",
+        &compression,
+        &Progress::default(),
+    )
+    .unwrap();
+
+    let calls = runner.calls();
+    assert_eq!(
+        calls[1].command,
+        shell::pipe(
+            "gunzip -c",
+            "mysql --defaults-extra-file=/tmp/tmp.abc app_db"
+        )
+    );
+    assert_eq!(calls[1].stdin, b"archive");
+    assert_eq!(calls[2].command, "rm -f /tmp/tmp.abc");
+    assert_eq!((progress.bytes(), progress.total()), (7, Some(7)));
+    assert_eq!(
+        calls[3].command,
+        "sudo -u app tar -z -xpvf - -C /home/app/new.com"
+    );
+}
+
+#[test]
+fn failed_restore_is_an_error() {
+    let dir = scratch_dir("restore_failed");
+    let archive = dir.join("db.sql.gz");
+    fs::write(&archive, "archive").unwrap();
+    let runner = RecordingRunner::new("forge@10.0.0.2");
+    runner.respond("mktemp", 0, "/tmp/tmp.abc\n");
+    runner.respond("mysql", 1, "");
+
+    let result = backup::restore_database(
+        &runner,
+        &archive,
+        &creds(),
+        &Compression::default(),
+        &Progress::default(),
+    );
+
+    assert!(matches!(result, Err(AppError::CommandError(..))));
+    // The option file is removed even when the restore fails.
+    assert_eq!(runner.commands().last().unwrap(), "rm -f /tmp/tmp.abc");
+}
+
+#[test]
+fn missing_archive_is_not_restored() {
+    let runner = RecordingRunner::new("forge@10.0.0.2");
+
+    let result = backup::restore_files(
+        &runner,
+        &scratch_dir("missing").join("files.tar.gz"),
+        None,
+        "/home/forge/new.com",
+        &Compression::default(),
+        &Progress::default(),
+    );
+
+    assert!(matches!(result, Err(AppError::FileError(..))));
+    assert!(runner.commands().is_empty());
+}
+
+#[test]
+fn path_exists_reads_the_exit_code() {
+    let runner = RecordingRunner::new("forge@10.0.0.1");
+    runner.respond("test -e /present", 0, "");
+    runner.respond("test -e /missing", 1, "");
+    runner.respond("test -e /unreachable", 255, "");
+
+    assert!(remote::path_exists(&runner, None, "/present").unwrap());
+    assert!(!remote::path_exists(&runner, None, "/missing").unwrap());
+    assert!(remote::path_exists(&runner, None, "/unreachable").is_err());
+}
+
+#[test]
+fn remote_config_is_rewritten_in_place() {
+    let runner = RecordingRunner::new("forge@10.0.0.2");
+    runner.respond(
+        "cat /home/forge/new.com/.env",
+        0,
+        "DB_DATABASE=old\nDB_USERNAME=old\nDB_PASSWORD=old\n",
+    );
+
+    backup::rewrite_remote_config(
+        &SiteType::Laravel(LaravelSite),
+        &runner,
+        None,
+        "/home/forge/new.com/",
+        &creds(),
+        None,
+    )
+    .unwrap();
+
+    let calls = runner.calls();
+    assert_eq!(calls[1].command, "tee /home/forge/new.com/.env > /dev/null");
+    assert_eq!(
+        String::from_utf8_lossy(&calls[1].stdin),
+        "DB_DATABASE=app_db\nDB_USERNAME=app\nDB_PASSWORD=secret\n"
+    );
+}
+
+#[test]
+fn local_as_remote_runs_commands_from_its_root() {
+    let dir = scratch_dir("local_as_remote");
+    let runner = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+
+    remote::write_file(&runner, None, "notes.txt", "hello").unwrap();
+    let output = runner
+        .run_checked(
+            "pwd && echo $HOME && cat notes.txt",
+            Stdin::Null,
+            Stdout::Capture,
+        )
+        .unwrap();
+
+    let dir = dir.display().to_string();
+    assert_eq!(output.stdout_string(), format!("{}\n{}\nhello", dir, dir));
+    assert_eq!(runner.host(), "forge@10.0.0.1");
+}
+
+#[test]
+fn files_round_trip_through_local_as_remote() {
+    for codec in [Codec::Gzip, Codec::Zstd, Codec::None] {
+        let dir = scratch_dir(&format!("round_trip_{}", codec));
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("public")).unwrap();
+        fs::write(source.join("public/index.html"), "<h1>hi</h1>").unwrap();
+        fs::create_dir_all(dir.join("dest")).unwrap();
+        let runner = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+        let compression = Compression::new(codec, None, Some(2)).unwrap();
+        let archive = dir.join(format!("files.tar{}", codec.extension()));
+
+        let source = RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: "source",
+        };
+        let progress = Progress::default();
+        backup::backup_remote_files(
+            source,
+            &compression,
+            &Excludes::default(),
+            &archive,
+            &progress,
+        )
+        .unwrap();
+        assert_eq!(progress.bytes(), fs::metadata(&archive).unwrap().len());
+        backup::restore_files(
+            &runner,
+            &archive,
+            None,
+            "dest",
+            &compression,
+            &Progress::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("dest/public/index.html")).unwrap(),
+            "<h1>hi</h1>"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+
+use forge_common::{
+    backup,
+    compression::Compression,
+    error::AppError,
+    feedback::Progress,
+    remote::RemoteFolder,
+    runner::{CommandRunner, LocalAsRemoteRunner, LocalRunner, Stdin, Stdout},
+    shell::{self, RemoteCommand},
+};
+
+mod common;
+
+use common::scratch_dir;
+
+#[test]
+fn only_words_that_need_it_are_quoted() {
+    assert_eq!(
+        shell::quote("/home/forge/example.com"),
+        "/home/forge/example.com"
+    );
+    assert_eq!(shell::quote("my site"), "'my site'");
+    assert_eq!(shell::quote("it's"), "'it'\\''s'");
+    assert_eq!(shell::quote("$(reboot)"), "'$(reboot)'");
+    assert_eq!(shell::quote(""), "''");
+}
+
+#[test]
+fn commands_run_as_the_isolated_user() {
+    let command = RemoteCommand::new("cat")
+        .arg("/home/app/my site/.env")
+        .raw("> /dev/null")
+        .as_user(Some("app"));
+
+    assert_eq!(
+        command.to_string(),
+        "sudo -u app cat '/home/app/my site/.env' > /dev/null"
+    );
+}
+
+#[test]
+fn quoted_arguments_reach_the_program_unchanged() {
+    let args = [
+        "plain",
+        "two words",
+        "it's",
+        "$HOME",
+        "a;b",
+        "*",
+        "back\\slash",
+    ];
+    let command = RemoteCommand::new("printf").arg("%s\\n").args(args);
+
+    let output = LocalRunner
+        .run_checked(&command.to_string(), Stdin::Null, Stdout::Capture)
+        .unwrap();
+
+    assert_eq!(output.stdout_string(), format!("{}\n", args.join("\n")));
+}
+
+#[test]
+fn pipes_fail_when_either_side_does() {
+    let run = |producer: &str, consumer: &str| {
+        LocalRunner
+            .run(
+                &shell::pipe(producer, consumer),
+                Stdin::Bytes(b"in\n"),
+                Stdout::Capture,
+            )
+            .unwrap()
+    };
+
+    let output = run("cat; echo out", "tr a-z A-Z");
+    assert!(output.success());
+    assert_eq!(output.stdout_string(), "IN\nOUT\n");
+
+    let output = run("echo partial; exit 3", "cat");
+    assert_eq!(output.code, Some(3));
+    assert_eq!(output.stdout_string(), "partial\n");
+
+    assert_eq!(run("echo out", "cat > /dev/null; exit 5").code, Some(5));
+}
+
+#[test]
+fn folders_with_spaces_and_shell_syntax_are_copied() {
+    let dir = scratch_dir("copy");
+    let source = "old site; touch pwned";
+    fs::create_dir_all(dir.join(source)).unwrap();
+    fs::write(dir.join(source).join("index.php"), "<?php\n").unwrap();
+    fs::create_dir_all(dir.join("new $(touch pwned) site")).unwrap();
+    let runner = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+    let compression = Compression::default();
+    let archive = dir.join("files.tar.gz");
+
+    backup::backup_remote_files(
+        RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: source,
+        },
+        &compression,
+        &Default::default(),
+        &archive,
+        &Progress::default(),
+    )
+    .unwrap();
+    backup::restore_files(
+        &runner,
+        &archive,
+        None,
+        "new $(touch pwned) site",
+        &compression,
+        &Progress::default(),
+    )
+    .unwrap();
+
+    assert!(dir.join("new $(touch pwned) site/index.php").exists());
+    assert!(!dir.join("pwned").exists());
+}
+
+#[test]
+fn identifiers_follow_forge_rules() {
+    assert!(shell::validate_user_name("app_user-1").is_ok());
+    assert!(shell::validate_user_name("_app").is_ok());
+    assert!(shell::validate_database_name("new_example").is_ok());
+
+    for user_name in ["App", "1app", "app user", "app;reboot", ""] {
+        assert!(
+            matches!(
+                shell::validate_user_name(user_name),
+                Err(AppError::ConfigError(_))
+            ),
+            "{}",
+            user_name
+        );
+    }
+    for name in ["new-example", "db name", "db`x`", "", &"a".repeat(65)] {
+        assert!(
+            matches!(
+                shell::validate_database_name(name),
+                Err(AppError::ConfigError(_))
+            ),
+            "{}",
+            name
+        );
+    }
+}
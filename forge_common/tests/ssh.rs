@@ -0,0 +1,122 @@
+use forge_common::{
+    error::AppError,
+    ssh::{self, HostKeyChecking, SshOptions, SshTransport},
+};
+
+#[test]
+fn defaults_leave_everything_to_ssh() {
+    assert!(SshOptions::default().args().is_empty());
+}
+
+#[test]
+fn every_setting_becomes_an_ssh_argument() {
+    let options = SshOptions {
+        user: Some("deploy".into()),
+        port: Some(2222),
+        identity_file: Some("/keys/deploy key".into()),
+        jump_host: Some("ops@bastion.example.com:2200".into()),
+        host_key_checking: Some(HostKeyChecking::AcceptNew),
+        options: vec!["ConnectTimeout=10".into(), "Compression=yes".into()],
+        multiplex: false,
+        transport: SshTransport::OpenSsh,
+    };
+
+    assert_eq!(
+        options.args(),
+        vec![
+            "-o",
+            "StrictHostKeyChecking=accept-new",
+            "-o",
+            "ConnectTimeout=10",
+            "-o",
+            "Compression=yes",
+            "-l",
+            "deploy",
+            "-p",
+            "2222",
+            "-i",
+            "/keys/deploy key",
+            "-J",
+            "ops@bastion.example.com:2200",
+        ]
+    );
+}
+
+#[test]
+fn multiplexing_shares_a_control_socket() {
+    let options = SshOptions {
+        multiplex: true,
+        ..Default::default()
+    };
+
+    assert_eq!(
+        options.args(),
+        vec![
+            "-o",
+            "ControlMaster=auto",
+            "-o",
+            "ControlPath=~/.ssh/forge-migrate-%C",
+            "-o",
+            "ControlPersist=60",
+        ]
+    );
+}
+
+#[test]
+fn host_key_policies_parse_from_names() {
+    assert_eq!("yes".parse(), Ok(HostKeyChecking::Yes));
+    assert_eq!("Accept-New".parse(), Ok(HostKeyChecking::AcceptNew));
+    assert_eq!("no".parse(), Ok(HostKeyChecking::No));
+    assert!("ask".parse::<HostKeyChecking>().is_err());
+}
+
+#[test]
+fn extra_options_have_to_be_key_value_pairs() {
+    assert!(ssh::validate_option("ServerAliveInterval=30").is_ok());
+
+    for option in [
+        "ServerAliveInterval",
+        "=30",
+        "-oProxyCommand=sh",
+        "Bad Key=1",
+    ] {
+        assert!(
+            matches!(ssh::validate_option(option), Err(AppError::ConfigError(_))),
+            "{}",
+            option
+        );
+    }
+}
+
+#[test]
+fn transports_parse_from_names() {
+    assert_eq!("openssh".parse(), Ok(SshTransport::OpenSsh));
+    assert_eq!("Native".parse(), Ok(SshTransport::Native));
+    assert!("libssh".parse::<SshTransport>().is_err());
+    assert_eq!(SshTransport::default(), SshTransport::OpenSsh);
+}
+
+#[test]
+fn the_native_transport_only_takes_options_it_understands() {
+    let options = |extra: &[&str]| SshOptions {
+        options: extra.iter().map(|option| option.to_string()).collect(),
+        transport: SshTransport::Native,
+        ..Default::default()
+    };
+
+    if cfg!(feature = "native-ssh") {
+        let supported = options(&["ConnectTimeout=10", "userknownhostsfile=/tmp/hosts"]);
+        assert!(supported.validate_transport().is_ok());
+        assert_eq!(supported.option("UserKnownHostsFile"), Some("/tmp/hosts"));
+    }
+    assert!(matches!(
+        options(&["ProxyCommand=nc %h %p"]).validate_transport(),
+        Err(AppError::ConfigError(_))
+    ));
+    assert!(SshOptions {
+        options: vec!["ProxyCommand=nc %h %p".into()],
+        ..Default::default()
+    }
+    .validate_transport()
+    .is_ok());
+}
@@ -0,0 +1,165 @@
+use std::fs;
+
+use forge_common::{
+    backup,
+    compression::{Codec, Compression},
+    error::AppError,
+    exclude::Excludes,
+    feedback::Progress,
+    remote::RemoteFolder,
+    runner::{LocalAsRemoteRunner, RecordingRunner, Stdin},
+    shell,
+    source::SourceFolder,
+    stream,
+};
+
+mod common;
+
+use common::scratch_dir;
+
+#[test]
+fn files_stream_from_a_remote_source() {
+    for codec in [Codec::Gzip, Codec::Zstd, Codec::None] {
+        let dir = scratch_dir(&format!("remote_{}", codec));
+        fs::create_dir_all(dir.join("source/public")).unwrap();
+        fs::write(dir.join("source/public/index.html"), "<h1>hi</h1>").unwrap();
+        fs::create_dir_all(dir.join("dest")).unwrap();
+        let source = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+        let dest = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+        let compression = Compression::new(codec, None, Some(1)).unwrap();
+
+        let (summary, _) = backup::stream_files(
+            SourceFolder::Remote(RemoteFolder {
+                runner: &source,
+                user_name: None,
+                path: "source",
+            }),
+            RemoteFolder {
+                runner: &dest,
+                user_name: None,
+                path: "dest",
+            },
+            &compression,
+            &Excludes::default(),
+            &Progress::default(),
+        )
+        .unwrap();
+
+        assert!(summary.bytes > 0);
+        assert_eq!(summary.checksum.len(), 64);
+        assert_eq!(
+            fs::read_to_string(dir.join("dest/public/index.html")).unwrap(),
+            "<h1>hi</h1>"
+        );
+    }
+}
+
+#[test]
+fn local_files_stream_with_progress() {
+    let dir = scratch_dir("local");
+    fs::create_dir_all(dir.join("source")).unwrap();
+    fs::write(dir.join("source/artisan"), "#!/usr/bin/env php\n").unwrap();
+    fs::create_dir_all(dir.join("dest")).unwrap();
+    let dest = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+    let progress = Progress::default();
+
+    backup::stream_files(
+        SourceFolder::Local(dir.join("source").to_str().unwrap()),
+        RemoteFolder {
+            runner: &dest,
+            user_name: None,
+            path: "dest",
+        },
+        &Compression::default(),
+        &Excludes::default(),
+        &progress,
+    )
+    .unwrap();
+
+    assert_eq!((progress.bytes(), progress.total()), (19, Some(19)));
+    assert!(dir.join("dest/artisan").exists());
+}
+
+#[test]
+fn destination_checksum_has_to_match() {
+    let dir = scratch_dir("mismatch");
+    fs::create_dir_all(dir.join("source")).unwrap();
+    fs::write(dir.join("source/index.php"), "<?php\n").unwrap();
+    let dest = RecordingRunner::new("forge@10.0.0.2");
+    dest.respond("tar", 0, "sha256:0000\n");
+
+    let result = backup::stream_files(
+        SourceFolder::Local(dir.join("source").to_str().unwrap()),
+        RemoteFolder {
+            runner: &dest,
+            user_name: None,
+            path: "/home/forge/new.com",
+        },
+        &Compression::default(),
+        &Excludes::default(),
+        &Progress::default(),
+    );
+
+    assert!(matches!(result, Err(AppError::ChecksumMismatch(_))));
+    assert!(!dest.calls()[0].stdin.is_empty());
+}
+
+#[test]
+fn source_checksum_has_to_match() {
+    let dir = scratch_dir("source_sum");
+    let dest = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+
+    let result = stream::pipe(&dest, "cat", |writer| {
+        writer.write_all(b"dump").unwrap();
+        Ok(Some("0000".into()))
+    });
+
+    assert!(matches!(result, Err(AppError::ChecksumMismatch(_))));
+}
+
+#[test]
+fn failing_destination_is_reported() {
+    let dir = scratch_dir("dest_failed");
+    let dest = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+
+    let result = stream::pipe(&dest, "echo 'no space left' >&2; exit 3", |writer| {
+        writer
+            .write_all(&[0; 1 << 20])
+            .map_err(|e| AppError::CommandError("test".into(), e))?;
+        Ok(None)
+    });
+
+    match result {
+        Err(AppError::CommandError(_, e)) => assert_eq!(e.to_string(), "no space left"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn failing_source_is_reported() {
+    let dir = scratch_dir("source_failed");
+    let source = LocalAsRemoteRunner::new("forge@10.0.0.1", &dir);
+    let dest = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+
+    for command in [
+        "echo partial; echo 'Access denied' >&2; exit 4".to_string(),
+        shell::pipe("echo partial; echo 'Access denied' >&2; exit 4", "gzip -c"),
+    ] {
+        let result = stream::pipe(&dest, "cat > /dev/null", |writer| {
+            stream::from_remote(&source, &command, Stdin::Null, writer)
+        });
+
+        match result {
+            Err(AppError::CommandError(_, e)) => assert_eq!(e.to_string(), "Access denied"),
+            other => panic!("unexpected result for `{}`: {:?}", command, other),
+        }
+    }
+}
+
+#[test]
+fn checksums_are_split_from_the_output() {
+    let (checksum, rest) = stream::split_checksum("warning\nsha256:abc\n");
+
+    assert_eq!(checksum.as_deref(), Some("abc"));
+    assert_eq!(rest, "warning");
+}
@@ -0,0 +1,204 @@
+use std::{fs, os::unix::fs::symlink, path::Path};
+
+use filetime::FileTime;
+use forge_common::{
+    compression::Compression,
+    exclude::Excludes,
+    feedback::Progress,
+    remote::RemoteFolder,
+    runner::LocalAsRemoteRunner,
+    source::SourceFolder,
+    sync::{self, CompareBy, Entry, EntryKind, Manifest, SyncOptions},
+};
+
+mod common;
+
+use common::scratch_dir;
+
+fn file(size: u64, mtime: i64) -> Entry {
+    Entry {
+        kind: EntryKind::File,
+        size,
+        mtime,
+        link: None,
+        checksum: None,
+    }
+}
+
+fn folder() -> Entry {
+    Entry {
+        kind: EntryKind::Directory,
+        ..file(4096, 0)
+    }
+}
+
+fn manifest(entries: &[(&str, Entry)]) -> Manifest {
+    entries
+        .iter()
+        .map(|(path, entry)| (path.to_string(), entry.clone()))
+        .collect()
+}
+
+fn write(root: &Path, path: &str, content: &str, mtime: i64) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, content).unwrap();
+    filetime::set_file_mtime(&path, FileTime::from_unix_time(mtime, 0)).unwrap();
+}
+
+#[test]
+fn only_changed_files_are_transferred() {
+    let source = manifest(&[
+        ("app", folder()),
+        ("app/same.php", file(10, 100)),
+        ("app/newer.php", file(10, 200)),
+        ("app/bigger.php", file(20, 100)),
+        ("app/new.php", file(5, 100)),
+    ]);
+    let dest = manifest(&[
+        ("app", folder()),
+        ("app/same.php", file(10, 100)),
+        ("app/newer.php", file(10, 100)),
+        ("app/bigger.php", file(10, 100)),
+        ("app/old.php", file(5, 100)),
+    ]);
+
+    let changes = sync::compare(&source, &dest, &SyncOptions::default());
+
+    assert_eq!(
+        changes.transfer,
+        vec!["app/bigger.php", "app/new.php", "app/newer.php"]
+    );
+    assert!(changes.delete.is_empty());
+    assert_eq!(changes.unchanged, 2);
+}
+
+#[test]
+fn checksums_ignore_the_modification_time() {
+    let with_sum = |sum: &str, mtime| Entry {
+        checksum: Some(sum.into()),
+        ..file(10, mtime)
+    };
+    let source = manifest(&[("a", with_sum("1", 100)), ("b", with_sum("2", 100))]);
+    let dest = manifest(&[("a", with_sum("1", 300)), ("b", with_sum("3", 100))]);
+    let options = SyncOptions {
+        compare: CompareBy::Checksum,
+        delete: false,
+    };
+
+    let changes = sync::compare(&source, &dest, &options);
+
+    assert_eq!(changes.transfer, vec!["b"]);
+}
+
+#[test]
+fn removed_folders_are_deleted_once() {
+    let source = manifest(&[("keep", file(1, 1)), ("swap", file(1, 1))]);
+    let dest = manifest(&[
+        ("gone", folder()),
+        ("gone/a", file(1, 1)),
+        ("gone/b", file(1, 1)),
+        ("keep", file(1, 1)),
+        ("swap", folder()),
+    ]);
+    let options = SyncOptions {
+        delete: true,
+        ..Default::default()
+    };
+
+    let changes = sync::compare(&source, &dest, &options);
+
+    // A folder replaced by a file is removed even without `delete`.
+    assert_eq!(changes.delete, vec!["swap", "gone"]);
+    assert_eq!(changes.transfer, vec!["swap"]);
+}
+
+#[test]
+fn manifest_listing_is_parsed() {
+    let output = b"d\x004096\x001700000000.5\x00app\x00\x00\
+f\x0012\x001700000001.0000000000\x00app/index.php\x00\x00\
+l\x0011\x001700000002.0\x00current\x00app/index.php\x00";
+
+    let manifest = sync::parse_manifest(output).unwrap();
+
+    assert_eq!(manifest.len(), 3);
+    assert_eq!(manifest["app"].kind, EntryKind::Directory);
+    assert_eq!(manifest["app/index.php"].size, 12);
+    assert_eq!(manifest["app/index.php"].mtime, 1700000001);
+    assert_eq!(manifest["current"].link.as_deref(), Some("app/index.php"));
+}
+
+#[test]
+fn repeated_syncs_copy_only_the_changes() {
+    for (compare, source_is_remote) in [
+        (CompareBy::Mtime, false),
+        (CompareBy::Checksum, false),
+        (CompareBy::Mtime, true),
+        (CompareBy::Checksum, true),
+    ] {
+        let dir = scratch_dir(&format!("repeat_{}_{}", compare, source_is_remote));
+        let source_root = dir.join("source");
+        write(
+            &source_root,
+            "public/index.php",
+            "<?php echo 1;",
+            1_700_000_000,
+        );
+        write(&source_root, "public/old.css", "body {}", 1_700_000_000);
+        symlink("public/index.php", source_root.join("current")).unwrap();
+        fs::create_dir_all(dir.join("dest")).unwrap();
+
+        let runner = LocalAsRemoteRunner::new("forge@10.0.0.2", &dir);
+        let source_path = source_root.to_str().unwrap().to_string();
+        let source = match source_is_remote {
+            true => SourceFolder::Remote(RemoteFolder {
+                runner: &runner,
+                user_name: None,
+                path: "source",
+            }),
+            false => SourceFolder::Local(&source_path),
+        };
+        let dest = RemoteFolder {
+            runner: &runner,
+            user_name: None,
+            path: "dest",
+        };
+        let options = SyncOptions {
+            compare,
+            delete: true,
+        };
+        let compression = Compression::default();
+        let excludes = Excludes::default();
+        let progress = Progress::default();
+
+        let first =
+            sync::sync_files(source, dest, &compression, &options, &excludes, &progress).unwrap();
+        assert_eq!(first.transferred, 4);
+        assert_eq!(
+            fs::read_link(dir.join("dest/current")).unwrap(),
+            Path::new("public/index.php")
+        );
+
+        let second =
+            sync::sync_files(source, dest, &compression, &options, &excludes, &progress).unwrap();
+        assert_eq!(second.transferred, 0);
+        assert_eq!(second.unchanged, 4);
+
+        write(
+            &source_root,
+            "public/index.php",
+            "<?php echo 2;",
+            1_700_000_100,
+        );
+        fs::remove_file(source_root.join("public/old.css")).unwrap();
+        let third =
+            sync::sync_files(source, dest, &compression, &options, &excludes, &progress).unwrap();
+
+        assert_eq!((third.transferred, third.deleted), (1, 1));
+        assert_eq!(
+            fs::read_to_string(dir.join("dest/public/index.php")).unwrap(),
+            "<?php echo 2;"
+        );
+        assert!(!dir.join("dest/public/old.css").exists());
+    }
+}
@@ -1,20 +1,29 @@
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
-    thread,
-    time::Duration,
 };
 
 use clap::Parser;
 
 use forge_common::{
-    args, backup, config,
-    error::AppResult,
+    archive::ArchiveProgress,
+    args, backup,
+    config::{self, Destination, FinalConfig},
+    database::DatabaseCredentials,
+    error::{AppError, AppResult},
+    exclude::Excludes,
     feedback,
-    forge::{database, site, ForgeClient},
-    setup, site_type,
+    forge::ForgeClient,
+    journal::{Journal, Step},
+    plan::{self, Plan},
+    preflight::Preflight,
+    remote::RemoteFolder,
+    rollback,
+    runner::LocalRunner,
+    setup,
+    source::SourceFolder,
+    sync,
 };
-use rand::Rng;
 
 fn main() {
     if let Err(e) = run() {
@@ -24,140 +33,469 @@ fn main() {
 }
 
 fn run() -> AppResult<()> {
-    // Step 1. Check prerequisites.
-    setup::check_prerequisites()?;
+    // Step 1. Parse config / arguments
+    let args = args::Args::parse();
+    let resume = args.resume.clone();
+    let resync = args.resync.clone();
+    let dry_run = args.dry_run;
+    let read_source = args.read_source;
+    let plan_file = args.plan_file.clone();
+    let apply = args.apply.clone();
 
-    // Step 2. Parse config / arguments
-    let config = Arc::new(
-        config::Config::load()?
-            .from_args(args::Args::parse())
-            .finalize()?,
-    );
+    let mut config = config::Config::load()?.from_args(args);
+    let applied_plan = match apply {
+        Some(path) => {
+            let plan = Plan::load(Path::new(&path))?;
+            config = config.from_plan(&plan);
+            Some(plan)
+        }
+        None => None,
+    };
+    let config = Arc::new(config.finalize(Destination::New)?);
+
+    // Step 2. Check prerequisites.
+    setup::check_prerequisites(&config)?;
+
+    if dry_run {
+        // Building the plan reads the source site, which is on another server.
+        if config.source_site.is_some() && !read_source {
+            return Err(AppError::ConfigError(
+                "A dry run of a site on another server reads it over SSH, add --read-source to allow that".into(),
+            ));
+        }
+
+        let plan = Plan::build(&config)?;
+        println!("{}", plan);
+
+        if let Some(path) = plan_file {
+            plan.save(&PathBuf::from(&path))?;
+            println!("Plan written to {}, execute it with --apply {}", path, path);
+        }
+        return Ok(());
+    }
+
+    let client = Arc::new(config.forge_client()?);
+
+    feedback::install_interrupt_handler()?;
+
+    let mut journal = match (resume, resync.as_ref()) {
+        (Some(run_id), _) => {
+            let journal = Journal::load(&config.temp_folder, &run_id)?;
+            journal.check_matches(&config)?;
+            println!("Resuming migration run {}", journal.run_id);
+            journal
+        }
+        (None, Some(run_id)) => {
+            let mut journal = Journal::load(&config.temp_folder, run_id)?;
+            journal.check_matches(&config)?;
+            journal.restart_sync()?;
+            println!("Syncing migration run {} again", journal.run_id);
+            journal
+        }
+        (None, None) => {
+            let plan = match applied_plan {
+                Some(plan) => plan,
+                None => Plan::build(&config)?,
+            };
+
+            // Step 2b. Make sure the migration can succeed before changing anything.
+            let preflight = Preflight::run(&config, &client, &plan);
+            println!("{}", preflight);
+            preflight.ensure_passed()?;
+
+            let journal = Journal::create(&config, plan)?;
+            println!(
+                "Starting migration run {} (journal at {})",
+                journal.run_id,
+                journal.path().display()
+            );
+            journal
+        }
+    };
+
+    if let Err(e) = migrate(&config, &client, &mut journal) {
+        eprintln!("Migration failed: {}", e);
+
+        // Without a working API key the deletes would fail as well.
+        let unauthorized = e.forge_error().is_some_and(|err| err.is_unauthorized());
+
+        // A site being synced again was finished by an earlier run, keep it.
+        if !unauthorized && resync.is_none() && rollback::should_rollback(&config, &journal) {
+            match rollback::rollback(&client, &mut journal) {
+                Ok(()) => eprintln!("Forge resources created by this run have been removed."),
+                Err(rollback_error) => eprintln!("Rollback failed: {}", rollback_error),
+            }
+        }
+
+        eprintln!(
+            "Fix the problem and continue with: --resume {}",
+            journal.run_id
+        );
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+fn migrate(
+    config: &Arc<FinalConfig>,
+    client: &Arc<ForgeClient>,
+    journal: &mut Journal,
+) -> AppResult<()> {
+    let plan = journal.plan.clone();
+    let compression = plan.compression;
 
     // Step 3. Detect site type
-    let site_type = site_type::detect_site_type(Path::new(&config.source_folder))?;
+    let source_site = plan.source_site.clone();
+    let site_type =
+        plan::detect_source_site_type(config, source_site.as_ref(), &plan.source_folder)?;
+    if site_type.to_string() != plan.site_type {
+        return Err(AppError::PlanError(format!(
+            "Source was planned as a {} site but is now detected as {}",
+            plan.site_type, site_type
+        )));
+    }
+
+    let excludes = Excludes::new(&plan.excludes)?;
 
     // Step 4. Backup database
-    let mut db_archive: Option<PathBuf> = None;
-    if let Some(creds) = site_type.get_database_credentials(Path::new(&config.source_folder))? {
-        if let Some(output_path) =
-            backup::generate_output_path(&config.source_folder, &config.temp_folder, "-db.sql.gz")
-        {
-            db_archive = Some(output_path.clone());
-            feedback::show_spinner(
-                move || backup::backup_database(&creds, &output_path),
-                "Backing up database",
+    journal.run_step(Step::BackupDatabase, |journal| {
+        if let Some(output_path) = plan.db_archive.clone() {
+            let creds = plan::source_database_credentials(
+                config,
+                &site_type,
+                source_site.as_ref(),
+                &plan.source_folder,
+            )?
+            .ok_or_else(|| {
+                AppError::PlanError("Source database settings are no longer present".into())
+            })?;
+            let source = source_site
+                .as_ref()
+                .map(|source_site| config.runner(&source_site.host));
+            journal.db_archive = Some(output_path.clone());
+            feedback::show_progress(
+                move |progress| match source {
+                    Some(source) => backup::backup_remote_database(
+                        source.as_ref(),
+                        &creds,
+                        &compression,
+                        &output_path,
+                        &progress,
+                    ),
+                    None => backup::backup_database(
+                        &LocalRunner,
+                        &creds,
+                        &compression,
+                        &output_path,
+                        &progress,
+                    ),
+                },
+                &Step::BackupDatabase.to_string(),
             )?;
         }
-    }
+        Ok(())
+    })?;
 
     // Step 5. Backup files
-    let mut files_archive: Option<PathBuf> = None;
-    if let Some(output_path) =
-        backup::generate_output_path(&config.source_folder, &config.temp_folder, "-files.tar.gz")
-    {
-        let config = Arc::clone(&config);
-        files_archive = Some(output_path.clone());
-        feedback::show_spinner(
-            move || backup::backup_files(&config, &output_path),
-            "Backing up files",
-        )?;
-    }
+    journal.run_step(Step::BackupFiles, |journal| {
+        if let Some(output_path) = plan.files_archive.clone() {
+            let source = source_site
+                .clone()
+                .map(|source_site| (config.runner(&source_site.host), source_site));
+            let source_folder = plan.source_folder.clone();
+            let excludes = excludes.clone();
+            journal.files_archive = Some(output_path.clone());
+            let totals = feedback::show_progress(
+                move |progress| match source {
+                    Some((source, source_site)) => backup::backup_remote_files(
+                        RemoteFolder {
+                            runner: source.as_ref(),
+                            user_name: source_site.isolated_user(),
+                            path: &source_site.root_directory,
+                        },
+                        &compression,
+                        &excludes,
+                        &output_path,
+                        &progress,
+                    ),
+                    None => backup::backup_files(
+                        &source_folder,
+                        &compression,
+                        &excludes,
+                        &output_path,
+                        &progress,
+                    ),
+                },
+                &Step::BackupFiles.to_string(),
+            )?;
+            report_excluded(&totals);
+        }
+        Ok(())
+    })?;
 
     // Step 6. Create forge site
-    let client = Arc::new(ForgeClient::new(&config.forge_api_key)?);
+    journal.run_step(Step::CreateSite, |journal| {
+        let csr = plan.create_site.clone();
 
-    let csr = site::CreateSiteRequest {
-        domain: config.dest_site_name.clone(),
-        isolated: config.isolated,
-        username: config.user_name.clone().unwrap_or_default(),
-        ..Default::default()
-    };
+        let client_clone = Arc::clone(client);
+        let config_clone = Arc::clone(config);
 
-    let client_clone = Arc::clone(&client);
-    let config_clone = Arc::clone(&config);
-
-    let site = feedback::show_spinner(
-        move || client_clone.create_site(&config_clone.dest_server_id, &csr),
-        "Creating forge site",
-    )?;
+        let site = feedback::show_spinner(
+            move || client_clone.create_site(&config_clone.dest_server_id, &csr),
+            &Step::CreateSite.to_string(),
+        )?;
 
-    let client_clone = Arc::clone(&client);
-    let config_clone = Arc::clone(&config);
+        journal.site_id = Some(site.site.id);
+        journal.web_directory = Some(site.site.root_directory());
+        Ok(())
+    })?;
 
     // Step 7. Create destination database
-    let password = generate_password(20);
+    journal.run_step(Step::CreateDatabase, |journal| {
+        let cdr = plan.create_database.clone();
+        let password = cdr.password.clone();
+        let user = cdr.user.clone();
 
-    let cdr = database::CreateDatabaseRequest {
-        name: config_clone.dest_db.clone(),
-        user: config_clone.user_name.clone().unwrap_or("forge".into()),
-        password: password.clone(),
-    };
+        // Only a database user created by this run may be removed on rollback.
+        let user_existed = client
+            .list_users(&config.dest_server_id)?
+            .users
+            .iter()
+            .any(|existing| existing.name == user);
 
-    feedback::show_spinner(
-        move || client_clone.create_database(&config_clone.dest_server_id, &cdr),
-        "Creating forge database",
-    )?;
-
-    let client_clone = Arc::clone(&client);
-    let config_clone = Arc::clone(&config);
-    let site_id = site.site.id.to_string();
-    // we can't restore the files straight away... need to wait for the user to be created.
-    feedback::show_spinner(
-        move || client_clone.wait_for_site_ready(&config_clone.dest_server_id, &site_id),
-        "Waiting for forge to create the site",
-    )?;
+        let client_clone = Arc::clone(client);
+        let config_clone = Arc::clone(config);
 
-    let web_directory = site.site.web_directory.clone();
-    //
-    // Step 8. Restore files to target server
-    if let Some(ref archive) = files_archive {
-        let archive_clone = archive.clone();
-        let config_clone = config.clone();
-        feedback::show_spinner(
-            move || {
-                backup::restore_files(
-                    archive_clone
-                        .to_str()
-                        .expect("Failed to convert PathBuf to string"),
-                    &config_clone.dest_host,
-                    config_clone.user_name.clone(),
-                    &web_directory,
-                )
-            },
-            "Copying files via SSH",
+        let database = feedback::show_spinner(
+            move || client_clone.create_database(&config_clone.dest_server_id, &cdr),
+            &Step::CreateDatabase.to_string(),
         )?;
-    }
 
-    if let Some(ref archive) = db_archive {
-        let archive_clone = archive.clone();
+        journal.database_id = Some(database.database.id);
+        journal.db_password = Some(password);
+
+        if !user_existed {
+            journal.database_user_id = client
+                .list_users(&config.dest_server_id)?
+                .users
+                .into_iter()
+                .find(|created| created.name == user)
+                .map(|created| created.id);
+        }
+        Ok(())
+    })?;
+
+    let site_id = journal
+        .site_id
+        .ok_or_else(|| AppError::JournalError("No site id was recorded for this run".into()))?;
+    let web_directory = journal.web_directory.clone().ok_or_else(|| {
+        AppError::JournalError("No web directory was recorded for this run".into())
+    })?;
+
+    // we can't restore the files straight away... need to wait for the user to be created.
+    journal.run_step(Step::WaitForSite, |_| {
+        let client_clone = Arc::clone(client);
+        let config_clone = Arc::clone(config);
         feedback::show_spinner(
             move || {
-                backup::restore_database(
-                    archive_clone
-                        .to_str()
-                        .expect("Failed to convert PathBuf to string"),
-                    &config.dest_host,
-                    config.user_name.clone(),
-                    &config.dest_db,
-                    &password,
-                )
+                client_clone.wait_for_site_ready(&config_clone.dest_server_id, &site_id.to_string())
             },
-            "Restoring DB on destination server",
-        )?;
-    }
+            &Step::WaitForSite.to_string(),
+        )
+    })?;
+
+    // Step 8. Restore files to target server
+    journal.run_step(Step::RestoreFiles, |journal| {
+        let dest = config.runner(&config.dest_host);
+        let user_name = config.user_name.clone();
+        let web_directory = web_directory.clone();
+
+        if let Some(options) = plan.sync {
+            let source = source_site
+                .clone()
+                .map(|source_site| (config.runner(&source_site.host), source_site));
+            let source_folder = plan.source_folder.clone();
+            let excludes = excludes.clone();
+            let summary = feedback::show_progress(
+                move |progress| {
+                    let source = match &source {
+                        Some((runner, site)) => SourceFolder::Remote(RemoteFolder {
+                            runner: runner.as_ref(),
+                            user_name: site.isolated_user(),
+                            path: &site.root_directory,
+                        }),
+                        None => SourceFolder::Local(&source_folder),
+                    };
+                    let dest = RemoteFolder {
+                        runner: dest.as_ref(),
+                        user_name: user_name.as_deref(),
+                        path: &web_directory,
+                    };
+                    sync::sync_files(source, dest, &compression, &options, &excludes, &progress)
+                },
+                &Step::RestoreFiles.to_string(),
+            )?;
+            println!("Synced files: {}", summary);
+            report_excluded(&ArchiveProgress {
+                excluded_files: summary.excluded_files,
+                excluded_bytes: summary.excluded_bytes,
+                ..Default::default()
+            });
+        } else if plan.stream {
+            let source = source_site
+                .clone()
+                .map(|source_site| (config.runner(&source_site.host), source_site));
+            let source_folder = plan.source_folder.clone();
+            let excludes = excludes.clone();
+            let (summary, totals) = feedback::show_progress(
+                move |progress| {
+                    let source = match &source {
+                        Some((runner, site)) => SourceFolder::Remote(RemoteFolder {
+                            runner: runner.as_ref(),
+                            user_name: site.isolated_user(),
+                            path: &site.root_directory,
+                        }),
+                        None => SourceFolder::Local(&source_folder),
+                    };
+                    let dest = RemoteFolder {
+                        runner: dest.as_ref(),
+                        user_name: user_name.as_deref(),
+                        path: &web_directory,
+                    };
+                    backup::stream_files(source, dest, &compression, &excludes, &progress)
+                },
+                &Step::RestoreFiles.to_string(),
+            )?;
+            println!(
+                "Streamed {} of files (sha256 {})",
+                feedback::format_bytes(summary.bytes),
+                summary.checksum
+            );
+            report_excluded(&totals);
+        } else if let Some(archive) = journal.files_archive.clone() {
+            feedback::show_progress(
+                move |progress| {
+                    backup::restore_files(
+                        dest.as_ref(),
+                        &archive,
+                        user_name.as_deref(),
+                        &web_directory,
+                        &compression,
+                        &progress,
+                    )
+                },
+                &Step::RestoreFiles.to_string(),
+            )?;
+        }
+        Ok(())
+    })?;
+
+    // Step 9. Point the restored application at the new database
+    journal.run_step(Step::RewriteConfig, |_| {
+        if plan.config_file.is_some() && plan.restores_files() {
+            let creds = DatabaseCredentials {
+                username: plan.create_database.user.clone(),
+                password: plan.create_database.password.clone(),
+                database: plan.create_database.name.clone(),
+                ..Default::default()
+            };
+            let site_type = site_type.clone();
+            let app_host = plan.app_host.clone();
+            let dest = config.runner(&config.dest_host);
+            let user_name = config.user_name.clone();
+            feedback::show_spinner(
+                move || {
+                    backup::rewrite_remote_config(
+                        &site_type,
+                        dest.as_ref(),
+                        user_name.as_deref(),
+                        &web_directory,
+                        &creds,
+                        app_host.as_deref(),
+                    )
+                },
+                &Step::RewriteConfig.to_string(),
+            )?;
+        }
+        Ok(())
+    })?;
+
+    // Step 10. Restore database to target server
+    journal.run_step(Step::RestoreDatabase, |journal| {
+        if !plan.restores_database() {
+            return Ok(());
+        }
+
+        let password = journal.db_password.clone().ok_or_else(|| {
+            AppError::JournalError("No database password was recorded for this run".into())
+        })?;
+        let dest = config.runner(&config.dest_host);
+        let dest_creds = DatabaseCredentials {
+            username: plan.create_database.user.clone(),
+            password,
+            database: config.dest_db.clone(),
+            ..Default::default()
+        };
+
+        if plan.stream {
+            let creds = plan::source_database_credentials(
+                config,
+                &site_type,
+                source_site.as_ref(),
+                &plan.source_folder,
+            )?
+            .ok_or_else(|| {
+                AppError::PlanError("Source database settings are no longer present".into())
+            })?;
+            let source = source_site
+                .as_ref()
+                .map(|source_site| config.runner(&source_site.host));
+            let summary = feedback::show_progress(
+                move |progress| {
+                    backup::stream_database(
+                        source.as_deref(),
+                        &creds,
+                        &compression,
+                        dest.as_ref(),
+                        &dest_creds,
+                        &progress,
+                    )
+                },
+                &Step::RestoreDatabase.to_string(),
+            )?;
+            println!(
+                "Streamed {} of database dump (sha256 {})",
+                feedback::format_bytes(summary.bytes),
+                summary.checksum
+            );
+        } else if let Some(archive) = journal.db_archive.clone() {
+            feedback::show_progress(
+                move |progress| {
+                    backup::restore_database(
+                        dest.as_ref(),
+                        &archive,
+                        &dest_creds,
+                        &compression,
+                        &progress,
+                    )
+                },
+                &Step::RestoreDatabase.to_string(),
+            )?;
+        }
+        Ok(())
+    })?;
 
     Ok(())
 }
 
-fn generate_password(length: usize) -> String {
-    const CHARSET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
-    let mut rng = rand::thread_rng();
-
-    (0..length)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx] as char
-        })
-        .collect()
+/// Tells the user how much the exclude rules left behind.
+fn report_excluded(totals: &ArchiveProgress) {
+    if totals.excluded_files > 0 {
+        println!(
+            "Excluded {} files ({})",
+            totals.excluded_files,
+            feedback::format_bytes(totals.excluded_bytes)
+        );
+    }
 }
@@ -0,0 +1,630 @@
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+    time::Duration,
+};
+
+use forge_mock::{Fault, MockForge};
+
+const SUDO: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -u) shift 2 ;;
+        -*) shift ;;
+        *) break ;;
+    esac
+done
+exec "$@"
+"#;
+
+/// Logs its arguments, then runs the command from `$HOME` as the server would.
+const SSH: &str = r#"#!/bin/sh
+echo "$@" >> "$HOME/ssh-args"
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -o|-l|-p|-i|-J) shift 2 ;;
+        *) break ;;
+    esac
+done
+cd "$HOME" && exec sh -c "$2"
+"#;
+
+/// Keeps its arguments and the option file it was given, then dumps.
+const MARIADB_DUMP: &str = r#"#!/bin/sh
+echo "$@" > "$HOME/dump-args"
+option_file="${1#--defaults-extra-file=}"
+stat -c %a "$option_file" > "$HOME/dump-option-mode"
+cat "$option_file" > "$HOME/dump-options"
+echo "CREATE TABLE posts (id int);"
+"#;
+
+/// Records what would have been restored into the destination database,
+/// along with its arguments and the option file it was given.
+const MYSQL: &str = r#"#!/bin/sh
+echo "$@" > "$HOME/restore-args"
+option_file="${1#--defaults-extra-file=}"
+stat -c %a "$option_file" > "$HOME/restore-option-mode"
+cat "$option_file" > "$HOME/restore-options"
+cat > "$HOME/restored.sql"
+"#;
+
+struct Fixture {
+    dir: PathBuf,
+    forge: MockForge,
+    server_id: u32,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("forge_migrate_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let bin = dir.join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        for (name, script) in [
+            ("sudo", SUDO),
+            ("ssh", SSH),
+            ("mariadb-dump", MARIADB_DUMP),
+            ("mysql", MYSQL),
+        ] {
+            let path = bin.join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let source = dir.join("old.example.com");
+        fs::create_dir_all(source.join("public")).unwrap();
+        fs::write(source.join("artisan"), "#!/usr/bin/env php\n").unwrap();
+        fs::write(source.join("public/index.php"), "<?php\n").unwrap();
+        fs::write(
+            source.join(".env"),
+            "APP_URL=https://old.example.com\nDB_HOST=127.0.0.1\nDB_PORT=3306\nDB_DATABASE=old\nDB_USERNAME=old\nDB_PASSWORD=old-secret\n",
+        )
+        .unwrap();
+
+        let forge = MockForge::start();
+        forge.set_home(dir.join("home").to_str().unwrap());
+        let server_id = forge.add_server("web", "127.0.0.1");
+
+        // Forge creates the site folder when it provisions the site.
+        fs::create_dir_all(dir.join("home/forge/new.example.com")).unwrap();
+
+        Fixture {
+            dir,
+            forge,
+            server_id,
+        }
+    }
+
+    fn site_root(&self) -> PathBuf {
+        self.dir.join("home/forge/new.example.com")
+    }
+
+    fn forge_migrate(&self, extra: &[&str]) -> Output {
+        self.command(extra).output().unwrap()
+    }
+
+    fn command(&self, extra: &[&str]) -> Command {
+        let path = format!(
+            "{}:{}",
+            self.dir.join("bin").display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_forge_migrate"));
+        if !extra.contains(&"--isolated") {
+            command.args(["--isolated", "false"]);
+        }
+        command
+            .env("HOME", &self.dir)
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("PATH", path)
+            .args(["--forge-api-key", "test-key"])
+            .args(["--forge-base-url", self.forge.url()])
+            .args(["--forge-requests-per-minute", "0"])
+            .args(["--forge-max-retries", "0"]);
+        // Stands in for both servers, with the fixture folder as their home.
+        // An explicit destination host is reached through the fake `ssh`.
+        if !extra.contains(&"--dest-host") {
+            command.args(["--local-remote-root", path_arg(&self.dir)]);
+        }
+        command
+            .args([
+                "--source-folder",
+                path_arg(&self.dir.join("old.example.com")),
+            ])
+            .args(["--dest-server-id", &self.server_id.to_string()])
+            .args(["--dest-site-name", "new.example.com"])
+            .args(["--dest-db", "new_example"])
+            .args(["--temp-folder", path_arg(&self.dir.join("tmp"))])
+            .args(extra)
+            .stdin(Stdio::null());
+        command
+    }
+}
+
+fn path_arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// The run ID printed when a migration starts.
+fn run_id(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .split("Starting migration run ")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap()
+        .to_string()
+}
+
+fn assert_success(output: &Output) {
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn migrates_a_laravel_site() {
+    let fixture = Fixture::new("laravel");
+
+    let output = fixture.forge_migrate(&[]);
+
+    assert_success(&output);
+    let forge = &fixture.forge;
+    assert_eq!(forge.site_names(fixture.server_id), vec!["new.example.com"]);
+    assert_eq!(forge.database_names(fixture.server_id), vec!["new_example"]);
+    assert_eq!(forge.user_names(fixture.server_id), vec!["forge"]);
+
+    let root = fixture.site_root();
+    assert!(root.join("artisan").exists());
+    assert!(root.join("public/index.php").exists());
+
+    let env = fs::read_to_string(root.join(".env")).unwrap();
+    assert!(env.contains("APP_URL=https://new.example.com\n"));
+    assert!(env.contains("DB_DATABASE=new_example\n"));
+    assert!(env.contains("DB_USERNAME=forge\n"));
+    assert!(!env.contains("old-secret"));
+
+    let restored = fs::read_to_string(fixture.dir.join("restored.sql")).unwrap();
+    assert_eq!(restored, "CREATE TABLE posts (id int);\n");
+
+    // The dump authenticates with the site's credentials from a private file.
+    let dump_args = fs::read_to_string(fixture.dir.join("dump-args")).unwrap();
+    let option_file = dump_args
+        .split_whitespace()
+        .next()
+        .and_then(|arg| arg.strip_prefix("--defaults-extra-file="))
+        .unwrap();
+    assert!(!dump_args.contains("old-secret"));
+    let options = fs::read_to_string(fixture.dir.join("dump-options")).unwrap();
+    assert_eq!(
+        options,
+        "[client]\nuser=\"old\"\npassword=\"old-secret\"\nhost=\"127.0.0.1\"\nport=3306\n"
+    );
+    let mode = fs::read_to_string(fixture.dir.join("dump-option-mode")).unwrap();
+    assert_eq!(mode.trim(), "600");
+    assert!(!Path::new(option_file).exists());
+
+    // The new database password reaches the destination the same way.
+    let password = env
+        .lines()
+        .find_map(|line| line.strip_prefix("DB_PASSWORD="))
+        .unwrap();
+    let restore_args = fs::read_to_string(fixture.dir.join("restore-args")).unwrap();
+    let option_file = restore_args
+        .split_whitespace()
+        .next()
+        .and_then(|arg| arg.strip_prefix("--defaults-extra-file="))
+        .unwrap();
+    assert!(!restore_args.contains(password));
+    let options = fs::read_to_string(fixture.dir.join("restore-options")).unwrap();
+    assert_eq!(
+        options,
+        format!("[client]\nuser=\"forge\"\npassword=\"{}\"\n", password)
+    );
+    let mode = fs::read_to_string(fixture.dir.join("restore-option-mode")).unwrap();
+    assert_eq!(mode.trim(), "600");
+    assert!(!Path::new(option_file).exists());
+}
+
+#[test]
+fn migrates_with_zstd_archives() {
+    let fixture = Fixture::new("zstd");
+
+    let output = fixture.forge_migrate(&["--compression", "zstd", "--compression-level", "9"]);
+
+    assert_success(&output);
+    let root = fixture.site_root();
+    assert!(root.join("public/index.php").exists());
+    let restored = fs::read_to_string(fixture.dir.join("restored.sql")).unwrap();
+    assert_eq!(restored, "CREATE TABLE posts (id int);\n");
+
+    let archives: Vec<String> = fs::read_dir(fixture.dir.join("tmp"))
+        .unwrap()
+        .flat_map(|day| fs::read_dir(day.unwrap().path()).unwrap())
+        .map(|archive| archive.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert!(archives.contains(&"old.example.com-files.tar.zst".to_string()));
+    assert!(archives.contains(&"old.example.com-db.sql.zst".to_string()));
+}
+
+#[test]
+fn migrates_by_streaming() {
+    let fixture = Fixture::new("stream");
+
+    let output = fixture.forge_migrate(&["--stream", "true"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Streamed"));
+    let root = fixture.site_root();
+    assert!(root.join("public/index.php").exists());
+    let env = fs::read_to_string(root.join(".env")).unwrap();
+    assert!(env.contains("DB_DATABASE=new_example\n"));
+    let restored = fs::read_to_string(fixture.dir.join("restored.sql")).unwrap();
+    assert_eq!(restored, "CREATE TABLE posts (id int);\n");
+
+    // Only the run journal is written to the temp folder.
+    let temp: Vec<String> = fs::read_dir(fixture.dir.join("tmp"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(temp, vec!["runs"]);
+}
+
+#[test]
+fn excluded_files_are_left_behind() {
+    let fixture = Fixture::new("exclude");
+    let source = fixture.dir.join("old.example.com");
+    fs::create_dir_all(source.join("node_modules/left-pad")).unwrap();
+    fs::write(source.join("node_modules/left-pad/index.js"), "").unwrap();
+    fs::create_dir_all(source.join("public/uploads")).unwrap();
+    fs::write(source.join("public/uploads/cat.jpg"), "meow").unwrap();
+    fs::write(source.join("public/index.php.bak"), "<?php\n").unwrap();
+    fs::write(
+        source.join(".forgeignore"),
+        "# user uploads\n/public/uploads/\n",
+    )
+    .unwrap();
+
+    let output = fixture.forge_migrate(&["--exclude", "*.bak"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Excluded 6 files"));
+    let root = fixture.site_root();
+    assert!(root.join("public/index.php").exists());
+    assert!(root.join(".forgeignore").exists());
+    assert!(!root.join("node_modules").exists());
+    assert!(!root.join("public/uploads").exists());
+    assert!(!root.join("public/index.php.bak").exists());
+}
+
+#[test]
+fn space_check_sizes_what_is_archived() {
+    let fixture = Fixture::new("space");
+    let source = fixture.dir.join("old.example.com");
+    fs::create_dir_all(source.join("node_modules/big")).unwrap();
+    fs::write(
+        source.join("node_modules/big/bundle.js"),
+        vec![b'x'; 3 << 20],
+    )
+    .unwrap();
+    std::os::unix::fs::symlink("../node_modules", source.join("public/modules")).unwrap();
+    std::os::unix::fs::symlink("/nonexistent", source.join("public/missing")).unwrap();
+
+    let output = fixture.forge_migrate(&[]);
+
+    // Neither the excluded folder nor the link to it count, and the dangling
+    // link doesn't stop the check.
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("0 B needed"), "{}", stdout);
+    let root = fixture.site_root();
+    assert!(fs::symlink_metadata(root.join("public/modules"))
+        .unwrap()
+        .is_symlink());
+    assert!(fs::symlink_metadata(root.join("public/missing"))
+        .unwrap()
+        .is_symlink());
+}
+
+#[test]
+fn synced_site_is_brought_up_to_date_again() {
+    let fixture = Fixture::new("resync");
+    let sync = ["--file-transfer", "sync", "--sync-delete", "true"];
+    let source = fixture.dir.join("old.example.com");
+    fs::write(source.join("public/old.css"), "body {}\n").unwrap();
+
+    let output = fixture.forge_migrate(&sync);
+    assert_success(&output);
+    let run_id = run_id(&output);
+
+    assert!(fixture.site_root().join("public/old.css").exists());
+
+    fs::write(source.join("public/index.php"), "<?php echo 'v2';\n").unwrap();
+    fs::write(source.join("public/robots.txt"), "").unwrap();
+    fs::remove_file(source.join("public/old.css")).unwrap();
+
+    let output = fixture.forge_migrate(&[&sync[..], &["--resync", &run_id]].concat());
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Synced files:"));
+    let root = fixture.site_root();
+    assert_eq!(
+        fs::read_to_string(root.join("public/index.php")).unwrap(),
+        "<?php echo 'v2';\n"
+    );
+    assert!(root.join("public/robots.txt").exists());
+    assert!(!root.join("public/old.css").exists());
+    let env = fs::read_to_string(root.join(".env")).unwrap();
+    assert!(env.contains("DB_DATABASE=new_example\n"));
+    // The site from the first run is reused.
+    assert_eq!(
+        fixture.forge.site_names(fixture.server_id),
+        vec!["new.example.com"]
+    );
+}
+
+#[test]
+fn dry_run_changes_nothing() {
+    let fixture = Fixture::new("dry_run");
+
+    let output = fixture.forge_migrate(&["--dry-run"]);
+
+    assert_success(&output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Migration plan"));
+    assert!(fixture
+        .forge
+        .requests()
+        .iter()
+        .all(|request| request.method == "GET"));
+    assert!(fixture.forge.site_names(fixture.server_id).is_empty());
+}
+
+#[test]
+fn dry_run_reads_a_remote_source_only_when_allowed() {
+    let fixture = Fixture::new("dry_run_source");
+    let source_server_id = fixture.forge.add_server("old", "127.0.0.2");
+    let source_site_id = fixture.forge.add_site(source_server_id, "old.example.com");
+    fs::create_dir_all(fixture.dir.join("home/forge")).unwrap();
+    std::os::unix::fs::symlink(
+        fixture.dir.join("old.example.com"),
+        fixture.dir.join("home/forge/old.example.com"),
+    )
+    .unwrap();
+    let source = [
+        "--dry-run",
+        "--source-server-id",
+        &source_server_id.to_string(),
+        "--source-site-id",
+        &source_site_id.to_string(),
+    ];
+
+    let output = fixture.forge_migrate(&source);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--read-source"));
+
+    let output = fixture.forge_migrate(&[&source[..], &["--read-source"]].concat());
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Migration plan"), "{}", stdout);
+    assert!(stdout.contains("Laravel"), "{}", stdout);
+}
+
+#[test]
+fn preflight_stops_when_the_site_already_exists() {
+    let fixture = Fixture::new("preflight");
+    fixture.forge.add_site(fixture.server_id, "new.example.com");
+
+    let output = fixture.forge_migrate(&[]);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("new.example.com already exists"));
+    assert!(fixture.forge.database_names(fixture.server_id).is_empty());
+}
+
+#[test]
+fn isolated_user_is_created_by_forge() {
+    let fixture = Fixture::new("isolated");
+    // The user doesn't exist on the machine, as on a destination before Forge
+    // provisions the site.
+    let user_name = "forge_migrate_isolated";
+    fs::create_dir_all(
+        fixture
+            .dir
+            .join("home/forge_migrate_isolated/new.example.com"),
+    )
+    .unwrap();
+
+    let output = fixture.forge_migrate(&["--isolated", "true", "--user-name", user_name]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("forge_migrate_isolated will be created"));
+    assert!(fixture
+        .dir
+        .join("home/forge_migrate_isolated/new.example.com/public/index.php")
+        .exists());
+}
+
+#[test]
+fn failed_run_is_rolled_back() {
+    let fixture = Fixture::new("rollback");
+    fixture.forge.inject(
+        "POST",
+        &format!("/servers/{}/databases", fixture.server_id),
+        Fault::Validation {
+            field: "name".into(),
+            message: "The name is invalid.".into(),
+        },
+        1,
+    );
+
+    let output = fixture.forge_migrate(&["--auto-rollback", "true"]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("The name is invalid."));
+    assert!(stderr.contains("--resume"));
+    assert!(fixture.forge.site_names(fixture.server_id).is_empty());
+    assert!(fixture.forge.database_names(fixture.server_id).is_empty());
+    assert!(fixture
+        .forge
+        .requests()
+        .iter()
+        .any(|request| request.method == "DELETE" && request.path.contains("/sites/")));
+}
+
+#[test]
+fn failed_run_keeps_its_error_when_the_rollback_question_cant_be_asked() {
+    let fixture = Fixture::new("no_prompt");
+    fixture.forge.inject(
+        "POST",
+        &format!("/servers/{}/databases", fixture.server_id),
+        Fault::Status(500),
+        1,
+    );
+
+    // stdin is not a terminal and `--auto-rollback` is not given.
+    let output = fixture.forge_migrate(&[]);
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Migration failed"), "{}", stderr);
+    assert!(stderr.contains("Keeping the forge resources"), "{}", stderr);
+    assert!(
+        stderr.contains(&format!("--resume {}", run_id(&output))),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        fixture.forge.site_names(fixture.server_id),
+        vec!["new.example.com"]
+    );
+}
+
+#[test]
+fn failed_run_is_resumed_where_it_stopped() {
+    let fixture = Fixture::new("resume");
+    let sites = format!("/api/v1/servers/{}/sites", fixture.server_id);
+    let databases = format!("/api/v1/servers/{}/databases", fixture.server_id);
+    fixture.forge.inject(
+        "POST",
+        &format!("/servers/{}/databases", fixture.server_id),
+        Fault::Status(500),
+        1,
+    );
+
+    let output = fixture.forge_migrate(&["--auto-rollback", "false"]);
+
+    assert!(!output.status.success());
+    let run_id = run_id(&output);
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!("--resume {}", run_id)));
+    assert_eq!(fixture.forge.count("POST", &sites), 1);
+    assert!(fixture.forge.database_names(fixture.server_id).is_empty());
+
+    let output = fixture.forge_migrate(&["--resume", &run_id]);
+
+    assert_success(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("Creating forge site - already complete, skipping"),
+        "{}",
+        stdout
+    );
+    // The site from the first attempt is kept, only the database is retried.
+    assert_eq!(fixture.forge.count("POST", &sites), 1);
+    assert_eq!(fixture.forge.count("POST", &databases), 2);
+    assert_eq!(
+        fixture.forge.site_names(fixture.server_id),
+        vec!["new.example.com"]
+    );
+    assert_eq!(
+        fixture.forge.database_names(fixture.server_id),
+        vec!["new_example"]
+    );
+    let root = fixture.site_root();
+    assert!(root.join("public/index.php").exists());
+    let env = fs::read_to_string(root.join(".env")).unwrap();
+    assert!(env.contains("DB_DATABASE=new_example\n"));
+    assert_eq!(
+        fs::read_to_string(fixture.dir.join("restored.sql")).unwrap(),
+        "CREATE TABLE posts (id int);\n"
+    );
+}
+
+#[test]
+fn interrupted_site_creation_is_rolled_back() {
+    let fixture = Fixture::new("interrupt");
+    let sites = format!("/servers/{}/sites", fixture.server_id);
+    fixture
+        .forge
+        .inject("POST", &sites, Fault::Slow(Duration::from_secs(2)), 1);
+
+    let mut child = fixture
+        .command(&["--auto-rollback", "true"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    while fixture.forge.count("POST", &format!("/api/v1{}", sites)) == 0 {
+        if child.try_wait().unwrap().is_some() {
+            assert_success(&child.wait_with_output().unwrap());
+            panic!("finished without creating the site");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let kill = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+    let output = child.wait_with_output().unwrap();
+
+    // The site is only created once the slow request returns, after Ctrl-C.
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("waiting for it to finish"), "{}", stdout);
+    assert!(fixture.forge.site_names(fixture.server_id).is_empty());
+    assert!(fixture
+        .forge
+        .requests()
+        .iter()
+        .any(|request| request.method == "DELETE" && request.path.contains("/sites/")));
+    // The run stops before the next step.
+    assert_eq!(
+        fixture.forge.count(
+            "POST",
+            &format!("/api/v1/servers/{}/databases", fixture.server_id)
+        ),
+        0
+    );
+}
+
+#[test]
+fn explicit_destination_host_logs_in_as_the_ssh_user() {
+    let fixture = Fixture::new("ssh_user");
+
+    let output = fixture.forge_migrate(&["--dest-host", "new.example.com", "--ssh-user", "deploy"]);
+
+    assert_success(&output);
+    assert!(fixture.site_root().join("public/index.php").exists());
+    let ssh_args = fs::read_to_string(fixture.dir.join("ssh-args")).unwrap();
+    let calls = ssh_args.matches("BatchMode=yes").count();
+    assert!(calls > 0);
+    assert_eq!(
+        ssh_args.matches("-l deploy new.example.com ").count(),
+        calls
+    );
+    // The control sockets need the folder to exist.
+    let ssh_dir = fs::metadata(fixture.dir.join(".ssh")).unwrap();
+    assert_eq!(ssh_dir.permissions().mode() & 0o777, 0o700);
+}
@@ -0,0 +1,315 @@
+//! An in-memory stand-in for the subset of the Laravel Forge v1 API used by
+//! the forge tools: servers, sites, databases and database users.
+//!
+//! Start one with [`MockForge::start`], point a client at [`MockForge::url`],
+//! and inspect the state and [`MockForge::requests`] afterwards. Faults can be
+//! injected per method and path to exercise error handling and retries.
+//!
+//! With the `ssh` feature, [`MockSsh`] stands in for a server's SSH daemon.
+
+mod http;
+#[cfg(feature = "ssh")]
+mod ssh;
+mod state;
+
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use serde_json::json;
+
+#[cfg(feature = "ssh")]
+pub use crate::ssh::{write_key, MockSsh, SshRequest};
+use crate::{
+    http::Response,
+    state::{Database, Server, Site, State, User},
+};
+
+/// A failure returned instead of handling a matching request.
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// Respond with the status and a JSON `message`.
+    Status(u16),
+    /// Respond with a 422 carrying a validation message for the field.
+    Validation { field: String, message: String },
+    /// Respond with a 429 and `Retry-After` in seconds.
+    RateLimited { retry_after: u64 },
+    /// Handle the request normally after a delay.
+    Slow(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedRequest {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+struct Injection {
+    method: String,
+    path: String,
+    fault: Fault,
+    remaining: usize,
+}
+
+struct Shared {
+    state: State,
+    faults: Vec<Injection>,
+    log: Vec<LoggedRequest>,
+}
+
+pub struct MockForge {
+    url: String,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MockForge {
+    /// Starts serving on a free local port. The server runs until the
+    /// process exits.
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock forge listener");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("mock forge listener address")
+        );
+        let shared = Arc::new(Mutex::new(Shared {
+            state: State::new(),
+            faults: vec![],
+            log: vec![],
+        }));
+
+        let accepting = Arc::clone(&shared);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = Arc::clone(&accepting);
+                thread::spawn(move || {
+                    let Ok(request) = http::read_request(&stream) else {
+                        return;
+                    };
+                    let response = handle(&shared, &request);
+                    let _ = http::write_response(&stream, &response);
+                });
+            }
+        });
+
+        MockForge { url, shared }
+    }
+
+    /// Base URL to configure the client with.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set_api_key(&self, api_key: &str) {
+        self.state().api_key = api_key.into();
+    }
+
+    /// Directory the site folders live under, `/home` like forge by default.
+    pub fn set_home(&self, home: &str) {
+        self.state().home = home.trim_end_matches('/').into();
+    }
+
+    /// Number of reads a newly created site reports `installing` for.
+    pub fn set_install_polls(&self, polls: u32) {
+        self.state().install_polls = polls;
+    }
+
+    pub fn add_server(&self, name: &str, ip_address: &str) -> u32 {
+        let mut state = self.state();
+        let id = state.next_id();
+        state.servers.push(Server {
+            id,
+            name: name.into(),
+            ip_address: ip_address.into(),
+            is_ready: true,
+        });
+        id
+    }
+
+    pub fn set_server_ready(&self, server_id: u32, is_ready: bool) {
+        if let Some(server) = self.state().servers.iter_mut().find(|s| s.id == server_id) {
+            server.is_ready = is_ready;
+        }
+    }
+
+    pub fn add_site(&self, server_id: u32, name: &str) -> u32 {
+        let mut state = self.state();
+        let id = state.next_id();
+        state.sites.push(Site {
+            id,
+            server_id,
+            name: name.into(),
+            directory: "/public".into(),
+            project_type: "php".into(),
+            php_version: "php83".into(),
+            aliases: vec![],
+            isolated: false,
+            username: "forge".into(),
+            pending_polls: 0,
+        });
+        id
+    }
+
+    pub fn add_database(&self, server_id: u32, name: &str) -> u32 {
+        let mut state = self.state();
+        let id = state.next_id();
+        state.databases.push(Database {
+            id,
+            server_id,
+            name: name.into(),
+        });
+        id
+    }
+
+    pub fn add_user(&self, server_id: u32, name: &str, databases: &[u32]) -> u32 {
+        let mut state = self.state();
+        let id = state.next_id();
+        state.users.push(User {
+            id,
+            server_id,
+            name: name.into(),
+            databases: databases.to_vec(),
+        });
+        id
+    }
+
+    pub fn site_names(&self, server_id: u32) -> Vec<String> {
+        let state = self.state();
+        state
+            .sites
+            .iter()
+            .filter(|site| site.server_id == server_id)
+            .map(|site| site.name.clone())
+            .collect()
+    }
+
+    pub fn database_names(&self, server_id: u32) -> Vec<String> {
+        let state = self.state();
+        state
+            .databases
+            .iter()
+            .filter(|database| database.server_id == server_id)
+            .map(|database| database.name.clone())
+            .collect()
+    }
+
+    pub fn user_names(&self, server_id: u32) -> Vec<String> {
+        let state = self.state();
+        state
+            .users
+            .iter()
+            .filter(|user| user.server_id == server_id)
+            .map(|user| user.name.clone())
+            .collect()
+    }
+
+    /// Returns `fault` for the next `times` requests with the method whose
+    /// path (without the `/api/v1` prefix) starts with `path`.
+    pub fn inject(&self, method: &str, path: &str, fault: Fault, times: usize) {
+        self.shared().faults.push(Injection {
+            method: method.to_uppercase(),
+            path: path.into(),
+            fault,
+            remaining: times,
+        });
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<LoggedRequest> {
+        self.shared().log.clone()
+    }
+
+    /// Requests received with the method and exact path.
+    pub fn count(&self, method: &str, path: &str) -> usize {
+        self.shared()
+            .log
+            .iter()
+            .filter(|request| request.method == method && request.path == path)
+            .count()
+    }
+
+    fn shared(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn state(&self) -> StateGuard<'_> {
+        StateGuard(self.shared())
+    }
+}
+
+/// Access to the state inside the shared lock.
+struct StateGuard<'a>(MutexGuard<'a, Shared>);
+
+impl std::ops::Deref for StateGuard<'_> {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.0.state
+    }
+}
+
+impl std::ops::DerefMut for StateGuard<'_> {
+    fn deref_mut(&mut self) -> &mut State {
+        &mut self.0.state
+    }
+}
+
+fn handle(shared: &Mutex<Shared>, request: &http::Request) -> Response {
+    let fault = {
+        let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+        let path = request.path.split('?').next().unwrap_or_default();
+        shared.log.push(LoggedRequest {
+            method: request.method.clone(),
+            path: path.to_string(),
+            body: request.body.clone(),
+        });
+        take_fault(&mut shared.faults, &request.method, path)
+    };
+
+    match fault {
+        Some(Fault::Status(status)) => {
+            Response::json(status, json!({ "message": "Injected failure" }))
+        }
+        Some(Fault::Validation { field, message }) => Response::json(
+            422,
+            json!({
+                "message": "The given data was invalid.",
+                "errors": { field: [message] },
+            }),
+        ),
+        Some(Fault::RateLimited { retry_after }) => Response {
+            headers: vec![("Retry-After".into(), retry_after.to_string())],
+            ..Response::json(429, json!({ "message": "Too Many Attempts." }))
+        },
+        Some(Fault::Slow(delay)) => {
+            // Sleep outside the lock so other requests are still served.
+            thread::sleep(delay);
+            with_state(shared, request)
+        }
+        None => with_state(shared, request),
+    }
+}
+
+fn with_state(shared: &Mutex<Shared>, request: &http::Request) -> Response {
+    let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+    shared.state.handle(request)
+}
+
+fn take_fault(faults: &mut Vec<Injection>, method: &str, path: &str) -> Option<Fault> {
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+    let index = faults.iter().position(|injection| {
+        injection.remaining > 0 && injection.method == method && path.starts_with(&injection.path)
+    })?;
+
+    let injection = &mut faults[index];
+    injection.remaining -= 1;
+    let fault = injection.fault.clone();
+    if injection.remaining == 0 {
+        faults.remove(index);
+    }
+
+    Some(fault)
+}
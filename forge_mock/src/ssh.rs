@@ -0,0 +1,392 @@
+// SSH server stand-in: commands run through `sh` and uploads land in a local
+// folder playing the part of the remote home directory
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use russh::{
+    keys::{ssh_key::LineEnding, Algorithm, PrivateKey, PublicKey},
+    server::{self, Auth, ChannelOpenHandle, Msg, Server as _, Session},
+    Channel, ChannelId, MethodKind, MethodSet,
+};
+use russh_sftp::protocol::{FileAttributes, Handle, OpenFlags, Status, StatusCode};
+use tokio::{io::AsyncWriteExt, net::TcpStream, process::Command};
+
+/// Something a client asked the server to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SshRequest {
+    Exec {
+        user: String,
+        command: String,
+    },
+    Upload {
+        user: String,
+        path: String,
+    },
+    Forward {
+        user: String,
+        host: String,
+        port: u32,
+    },
+}
+
+struct Shared {
+    root: PathBuf,
+    authorized: Vec<PublicKey>,
+    log: Vec<SshRequest>,
+}
+
+pub struct MockSsh {
+    port: u16,
+    host_key: PublicKey,
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl MockSsh {
+    /// Starts serving on a free local port, running commands from `root`.
+    /// The server runs until the process exits.
+    pub fn start(root: &Path) -> Self {
+        fs::create_dir_all(root).expect("create mock ssh root");
+        let host_key = generate_key();
+        let public_key = host_key.public_key().clone();
+        let shared = Arc::new(Mutex::new(Shared {
+            root: root.to_path_buf(),
+            authorized: vec![],
+            log: vec![],
+        }));
+
+        let (port_sender, port_receiver) = mpsc::channel();
+        let serving = Arc::clone(&shared);
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .expect("mock ssh runtime");
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+                    .await
+                    .expect("bind mock ssh listener");
+                let port = listener.local_addr().expect("mock ssh address").port();
+                let _ = port_sender.send(port);
+
+                let config = server::Config {
+                    methods: MethodSet::from(&[MethodKind::PublicKey][..]),
+                    auth_rejection_time: Duration::ZERO,
+                    inactivity_timeout: None,
+                    keys: vec![host_key],
+                    ..Default::default()
+                };
+                let mut server = MockServer { shared: serving };
+                let _ = server.run_on_socket(Arc::new(config), &listener).await;
+            });
+        });
+
+        MockSsh {
+            port: port_receiver.recv().expect("mock ssh port"),
+            host_key: public_key,
+            shared,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The server's entry for a `known_hosts` file.
+    pub fn known_hosts_line(&self) -> String {
+        format!(
+            "[127.0.0.1]:{} {}",
+            self.port,
+            self.host_key.to_openssh().expect("encode host key")
+        )
+    }
+
+    /// Writes a new private key to `path` and lets it log in as any user.
+    pub fn authorized_key(&self, path: &Path) {
+        let key = write_key(path);
+        self.shared().authorized.push(key);
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<SshRequest> {
+        self.shared().log.clone()
+    }
+
+    fn shared(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Writes a new ed25519 private key to `path` in the OpenSSH format and
+/// returns its public half.
+pub fn write_key(path: &Path) -> PublicKey {
+    let key = generate_key();
+    let encoded = key.to_openssh(LineEnding::LF).expect("encode private key");
+    fs::write(path, encoded.as_bytes()).expect("write private key");
+    key.public_key().clone()
+}
+
+fn generate_key() -> PrivateKey {
+    PrivateKey::random(&mut rand::rng(), Algorithm::Ed25519).expect("generate key")
+}
+
+struct MockServer {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl server::Server for MockServer {
+    type Handler = Connection;
+
+    fn new_client(&mut self, _: Option<SocketAddr>) -> Connection {
+        Connection {
+            shared: Arc::clone(&self.shared),
+            user: String::new(),
+            channels: HashMap::new(),
+        }
+    }
+}
+
+/// One client connection.
+struct Connection {
+    shared: Arc<Mutex<Shared>>,
+    user: String,
+    channels: HashMap<ChannelId, Channel<Msg>>,
+}
+
+impl Connection {
+    fn log(&self, request: SshRequest) -> PathBuf {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        shared.log.push(request);
+        shared.root.clone()
+    }
+}
+
+impl server::Handler for Connection {
+    type Error = russh::Error;
+
+    async fn auth_publickey(&mut self, user: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+        let shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        if !shared
+            .authorized
+            .iter()
+            .any(|authorized| authorized.key_data() == key.key_data())
+        {
+            return Ok(Auth::reject());
+        }
+
+        self.user = user.to_string();
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.channels.insert(channel.id(), channel);
+        reply.accept().await;
+        Ok(())
+    }
+
+    async fn channel_open_direct_tcpip(
+        &mut self,
+        channel: Channel<Msg>,
+        host: &str,
+        port: u32,
+        _originator_address: &str,
+        _originator_port: u32,
+        reply: ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        self.log(SshRequest::Forward {
+            user: self.user.clone(),
+            host: host.to_string(),
+            port,
+        });
+        let Ok(mut stream) = TcpStream::connect((host, port as u16)).await else {
+            reply.reject(russh::ChannelOpenFailure::ConnectFailed).await;
+            return Ok(());
+        };
+
+        reply.accept().await;
+        tokio::spawn(async move {
+            let mut channel = channel.into_stream();
+            let _ = tokio::io::copy_bidirectional(&mut channel, &mut stream).await;
+        });
+        Ok(())
+    }
+
+    async fn exec_request(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let Some(opened) = self.channels.remove(&channel) else {
+            return session.channel_failure(channel);
+        };
+        let command = String::from_utf8_lossy(data).to_string();
+        let root = self.log(SshRequest::Exec {
+            user: self.user.clone(),
+            command: command.clone(),
+        });
+
+        session.channel_success(channel)?;
+        tokio::spawn(exec(opened, command, root));
+        Ok(())
+    }
+
+    async fn subsystem_request(
+        &mut self,
+        channel: ChannelId,
+        name: &str,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let opened = match (name, self.channels.remove(&channel)) {
+            ("sftp", Some(opened)) => opened,
+            _ => return session.channel_failure(channel),
+        };
+
+        session.channel_success(channel)?;
+        let sftp = Sftp {
+            shared: Arc::clone(&self.shared),
+            user: self.user.clone(),
+            files: HashMap::new(),
+            next_handle: 0,
+        };
+        russh_sftp::server::run(opened.into_stream(), sftp).await;
+        Ok(())
+    }
+}
+
+/// Runs `command` through `sh` in `root`, streaming its input and output
+/// over the channel and reporting its exit status.
+async fn exec(channel: Channel<Msg>, command: String, root: PathBuf) {
+    let (mut read, write) = channel.split();
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .current_dir(&root)
+        .env("HOME", &root)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let Ok(mut child) = child else {
+        let _ = write.exit_status(127).await;
+        let _ = write.close().await;
+        return;
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        tokio::spawn(async move {
+            let mut input = read.make_reader();
+            let _ = tokio::io::copy(&mut input, &mut stdin).await;
+        });
+    }
+    let mut stdout = child.stdout.take().expect("piped stdout");
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let mut out = write.make_writer();
+    let mut err = write.make_writer_ext(Some(1));
+    let _ = tokio::join!(
+        async {
+            tokio::io::copy(&mut stdout, &mut out).await?;
+            out.flush().await
+        },
+        async {
+            tokio::io::copy(&mut stderr, &mut err).await?;
+            err.flush().await
+        },
+    );
+
+    let code = match child.wait().await {
+        Ok(status) => status.code().unwrap_or(255),
+        Err(_) => 255,
+    };
+    let _ = write.exit_status(code as u32).await;
+    let _ = write.eof().await;
+    let _ = write.close().await;
+}
+
+/// Just enough SFTP to create and write files.
+struct Sftp {
+    shared: Arc<Mutex<Shared>>,
+    user: String,
+    files: HashMap<String, File>,
+    next_handle: u32,
+}
+
+fn ok(id: u32) -> Status {
+    Status {
+        id,
+        status_code: StatusCode::Ok,
+        error_message: "Ok".into(),
+        language_tag: "en-US".into(),
+    }
+}
+
+impl russh_sftp::server::Handler for Sftp {
+    type Error = StatusCode;
+
+    fn unimplemented(&self) -> Self::Error {
+        StatusCode::OpUnsupported
+    }
+
+    async fn open(
+        &mut self,
+        id: u32,
+        filename: String,
+        flags: OpenFlags,
+        _attrs: FileAttributes,
+    ) -> Result<Handle, Self::Error> {
+        let root = {
+            let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+            shared.log.push(SshRequest::Upload {
+                user: self.user.clone(),
+                path: filename.clone(),
+            });
+            shared.root.clone()
+        };
+        let file = OpenOptions::new()
+            .read(flags.contains(OpenFlags::READ))
+            .write(flags.contains(OpenFlags::WRITE))
+            .create(flags.contains(OpenFlags::CREATE))
+            .truncate(flags.contains(OpenFlags::TRUNCATE))
+            .open(root.join(&filename))
+            .map_err(|_| StatusCode::NoSuchFile)?;
+
+        self.next_handle += 1;
+        let handle = self.next_handle.to_string();
+        self.files.insert(handle.clone(), file);
+        Ok(Handle { id, handle })
+    }
+
+    async fn write(
+        &mut self,
+        id: u32,
+        handle: String,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<Status, Self::Error> {
+        let file = self.files.get_mut(&handle).ok_or(StatusCode::Failure)?;
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.write_all(&data))
+            .map_err(|_| StatusCode::Failure)?;
+        Ok(ok(id))
+    }
+
+    async fn close(&mut self, id: u32, handle: String) -> Result<Status, Self::Error> {
+        self.files.remove(&handle);
+        Ok(ok(id))
+    }
+}
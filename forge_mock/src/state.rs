@@ -0,0 +1,435 @@
+// In-memory forge state and routing
+
+use serde_json::{json, Value};
+
+use crate::http::{Request, Response};
+
+pub struct Server {
+    pub id: u32,
+    pub name: String,
+    pub ip_address: String,
+    pub is_ready: bool,
+}
+
+pub struct Site {
+    pub id: u32,
+    pub server_id: u32,
+    pub name: String,
+    pub directory: String,
+    pub project_type: String,
+    pub php_version: String,
+    pub aliases: Vec<String>,
+    pub isolated: bool,
+    pub username: String,
+    /// Reads that still report the site as `installing`.
+    pub pending_polls: u32,
+}
+
+pub struct Database {
+    pub id: u32,
+    pub server_id: u32,
+    pub name: String,
+}
+
+pub struct User {
+    pub id: u32,
+    pub server_id: u32,
+    pub name: String,
+    pub databases: Vec<u32>,
+}
+
+pub struct State {
+    pub api_key: String,
+    pub home: String,
+    pub install_polls: u32,
+    pub servers: Vec<Server>,
+    pub sites: Vec<Site>,
+    pub databases: Vec<Database>,
+    pub users: Vec<User>,
+    next_id: u32,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self {
+            api_key: "test-key".into(),
+            home: "/home".into(),
+            install_polls: 0,
+            servers: vec![],
+            sites: vec![],
+            databases: vec![],
+            users: vec![],
+            next_id: 1,
+        }
+    }
+
+    pub fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn handle(&mut self, request: &Request) -> Response {
+        if request.authorization.as_deref() != Some(&format!("Bearer {}", self.api_key)) {
+            return Response::json(401, json!({ "message": "Unauthenticated." }));
+        }
+
+        let body: Value = serde_json::from_str(&request.body).unwrap_or(Value::Null);
+        let path = request.path.split('?').next().unwrap_or_default();
+        let Some(path) = path.strip_prefix("/api/v1/") else {
+            return Response::not_found();
+        };
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["servers"]) => {
+                let servers: Vec<Value> = self.servers.iter().map(server_json).collect();
+                Response::json(200, json!({ "servers": servers }))
+            }
+            (method, ["servers", server_id, rest @ ..]) => {
+                let Some(server_id) = self.server_id(server_id) else {
+                    return Response::not_found();
+                };
+                match (method, rest) {
+                    ("GET", []) => {
+                        let server = self.servers.iter().find(|s| s.id == server_id);
+                        Response::json(200, json!({ "server": server.map(server_json) }))
+                    }
+                    (method, ["sites", rest @ ..]) => self.sites(method, server_id, rest, &body),
+                    (method, ["databases", rest @ ..]) => {
+                        self.databases(method, server_id, rest, &body)
+                    }
+                    (method, ["database-users", rest @ ..]) => {
+                        self.users(method, server_id, rest, &body)
+                    }
+                    _ => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    fn server_id(&self, id: &str) -> Option<u32> {
+        let id = id.parse().ok()?;
+        self.servers.iter().any(|s| s.id == id).then_some(id)
+    }
+
+    fn sites(&mut self, method: &str, server_id: u32, rest: &[&str], body: &Value) -> Response {
+        match (method, rest) {
+            ("GET", []) => {
+                let sites: Vec<Value> = self
+                    .sites
+                    .iter()
+                    .filter(|site| site.server_id == server_id)
+                    .map(|site| self.site_json(site))
+                    .collect();
+                Response::json(200, json!({ "sites": sites }))
+            }
+            ("POST", []) => {
+                let domain = str_field(body, "domain");
+                if domain.is_empty() {
+                    return validation("domain", "The domain field is required.");
+                }
+                if self
+                    .sites
+                    .iter()
+                    .any(|site| site.server_id == server_id && site.name == domain)
+                {
+                    return validation("domain", "The domain has already been taken.");
+                }
+
+                let isolated = body["isolated"].as_bool().unwrap_or(false);
+                let username = match str_field(body, "username") {
+                    name if isolated && !name.is_empty() => name,
+                    _ => "forge".into(),
+                };
+                let directory = match str_field(body, "directory") {
+                    directory if directory.is_empty() => "/public".into(),
+                    directory => directory,
+                };
+
+                let site = Site {
+                    id: self.next_id(),
+                    server_id,
+                    name: domain,
+                    directory,
+                    project_type: str_field(body, "project_type"),
+                    php_version: str_field(body, "php_version"),
+                    aliases: body["aliases"]
+                        .as_array()
+                        .map(|aliases| {
+                            aliases
+                                .iter()
+                                .filter_map(|alias| alias.as_str().map(String::from))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    isolated,
+                    username,
+                    pending_polls: self.install_polls,
+                };
+                let response = json!({ "site": self.site_json(&site) });
+                self.sites.push(site);
+                Response::json(200, response)
+            }
+            (method, [site_id]) => {
+                let Some(index) = self.sites.iter().position(|site| {
+                    site.server_id == server_id && site.id.to_string() == *site_id
+                }) else {
+                    return Response::not_found();
+                };
+
+                match method {
+                    "GET" => {
+                        let response = json!({ "site": self.site_json(&self.sites[index]) });
+                        let site = &mut self.sites[index];
+                        site.pending_polls = site.pending_polls.saturating_sub(1);
+                        Response::json(200, response)
+                    }
+                    "DELETE" => {
+                        self.sites.remove(index);
+                        Response::empty(200)
+                    }
+                    _ => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    fn databases(&mut self, method: &str, server_id: u32, rest: &[&str], body: &Value) -> Response {
+        match (method, rest) {
+            ("GET", []) => {
+                let databases: Vec<Value> = self
+                    .databases
+                    .iter()
+                    .filter(|database| database.server_id == server_id)
+                    .map(database_json)
+                    .collect();
+                Response::json(200, json!({ "databases": databases }))
+            }
+            ("POST", []) => {
+                let name = str_field(body, "name");
+                if name.is_empty() {
+                    return validation("name", "The name field is required.");
+                }
+                if self
+                    .databases
+                    .iter()
+                    .any(|database| database.server_id == server_id && database.name == name)
+                {
+                    return validation("name", "The name has already been taken.");
+                }
+
+                let database = Database {
+                    id: self.next_id(),
+                    server_id,
+                    name,
+                };
+
+                // Like forge, a user and password create or extend a database user.
+                let user = str_field(body, "user");
+                if !user.is_empty() {
+                    match self
+                        .users
+                        .iter_mut()
+                        .find(|existing| existing.server_id == server_id && existing.name == user)
+                    {
+                        Some(existing) => existing.databases.push(database.id),
+                        None => {
+                            let id = self.next_id();
+                            self.users.push(User {
+                                id,
+                                server_id,
+                                name: user,
+                                databases: vec![database.id],
+                            });
+                        }
+                    }
+                }
+
+                let response = json!({ "database": database_json(&database) });
+                self.databases.push(database);
+                Response::json(200, response)
+            }
+            (method, [database_id]) => {
+                let Some(index) = self.databases.iter().position(|database| {
+                    database.server_id == server_id && database.id.to_string() == *database_id
+                }) else {
+                    return Response::not_found();
+                };
+
+                match method {
+                    "GET" => Response::json(
+                        200,
+                        json!({ "database": database_json(&self.databases[index]) }),
+                    ),
+                    "DELETE" => {
+                        let database = self.databases.remove(index);
+                        for user in &mut self.users {
+                            user.databases.retain(|id| *id != database.id);
+                        }
+                        Response::empty(200)
+                    }
+                    _ => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    fn users(&mut self, method: &str, server_id: u32, rest: &[&str], body: &Value) -> Response {
+        match (method, rest) {
+            ("GET", []) => {
+                let users: Vec<Value> = self
+                    .users
+                    .iter()
+                    .filter(|user| user.server_id == server_id)
+                    .map(user_json)
+                    .collect();
+                Response::json(200, json!({ "users": users }))
+            }
+            ("POST", []) => {
+                let name = str_field(body, "name");
+                if name.is_empty() {
+                    return validation("name", "The name field is required.");
+                }
+                if self
+                    .users
+                    .iter()
+                    .any(|user| user.server_id == server_id && user.name == name)
+                {
+                    return validation("name", "The name has already been taken.");
+                }
+
+                let user = User {
+                    id: self.next_id(),
+                    server_id,
+                    name,
+                    databases: database_ids(body),
+                };
+                let response = json!({ "user": user_json(&user) });
+                self.users.push(user);
+                Response::json(200, response)
+            }
+            (method, [user_id]) => {
+                let Some(index) = self.users.iter().position(|user| {
+                    user.server_id == server_id && user.id.to_string() == *user_id
+                }) else {
+                    return Response::not_found();
+                };
+
+                match method {
+                    "GET" => Response::json(200, json!({ "user": user_json(&self.users[index]) })),
+                    "PUT" => {
+                        self.users[index].databases = database_ids(body);
+                        Response::json(200, json!({ "user": user_json(&self.users[index]) }))
+                    }
+                    "DELETE" => {
+                        self.users.remove(index);
+                        Response::empty(200)
+                    }
+                    _ => Response::not_found(),
+                }
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    fn site_json(&self, site: &Site) -> Value {
+        let status = match site.pending_polls {
+            0 => "installed",
+            _ => "installing",
+        };
+
+        json!({
+            "id": site.id,
+            "server_id": site.server_id,
+            "name": site.name,
+            "aliases": site.aliases,
+            "directory": site.directory,
+            "wildcards": false,
+            "status": status,
+            "repository": null,
+            "repository_provider": null,
+            "repository_branch": null,
+            "repository_status": null,
+            "quick_deploy": false,
+            "project_type": site.project_type,
+            "php_version": site.php_version,
+            "app": null,
+            "app_status": null,
+            "slack_channel": null,
+            "telegram_chat_id": null,
+            "telegram_chat_title": null,
+            "teams_webhook_url": null,
+            "discord_webhook_url": null,
+            "created_at": "2024-01-01 00:00:00",
+            "telegram_secret": "",
+            "username": site.username,
+            "deployment_url": "",
+            "is_secured": false,
+            "web_directory": format!("{}/{}/{}{}", self.home, site.username, site.name, site.directory),
+            "isolated": site.isolated,
+            "tags": [],
+        })
+    }
+}
+
+fn server_json(server: &Server) -> Value {
+    json!({
+        "id": server.id,
+        "name": server.name,
+        "ip_address": server.ip_address,
+        "private_ip_address": null,
+        "php_version": "php83",
+        "database_type": "mysql8",
+        "provider": "custom",
+        "is_ready": server.is_ready,
+        "tags": [],
+    })
+}
+
+fn database_json(database: &Database) -> Value {
+    json!({
+        "id": database.id,
+        "name": database.name,
+        "status": "installed",
+        "created_at": "2024-01-01 00:00:00",
+    })
+}
+
+fn user_json(user: &User) -> Value {
+    json!({
+        "id": user.id,
+        "name": user.name,
+        "status": "installed",
+        "created_at": "2024-01-01 00:00:00",
+        "databases": user.databases,
+    })
+}
+
+fn str_field(body: &Value, field: &str) -> String {
+    body[field].as_str().unwrap_or_default().to_string()
+}
+
+fn database_ids(body: &Value) -> Vec<u32> {
+    body["databases"]
+        .as_array()
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|id| id.as_u64().map(|id| id as u32))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn validation(field: &str, message: &str) -> Response {
+    Response::json(
+        422,
+        json!({
+            "message": "The given data was invalid.",
+            "errors": { field: [message] },
+        }),
+    )
+}
@@ -1,7 +1,13 @@
 use std::sync::Arc;
 
 use clap::Parser;
-use forge_common::{args, config, error::AppResult, feedback, forge::ForgeClient};
+use forge_common::{
+    args,
+    config::{self, Destination},
+    error::{AppError, AppResult},
+    feedback,
+    forge::Deletion,
+};
 
 fn main() {
     if let Err(e) = run() {
@@ -14,43 +20,81 @@ fn run() -> AppResult<()> {
     let config = Arc::new(
         config::Config::load()?
             .from_args(args::Args::parse())
-            .finalize()?,
+            .finalize(Destination::Existing)?,
     );
 
-    let client = Arc::new(ForgeClient::new(&config.forge_api_key)?);
+    let client = Arc::new(config.forge_client()?);
+
+    let mut outcomes = vec![];
 
     let client_clone = client.clone();
     let config_clone = config.clone();
-
-    feedback::show_spinner(
+    let result = feedback::show_spinner(
         move || {
             client_clone
                 .delete_site_by_name(&config_clone.dest_server_id, &config_clone.dest_site_name)
         },
         "Deleting forge site",
-    )?;
+    );
+    outcomes.push((format!("site {}", config.dest_site_name), check(result)?));
 
     let client_clone = client.clone();
     let config_clone = config.clone();
-    feedback::show_spinner(
+    let result = feedback::show_spinner(
         move || {
             client_clone
                 .delete_database_by_name(&config_clone.dest_server_id, &config_clone.dest_db)
         },
         "Deleting forge database",
-    )?;
-
-    let client_clone = client.clone();
-    let config_clone = config.clone();
-
-    let user_name = config_clone.user_name.clone();
+    );
+    outcomes.push((format!("database {}", config.dest_db), check(result)?));
 
-    if let Some(user) = user_name {
-        feedback::show_spinner(
+    if let Some(user) = config.user_name.clone() {
+        let client_clone = client.clone();
+        let config_clone = config.clone();
+        let label = format!("database user {}", user);
+        let result = feedback::show_spinner(
             move || client_clone.delete_user_by_name(&config_clone.dest_server_id, &user),
             "Deleting database user",
-        )?;
+        );
+        outcomes.push((label, check(result)?));
     }
 
-    Ok(())
+    print_summary(&outcomes);
+
+    let failed: Vec<&str> = outcomes
+        .iter()
+        .filter(|(_, outcome)| outcome.is_err())
+        .map(|(label, _)| label.as_str())
+        .collect();
+
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(AppError::ForgeAPIError(format!(
+            "Unable to delete {}",
+            failed.join(", ")
+        ))),
+    }
+}
+
+/// Keeps going after a failed delete so the rest are still attempted, but
+/// stops when the user interrupts.
+fn check(result: AppResult<Deletion>) -> AppResult<Result<Deletion, String>> {
+    match result {
+        Err(AppError::Interrupted) => Err(AppError::Interrupted),
+        Err(e) => Ok(Err(e.to_string())),
+        Ok(deletion) => Ok(Ok(deletion)),
+    }
+}
+
+fn print_summary(outcomes: &[(String, Result<Deletion, String>)]) {
+    println!();
+    println!("Summary");
+    for (label, outcome) in outcomes {
+        match outcome {
+            Ok(Deletion::Removed) => println!("  ✔  {} - removed", label),
+            Ok(Deletion::AlreadyAbsent) => println!("  ↷  {} - already absent", label),
+            Err(e) => println!("  ✖  {} - failed: {}", label, e),
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+};
+
+use forge_mock::{Fault, MockForge};
+
+/// Scratch folder used as `HOME` so the run gets its own config file.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("forge_reset_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn forge_reset(forge: &MockForge, dir: &PathBuf, server_id: u32, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_forge_reset"))
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .args(["--forge-api-key", "test-key"])
+        .args(["--forge-base-url", forge.url()])
+        .args(["--forge-requests-per-minute", "0"])
+        .args(["--dest-server-id", &server_id.to_string()])
+        .args(["--dest-site-name", "example.com"])
+        .args(["--dest-db", "example"])
+        .args(["--user-name", "example"])
+        .args(["--isolated", "false"])
+        .args(["--source-folder", dir.join("example.com").to_str().unwrap()])
+        .args(["--temp-folder", dir.join("tmp").to_str().unwrap()])
+        .args(extra)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn removes_site_database_and_user() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    forge.add_site(server_id, "example.com");
+    forge.add_site(server_id, "other.com");
+    let database_id = forge.add_database(server_id, "example");
+    forge.add_user(server_id, "example", &[database_id]);
+    let dir = scratch_dir("removes");
+
+    let output = forge_reset(&forge, &dir, server_id, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(forge.site_names(server_id), vec!["other.com"]);
+    assert!(forge.database_names(server_id).is_empty());
+    assert!(forge.user_names(server_id).is_empty());
+    assert!(stdout.contains("site example.com - removed"));
+    assert!(stdout.contains("database example - removed"));
+    assert!(stdout.contains("database user example - removed"));
+}
+
+#[test]
+fn reports_resources_that_are_already_gone() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    forge.add_database(server_id, "example");
+    let dir = scratch_dir("absent");
+
+    let output = forge_reset(&forge, &dir, server_id, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(stdout.contains("site example.com - already absent"));
+    assert!(stdout.contains("database example - removed"));
+    assert!(stdout.contains("database user example - already absent"));
+}
+
+#[test]
+fn failed_delete_is_reported_and_the_rest_still_run() {
+    let forge = MockForge::start();
+    let server_id = forge.add_server("web", "127.0.0.1");
+    forge.add_site(server_id, "example.com");
+    let database_id = forge.add_database(server_id, "example");
+    forge.add_user(server_id, "example", &[database_id]);
+    forge.inject(
+        "DELETE",
+        &format!("/servers/{}/databases", server_id),
+        Fault::Status(500),
+        1,
+    );
+    let dir = scratch_dir("failed");
+
+    let output = forge_reset(&forge, &dir, server_id, &["--forge-max-retries", "0"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(!output.status.success());
+    assert!(stdout.contains("database example - failed"));
+    assert!(forge.site_names(server_id).is_empty());
+    assert_eq!(forge.database_names(server_id), vec!["example"]);
+    assert!(forge.user_names(server_id).is_empty());
+}